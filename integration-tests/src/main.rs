@@ -40,6 +40,7 @@ async fn main() -> anyhow::Result<()> {
         cli.create(CreateReq {
             key: format!("key-{}", i),
             data: vec![0; 512].into(),
+            ..Default::default()
         })
         .await?;
     }
@@ -0,0 +1,250 @@
+use crate::proto::*;
+use crate::Client;
+use bytes::{Bytes, BytesMut};
+use futures::future::BoxFuture;
+use futures::ready;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tonic::Status;
+
+fn io_error(e: Status) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+/// Reads a version of an object by ranges.
+/// Each range is retried on another node so a node failure doesn't break the read.
+pub struct ObjectReader {
+    cli: Client,
+    key: String,
+    version_id: String,
+    size: u64,
+    pos: u64,
+    chunk_size: u64,
+    buf: Bytes,
+    fut: Option<BoxFuture<'static, Result<Bytes, Status>>>,
+}
+impl ObjectReader {
+    pub(crate) fn new(
+        cli: Client,
+        key: String,
+        version_id: String,
+        size: u64,
+        chunk_size: u64,
+    ) -> Self {
+        Self {
+            cli,
+            key,
+            version_id,
+            size,
+            pos: 0,
+            chunk_size: std::cmp::max(chunk_size, 1),
+            buf: Bytes::new(),
+            fut: None,
+        }
+    }
+    pub fn version_id(&self) -> &str {
+        &self.version_id
+    }
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+impl AsyncRead for ObjectReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        out: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            if !self.buf.is_empty() {
+                let n = std::cmp::min(out.remaining(), self.buf.len());
+                let data = self.buf.split_to(n);
+                out.put_slice(&data);
+                return Poll::Ready(Ok(()));
+            }
+            if self.pos >= self.size {
+                return Poll::Ready(Ok(()));
+            }
+            if self.fut.is_none() {
+                let req = ReadRangeReq {
+                    key: self.key.clone(),
+                    offset: self.pos,
+                    length: std::cmp::min(self.chunk_size, self.size - self.pos),
+                    version_id: self.version_id.clone(),
+                };
+                let cli = self.cli.clone();
+                self.fut = Some(Box::pin(async move {
+                    cli.read_range(req).await.map(|rep| rep.data)
+                }));
+            }
+            let res = ready!(self.fut.as_mut().unwrap().as_mut().poll(cx));
+            self.fut = None;
+            let data = res.map_err(io_error)?;
+            if data.is_empty() {
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+            self.pos += data.len() as u64;
+            self.buf = data;
+        }
+    }
+}
+
+enum Step {
+    Initiated(String),
+    Uploaded(u32),
+    Created(String),
+}
+
+/// Writes an object by parts.
+/// An object smaller than the part size is created at once and a larger one is uploaded by multipart.
+/// The parts are retried on another node so a node failure doesn't break the write.
+///
+/// The object is created on shutdown. A writer dropped before that leaves the upload
+/// which is cleaned up by the cluster later.
+pub struct ObjectWriter {
+    cli: Client,
+    key: String,
+    metadata: HashMap<String, String>,
+    part_size: usize,
+    buf: BytesMut,
+    upload_id: Option<String>,
+    part_numbers: Vec<u32>,
+    fut: Option<BoxFuture<'static, Result<Step, Status>>>,
+    version_id: Option<String>,
+}
+impl ObjectWriter {
+    pub(crate) fn new(
+        cli: Client,
+        key: String,
+        metadata: HashMap<String, String>,
+        part_size: usize,
+    ) -> Self {
+        Self {
+            cli,
+            key,
+            metadata,
+            part_size: std::cmp::max(part_size, 1),
+            buf: BytesMut::new(),
+            upload_id: None,
+            part_numbers: vec![],
+            fut: None,
+            version_id: None,
+        }
+    }
+    /// The version created. None until shutdown completes.
+    pub fn version_id(&self) -> Option<&str> {
+        self.version_id.as_deref()
+    }
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let fut = match self.fut.as_mut() {
+            Some(fut) => fut,
+            None => return Poll::Ready(Ok(())),
+        };
+        let res = ready!(fut.as_mut().poll(cx));
+        self.fut = None;
+        match res.map_err(io_error)? {
+            Step::Initiated(upload_id) => self.upload_id = Some(upload_id),
+            Step::Uploaded(part_number) => self.part_numbers.push(part_number),
+            Step::Created(version_id) => self.version_id = Some(version_id),
+        }
+        Poll::Ready(Ok(()))
+    }
+    /// Upload the buffer as the next part. The upload is initiated first if not yet.
+    fn start_upload(&mut self) {
+        let cli = self.cli.clone();
+        let key = self.key.clone();
+        match &self.upload_id {
+            None => {
+                let req = InitiateMultipartReq {
+                    key,
+                    metadata: self.metadata.clone(),
+                };
+                self.fut = Some(Box::pin(async move {
+                    let rep = cli.initiate_multipart(req).await?;
+                    Ok(Step::Initiated(rep.upload_id))
+                }));
+            }
+            Some(upload_id) => {
+                let part_number = self.part_numbers.len() as u32 + 1;
+                let req = UploadPartReq {
+                    key,
+                    upload_id: upload_id.clone(),
+                    part_number,
+                    data: self.buf.split().freeze(),
+                };
+                self.fut = Some(Box::pin(async move {
+                    cli.upload_part(req).await?;
+                    Ok(Step::Uploaded(part_number))
+                }));
+            }
+        }
+    }
+}
+impl AsyncWrite for ObjectWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.version_id.is_some() {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::Other,
+                "the object is already created",
+            )));
+        }
+        loop {
+            ready!(self.poll_pending(cx))?;
+            if self.buf.len() < self.part_size {
+                let n = std::cmp::min(data.len(), self.part_size - self.buf.len());
+                self.buf.extend_from_slice(&data[..n]);
+                return Poll::Ready(Ok(n));
+            }
+            self.start_upload();
+        }
+    }
+    /// The buffer is kept until it fills a part.
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_pending(cx)
+    }
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            ready!(this.poll_pending(cx))?;
+            if this.version_id.is_some() {
+                return Poll::Ready(Ok(()));
+            }
+            match this.upload_id.clone() {
+                None => {
+                    let cli = this.cli.clone();
+                    let req = CreateReq {
+                        key: this.key.clone(),
+                        data: this.buf.split().freeze(),
+                        metadata: this.metadata.clone(),
+                        ..Default::default()
+                    };
+                    this.fut = Some(Box::pin(async move {
+                        let rep = cli.create(req).await?;
+                        Ok(Step::Created(rep.version_id))
+                    }));
+                }
+                Some(_) if !this.buf.is_empty() => this.start_upload(),
+                Some(upload_id) => {
+                    let cli = this.cli.clone();
+                    let req = CompleteMultipartReq {
+                        key: this.key.clone(),
+                        upload_id,
+                        part_numbers: this.part_numbers.clone(),
+                    };
+                    this.fut = Some(Box::pin(async move {
+                        let rep = cli.complete_multipart(req).await?;
+                        Ok(Step::Created(rep.version_id))
+                    }));
+                }
+            }
+        }
+    }
+}
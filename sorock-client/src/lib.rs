@@ -0,0 +1,326 @@
+//! Client of a Sorock cluster.
+//!
+//! The requests are balanced across the nodes of the cluster.
+//! A node that returns Unavailable is skipped for a while and
+//! the idempotent calls are retried on the other nodes with backoff.
+
+use futures::Future;
+use lol_core::api::ClusterInfoReq;
+use lol_core::{RaftClient, Uri};
+use proto::sorock_client::SorockClient;
+use proto::*;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tonic::transport::Channel;
+use tonic::Status;
+
+mod io;
+mod nodes;
+mod retry;
+
+pub use io::{ObjectReader, ObjectWriter};
+pub use retry::Backoff;
+pub use sorock_core::proto_compiled as proto;
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Discover the members of the cluster from the seeds.
+    /// Disable this if the nodes are only reachable by the seed addresses, e.g. behind a load balancer.
+    pub discovery: bool,
+    /// How often the members are discovered again.
+    pub discovery_interval: Duration,
+    /// How long a node that returned Unavailable is skipped.
+    pub eject_duration: Duration,
+    pub backoff: Backoff,
+    /// Size of the ranges ObjectReader reads at once.
+    pub read_chunk_size: u64,
+    /// Size of the parts ObjectWriter uploads.
+    pub part_size: usize,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            discovery: true,
+            discovery_interval: Duration::from_secs(10),
+            eject_duration: Duration::from_secs(5),
+            backoff: Backoff::default(),
+            read_chunk_size: 4 << 20,
+            part_size: 8 << 20,
+        }
+    }
+}
+
+struct Inner {
+    seeds: Vec<Uri>,
+    nodes: nodes::Nodes,
+    config: Config,
+}
+impl Inner {
+    /// Ask the known nodes for the membership of the cluster.
+    /// The nodes are kept if none of them answers.
+    async fn discover(&self) -> bool {
+        let mut candidates = self.nodes.uris();
+        candidates.extend(self.seeds.iter().cloned());
+        for uri in candidates {
+            let chan = tonic::transport::Endpoint::from(uri).connect_lazy();
+            let mut cli = RaftClient::new(chan);
+            let rep = match cli.request_cluster_info(ClusterInfoReq {}).await {
+                Ok(rep) => rep.into_inner(),
+                Err(_) => continue,
+            };
+            let members: Vec<Uri> = rep
+                .membership
+                .iter()
+                .filter_map(|x| x.parse().ok())
+                .collect();
+            if !members.is_empty() {
+                self.nodes.set(members);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<Inner>,
+}
+impl Client {
+    /// `seeds` are some of the nodes in the cluster.
+    pub async fn connect(seeds: Vec<Uri>, config: Config) -> Result<Self, Status> {
+        if seeds.is_empty() {
+            return Err(Status::invalid_argument("no seed is given"));
+        }
+        let inner = Arc::new(Inner {
+            nodes: nodes::Nodes::new(seeds.clone()),
+            seeds,
+            config,
+        });
+        if inner.config.discovery {
+            if !inner.discover().await {
+                return Err(Status::unavailable("no seed answered the membership"));
+            }
+            tokio::spawn(discovery_loop(Arc::downgrade(&inner)));
+        }
+        Ok(Self { inner })
+    }
+    /// The nodes the requests are currently sent to.
+    pub fn nodes(&self) -> Vec<Uri> {
+        self.inner.nodes.uris()
+    }
+    /// A raw client of one of the nodes for the calls this client doesn't wrap.
+    /// Unavailable if no node is known.
+    pub fn node(&self) -> Result<SorockClient<Channel>, Status> {
+        match self.inner.nodes.pick() {
+            Some(node) => Ok(SorockClient::new(node.chan)),
+            None => Err(Status::unavailable("no node")),
+        }
+    }
+    /// Send the request to one of the nodes.
+    /// A node that returns Unavailable is ejected and
+    /// an idempotent call is retried on another node.
+    async fn call<T, F, Fut>(&self, idempotent: bool, mut f: F) -> Result<T, Status>
+    where
+        F: FnMut(SorockClient<Channel>) -> Fut,
+        Fut: Future<Output = Result<T, Status>>,
+    {
+        let config = &self.inner.config;
+        let mut attempt = 0;
+        loop {
+            let node = match self.inner.nodes.pick() {
+                Some(node) => node,
+                None => return Err(Status::unavailable("no node")),
+            };
+            match f(SorockClient::new(node.chan)).await {
+                Ok(rep) => {
+                    self.inner.nodes.restore(&node.uri);
+                    return Ok(rep);
+                }
+                Err(e) if retry::is_retriable(&e) => {
+                    self.inner.nodes.eject(&node.uri, config.eject_duration);
+                    if !idempotent || attempt >= config.backoff.max_retries {
+                        return Err(e);
+                    }
+                    tokio::time::sleep(config.backoff.delay(attempt)).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    pub async fn ping(&self) -> Result<(), Status> {
+        self.call(true, |mut cli| async move { cli.ping(()).await })
+            .await?;
+        Ok(())
+    }
+    /// Create a new version of the object.
+    /// Not retried because a retry after a create that succeeded writes another version.
+    pub async fn create(&self, req: CreateReq) -> Result<CreateRep, Status> {
+        self.call(false, |mut cli| {
+            let req = req.clone();
+            async move { cli.create(req).await }
+        })
+        .await
+        .map(|rep| rep.into_inner())
+    }
+    /// Retried because the key is the digest of the content.
+    pub async fn put(&self, req: PutReq) -> Result<PutRep, Status> {
+        self.call(true, |mut cli| {
+            let req = req.clone();
+            async move { cli.put(req).await }
+        })
+        .await
+        .map(|rep| rep.into_inner())
+    }
+    pub async fn read(&self, req: ReadReq) -> Result<ReadRep, Status> {
+        self.call(true, |mut cli| {
+            let req = req.clone();
+            async move { cli.read(req).await }
+        })
+        .await
+        .map(|rep| rep.into_inner())
+    }
+    pub async fn read_range(&self, req: ReadRangeReq) -> Result<ReadRep, Status> {
+        self.call(true, |mut cli| {
+            let req = req.clone();
+            async move { cli.read_range(req).await }
+        })
+        .await
+        .map(|rep| rep.into_inner())
+    }
+    pub async fn batch_read(&self, req: BatchReadReq) -> Result<BatchReadRep, Status> {
+        self.call(true, |mut cli| {
+            let req = req.clone();
+            async move { cli.batch_read(req).await }
+        })
+        .await
+        .map(|rep| rep.into_inner())
+    }
+    pub async fn head(&self, req: HeadReq) -> Result<HeadRep, Status> {
+        self.call(true, |mut cli| {
+            let req = req.clone();
+            async move { cli.head(req).await }
+        })
+        .await
+        .map(|rep| rep.into_inner())
+    }
+    /// The keys of the page are merged into one reply.
+    pub async fn list(&self, req: ListReq) -> Result<ListRep, Status> {
+        self.call(true, |mut cli| {
+            let req = req.clone();
+            async move {
+                let mut stream = cli.list(req).await?.into_inner();
+                let mut out = ListRep::default();
+                while let Some(rep) = stream.message().await? {
+                    out.keys.extend(rep.keys);
+                    out.next_continuation_token = rep.next_continuation_token;
+                }
+                Ok::<_, Status>(out)
+            }
+        })
+        .await
+    }
+    pub async fn list_versions(&self, req: ListVersionsReq) -> Result<ListVersionsRep, Status> {
+        self.call(true, |mut cli| {
+            let req = req.clone();
+            async move { cli.list_versions(req).await }
+        })
+        .await
+        .map(|rep| rep.into_inner())
+    }
+    pub async fn delete(&self, req: DeleteReq) -> Result<(), Status> {
+        self.call(true, |mut cli| {
+            let req = req.clone();
+            async move { cli.delete(req).await }
+        })
+        .await?;
+        Ok(())
+    }
+    /// Not retried because a retry initiates another upload.
+    pub async fn initiate_multipart(
+        &self,
+        req: InitiateMultipartReq,
+    ) -> Result<InitiateMultipartRep, Status> {
+        self.call(false, |mut cli| {
+            let req = req.clone();
+            async move { cli.initiate_multipart(req).await }
+        })
+        .await
+        .map(|rep| rep.into_inner())
+    }
+    /// Retried because uploading a part again overwrites it.
+    pub async fn upload_part(&self, req: UploadPartReq) -> Result<UploadPartRep, Status> {
+        self.call(true, |mut cli| {
+            let req = req.clone();
+            async move { cli.upload_part(req).await }
+        })
+        .await
+        .map(|rep| rep.into_inner())
+    }
+    /// Not retried because the upload is gone after it succeeds.
+    pub async fn complete_multipart(&self, req: CompleteMultipartReq) -> Result<CreateRep, Status> {
+        self.call(false, |mut cli| {
+            let req = req.clone();
+            async move { cli.complete_multipart(req).await }
+        })
+        .await
+        .map(|rep| rep.into_inner())
+    }
+    pub async fn abort_multipart(&self, req: AbortMultipartReq) -> Result<(), Status> {
+        self.call(true, |mut cli| {
+            let req = req.clone();
+            async move { cli.abort_multipart(req).await }
+        })
+        .await?;
+        Ok(())
+    }
+    /// Read the object through AsyncRead.
+    /// The version is fixed when the reader is opened. Empty `version_id` opens the latest version.
+    pub async fn reader(&self, key: &str, version_id: &str) -> Result<ObjectReader, Status> {
+        let head = self
+            .head(HeadReq {
+                key: key.to_string(),
+                version_id: version_id.to_string(),
+            })
+            .await?;
+        Ok(ObjectReader::new(
+            self.clone(),
+            key.to_string(),
+            head.version_id,
+            head.size,
+            self.inner.config.read_chunk_size,
+        ))
+    }
+    /// Write an object through AsyncWrite.
+    /// The object is created on shutdown.
+    pub fn writer(
+        &self,
+        key: &str,
+        metadata: std::collections::HashMap<String, String>,
+    ) -> ObjectWriter {
+        ObjectWriter::new(
+            self.clone(),
+            key.to_string(),
+            metadata,
+            self.inner.config.part_size,
+        )
+    }
+}
+
+async fn discovery_loop(inner: Weak<Inner>) {
+    loop {
+        let interval = match inner.upgrade() {
+            Some(inner) => inner.config.discovery_interval,
+            None => break,
+        };
+        tokio::time::sleep(interval).await;
+        // Stop when the client is dropped.
+        match inner.upgrade() {
+            Some(inner) => {
+                inner.discover().await;
+            }
+            None => break,
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use lol_core::Uri;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tonic::transport::{Channel, Endpoint};
+
+#[derive(Clone)]
+pub struct Node {
+    pub uri: Uri,
+    pub chan: Channel,
+}
+
+struct Entry {
+    node: Node,
+    /// The node isn't picked until then unless all the nodes are ejected.
+    ejected_until: Option<Instant>,
+}
+
+/// The nodes the requests are balanced across in round-robin.
+pub struct Nodes {
+    entries: Mutex<Vec<Entry>>,
+    next: AtomicUsize,
+}
+impl Nodes {
+    pub fn new(uris: Vec<Uri>) -> Self {
+        let nodes = Self {
+            entries: Mutex::new(vec![]),
+            next: AtomicUsize::new(0),
+        };
+        nodes.set(uris);
+        nodes
+    }
+    pub fn uris(&self) -> Vec<Uri> {
+        let entries = self.entries.lock().unwrap();
+        entries.iter().map(|e| e.node.uri.clone()).collect()
+    }
+    /// Replace the nodes. The connections and the states of the nodes that remain are kept.
+    pub fn set(&self, mut uris: Vec<Uri>) {
+        uris.sort_by_key(|uri| uri.to_string());
+        uris.dedup();
+        let mut entries = self.entries.lock().unwrap();
+        let mut new_entries = vec![];
+        for uri in uris {
+            let entry = match entries.iter().position(|e| e.node.uri == uri) {
+                Some(i) => entries.swap_remove(i),
+                None => {
+                    let chan = Endpoint::from(uri.clone()).connect_lazy();
+                    Entry {
+                        node: Node { uri, chan },
+                        ejected_until: None,
+                    }
+                }
+            };
+            new_entries.push(entry);
+        }
+        *entries = new_entries;
+    }
+    pub fn pick(&self) -> Option<Node> {
+        let entries = self.entries.lock().unwrap();
+        let n = entries.len();
+        if n == 0 {
+            return None;
+        }
+        let now = Instant::now();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        for i in 0..n {
+            let entry = &entries[(start + i) % n];
+            match entry.ejected_until {
+                Some(t) if t > now => {}
+                _ => return Some(entry.node.clone()),
+            }
+        }
+        // All the nodes are ejected. The one back the earliest is tried.
+        entries
+            .iter()
+            .min_by_key(|e| e.ejected_until)
+            .map(|e| e.node.clone())
+    }
+    /// Skip the node for a while because it failed.
+    pub fn eject(&self, uri: &Uri, duration: Duration) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.iter_mut().find(|e| &e.node.uri == uri) {
+            entry.ejected_until = Some(Instant::now() + duration);
+        }
+    }
+    /// The node served a request.
+    pub fn restore(&self, uri: &Uri) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.iter_mut().find(|e| &e.node.uri == uri) {
+            entry.ejected_until = None;
+        }
+    }
+}
+
+#[cfg(test)]
+fn uri(port: u16) -> Uri {
+    format!("http://localhost:{}", port).parse().unwrap()
+}
+
+#[tokio::test]
+async fn test_round_robin() {
+    let nodes = Nodes::new(vec![uri(1), uri(2), uri(3), uri(2)]);
+    assert_eq!(nodes.uris().len(), 3);
+    let mut picked = vec![];
+    for _ in 0..6 {
+        picked.push(nodes.pick().unwrap().uri);
+    }
+    for u in [uri(1), uri(2), uri(3)] {
+        assert_eq!(picked.iter().filter(|x| **x == u).count(), 2);
+    }
+
+    assert!(Nodes::new(vec![]).pick().is_none());
+}
+
+#[tokio::test]
+async fn test_eject() {
+    let nodes = Nodes::new(vec![uri(1), uri(2)]);
+    nodes.eject(&uri(1), Duration::from_secs(60));
+    for _ in 0..4 {
+        assert_eq!(nodes.pick().unwrap().uri, uri(2));
+    }
+    // All ejected
+    nodes.eject(&uri(2), Duration::from_secs(120));
+    assert_eq!(nodes.pick().unwrap().uri, uri(1));
+
+    nodes.restore(&uri(2));
+    assert_eq!(nodes.pick().unwrap().uri, uri(2));
+
+    // The state is kept over the update.
+    nodes.set(vec![uri(1), uri(2), uri(3)]);
+    for _ in 0..4 {
+        assert_ne!(nodes.pick().unwrap().uri, uri(1));
+    }
+}
@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+/// Exponential backoff between the retries.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    /// 0 means the calls are never retried.
+    pub max_retries: usize,
+}
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(50),
+            max: Duration::from_secs(2),
+            max_retries: 5,
+        }
+    }
+}
+impl Backoff {
+    /// The delay before the retry after the `attempt`-th failure (0-origin).
+    /// The upper half is randomized so the clients don't retry in lockstep.
+    pub fn delay(&self, attempt: usize) -> Duration {
+        let exp = std::cmp::min(attempt, 16) as u32;
+        let d = std::cmp::min(self.initial.saturating_mul(1 << exp), self.max);
+        let half = d / 2;
+        half + half.mul_f64(rand::random::<f64>())
+    }
+}
+
+/// The node couldn't serve the request. Another node or a later retry may.
+pub fn is_retriable(status: &tonic::Status) -> bool {
+    status.code() == tonic::Code::Unavailable
+}
+
+#[test]
+fn test_backoff() {
+    let backoff = Backoff {
+        initial: Duration::from_millis(100),
+        max: Duration::from_secs(1),
+        max_retries: 10,
+    };
+    for _ in 0..100 {
+        let d = backoff.delay(0);
+        assert!((Duration::from_millis(50)..=Duration::from_millis(100)).contains(&d));
+        let d = backoff.delay(2);
+        assert!((Duration::from_millis(200)..=Duration::from_millis(400)).contains(&d));
+        let d = backoff.delay(100);
+        assert!((Duration::from_millis(500)..=Duration::from_secs(1)).contains(&d));
+    }
+}
+
+#[test]
+fn test_is_retriable() {
+    assert!(is_retriable(&tonic::Status::unavailable("")));
+    assert!(!is_retriable(&tonic::Status::not_found("")));
+    assert!(!is_retriable(&tonic::Status::internal("")));
+}
@@ -0,0 +1,121 @@
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use prost::Message;
+use sorock_core::piece_store::mem as mem_piece_store;
+use sorock_core::proto_compiled::{RequestPieceRep, SendPieceReq};
+use sorock_core::*;
+
+const KEY: &str = "bench";
+
+fn loc() -> PieceLocator {
+    PieceLocator {
+        key: KEY.to_string(),
+        index: 0,
+    }
+}
+
+/// A piece moved by the stabilizer:
+/// read from the local store, sent to the new holder, saved there and deleted here.
+/// The message goes through the wire format as gRPC does.
+async fn stabilize(mut from: piece_store::ClientT, mut to: piece_store::ClientT) {
+    let piece = from.get_piece(loc()).await.unwrap().unwrap();
+    let req = SendPieceReq {
+        key: KEY.to_string(),
+        index: 0,
+        data: Some(piece.data),
+        checksum: piece.checksum,
+        version: 0,
+    };
+    let buf = Bytes::from(req.encode_to_vec());
+
+    let req = SendPieceReq::decode(buf).unwrap();
+    let piece = Piece {
+        data: req.data.unwrap(),
+        checksum: req.checksum,
+    };
+    assert!(piece.verify());
+    to.put_piece(loc(), piece).await.unwrap();
+    from.delete_piece(loc()).await.unwrap();
+}
+
+/// The same as `stabilize` but the data is copied at every hop
+/// as it was when the pieces were passed around in `Vec<u8>`.
+async fn stabilize_with_copies(mut from: piece_store::ClientT, mut to: piece_store::ClientT) {
+    let piece = from.get_piece(loc()).await.unwrap().unwrap();
+    let data: Vec<u8> = piece.data.to_vec();
+    let req = SendPieceReq {
+        key: KEY.to_string(),
+        index: 0,
+        data: Some(Bytes::copy_from_slice(&data)),
+        checksum: piece.checksum,
+        version: 0,
+    };
+    let buf = req.encode_to_vec();
+
+    let req = SendPieceReq::decode(&buf[..]).unwrap();
+    let data: Vec<u8> = req.data.unwrap().to_vec();
+    let piece = Piece {
+        data: data.into(),
+        checksum: req.checksum,
+    };
+    assert!(piece.verify());
+    to.put_piece(loc(), piece).await.unwrap();
+    from.delete_piece(loc()).await.unwrap();
+}
+
+/// A piece requested by a reader.
+fn request_piece(piece: &Piece) -> Piece {
+    let rep = RequestPieceRep {
+        data: Some(piece.data.clone()),
+        checksum: piece.checksum,
+    };
+    let buf = Bytes::from(rep.encode_to_vec());
+    let rep = RequestPieceRep::decode(buf).unwrap();
+    Piece {
+        data: rep.data.unwrap(),
+        checksum: rep.checksum,
+    }
+}
+
+fn bench_piece_path(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("stabilize");
+    for size in [64 << 10, 1 << 20, 8 << 20] {
+        group.throughput(Throughput::Bytes(size as u64));
+        let piece = Piece::new(Bytes::from(vec![1; size]));
+        let (a, b) = rt.block_on(async {
+            let a = mem_piece_store::spawn(mem_piece_store::State::new());
+            let b = mem_piece_store::spawn(mem_piece_store::State::new());
+            a.clone().put_piece(loc(), piece).await.unwrap();
+            (a, b)
+        });
+        // The piece goes back and forth between the stores.
+        group.bench_with_input(BenchmarkId::new("bytes", size), &size, |bench, _| {
+            bench.to_async(&rt).iter(|| async {
+                stabilize(a.clone(), b.clone()).await;
+                stabilize(b.clone(), a.clone()).await;
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("copy", size), &size, |bench, _| {
+            bench.to_async(&rt).iter(|| async {
+                stabilize_with_copies(a.clone(), b.clone()).await;
+                stabilize_with_copies(b.clone(), a.clone()).await;
+            })
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("request_piece");
+    for size in [64 << 10, 1 << 20, 8 << 20] {
+        group.throughput(Throughput::Bytes(size as u64));
+        let piece = Piece::new(Bytes::from(vec![1; size]));
+        group.bench_with_input(BenchmarkId::new("bytes", size), &piece, |bench, piece| {
+            bench.iter(|| request_piece(piece))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_piece_path);
+criterion_main!(benches);
@@ -2,8 +2,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut config = prost_build::Config::new();
     config.bytes(&[
         ".sorock.CreateReq.data",
+        ".sorock.CreateStreamReq.data",
+        ".sorock.PutReq.data",
+        ".sorock.UploadPartReq.data",
         ".sorock.ReadRep.data",
+        ".sorock.BatchReadEntry.data",
         ".sorock.SendPieceReq.data",
+        ".sorock.RequestPieceRep.data",
+        ".sorock.IndexedPiece.data",
     ]);
     tonic_build::configure().compile_with_config(config, &["proto/sorock.proto"], &["proto"])?;
     Ok(())
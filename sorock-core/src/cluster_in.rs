@@ -11,6 +11,8 @@ pub fn spawn(
     stabilizer_cli: stabilizer::ClientT,
     peer_in_cli: peer_in::ClientT,
     rebuild_queue_cli: rebuild_queue::ClientT,
+    rollback_queue_cli: rollback_queue::ClientT,
+    tombstone_gc_cli: tombstone_gc::ClientT,
     fd_app_in_cli: failure_detector::app_in::ClientT,
 ) -> ClientT {
     use norpc::runtime::tokio::*;
@@ -19,6 +21,8 @@ pub fn spawn(
         stabilizer_cli,
         peer_in_cli,
         rebuild_queue_cli,
+        rollback_queue_cli,
+        tombstone_gc_cli,
         fd_app_in_cli,
     };
     let svc = ClusterInService::new(svc);
@@ -31,7 +35,9 @@ struct App {
     io_front_cli: io_front::ClientT,
     stabilizer_cli: stabilizer::ClientT,
     rebuild_queue_cli: rebuild_queue::ClientT,
+    rollback_queue_cli: rollback_queue::ClientT,
     peer_in_cli: peer_in::ClientT,
+    tombstone_gc_cli: tombstone_gc::ClientT,
     fd_app_in_cli: failure_detector::app_in::ClientT,
 }
 
@@ -54,6 +60,14 @@ impl ClusterIn for App {
             .clone()
             .set_new_cluster(cluster.clone())
             .await;
+        self.rollback_queue_cli
+            .clone()
+            .set_new_cluster(cluster.clone())
+            .await;
+        self.tombstone_gc_cli
+            .clone()
+            .set_new_cluster(cluster.clone())
+            .await;
         self.stabilizer_cli.clone().set_new_cluster(cluster).await?;
         self.stabilizer_cli.clone().flush_queue().await;
 
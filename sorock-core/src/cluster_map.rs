@@ -1,5 +1,5 @@
 use crate::*;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
 
 #[derive(Clone, Debug)]
@@ -14,6 +14,9 @@ struct Inner {
     last_change: Change,
     cluster: asura::Cluster,
     idmap: HashMap<u64, URI>,
+    ec: EcParams,
+    durability: Durability,
+    storage_classes: BTreeMap<String, EcParams>,
 }
 
 #[derive(Clone)]
@@ -28,6 +31,9 @@ impl ClusterMap {
                 last_change: Change::Set,
                 cluster: asura::Cluster::new(),
                 idmap: HashMap::new(),
+                ec: EcParams::default(),
+                durability: Durability::default(),
+                storage_classes: BTreeMap::new(),
             }),
         }
     }
@@ -36,12 +42,18 @@ impl ClusterMap {
         last_change: Change,
         cluster: asura::Cluster,
         idmap: HashMap<u64, URI>,
+        ec: EcParams,
+        durability: Durability,
+        storage_classes: BTreeMap<String, EcParams>,
     ) -> Self {
         let inner = Inner {
             version,
             last_change,
             cluster,
             idmap,
+            ec,
+            durability,
+            storage_classes,
         };
         Self {
             inner: Arc::new(inner),
@@ -53,6 +65,34 @@ impl ClusterMap {
     pub fn version(&self) -> u64 {
         self.inner.version
     }
+    /// Parameters new objects are written with.
+    pub fn ec(&self) -> EcParams {
+        self.inner.ec
+    }
+    /// Durability of the creates which don't specify one.
+    pub fn durability(&self) -> Durability {
+        self.inner.durability
+    }
+    /// Parameters of the storage class. The empty name is the default of the cluster.
+    pub fn storage_class(&self, name: &str) -> Option<EcParams> {
+        if name.is_empty() {
+            return Some(self.inner.ec);
+        }
+        self.inner.storage_classes.get(name).copied()
+    }
+    pub fn storage_classes(&self) -> BTreeMap<String, EcParams> {
+        self.inner.storage_classes.clone()
+    }
+    /// The largest n of the default and the storage classes.
+    /// Because the holders of a smaller n are a prefix of the holders of a larger n,
+    /// the holders under this n hold the pieces of any object.
+    pub fn max_n(&self) -> usize {
+        self.inner
+            .storage_classes
+            .values()
+            .map(|ec| ec.n)
+            .fold(self.inner.ec.n, std::cmp::max)
+    }
     pub fn members(&self) -> HashSet<Uri> {
         let mut out = HashSet::new();
         for (_, uri) in &self.inner.idmap {
@@ -60,12 +100,15 @@ impl ClusterMap {
         }
         out
     }
+    /// The holders of the pieces from the first index.
+    /// The candidates are drawn in a fixed order for the key
+    /// so the holders of a smaller n are a prefix of the holders of a larger n.
     pub fn compute_holders(&self, key: String, n: usize) -> Vec<Option<Uri>> {
         use std::collections::hash_map::DefaultHasher;
         use std::hash::{Hash, Hasher};
 
         let mut s = DefaultHasher::new();
-        key.hash(&mut s);
+        versioning::placement_key(&key).hash(&mut s);
         let data_key = s.finish();
 
         match self.inner.cluster.calc_candidates(data_key, n) {
@@ -0,0 +1,136 @@
+use bytes::Bytes;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Codec the payload of an object is compressed with before erasure coding.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Zstd,
+    Lz4,
+}
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::None
+    }
+}
+impl Compression {
+    pub fn compress(&self, data: &[u8]) -> Bytes {
+        match self {
+            Compression::None => Bytes::copy_from_slice(data),
+            Compression::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL).unwrap().into(),
+            Compression::Lz4 => lz4_flex::compress(data).into(),
+        }
+    }
+    /// `size` is the length of the original data.
+    pub fn decompress(&self, data: &[u8], size: usize) -> anyhow::Result<Bytes> {
+        let out: Vec<u8> = match self {
+            Compression::None => data.to_vec(),
+            Compression::Zstd => zstd::bulk::decompress(data, size)?,
+            Compression::Lz4 => lz4_flex::decompress(data, size)?,
+        };
+        anyhow::ensure!(out.len() == size, "decompressed to a wrong length");
+        Ok(out.into())
+    }
+}
+
+/// The default level of zstd. Higher levels are too slow for the write path.
+const ZSTD_LEVEL: i32 = 3;
+
+/// How the objects are compressed unless the request chooses the codec.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Policy {
+    pub codec: Compression,
+    /// Objects smaller than this are stored as they are.
+    pub threshold: usize,
+}
+impl Policy {
+    pub fn choose(&self, len: usize) -> Compression {
+        if len < self.threshold {
+            Compression::None
+        } else {
+            self.codec
+        }
+    }
+}
+
+/// Compress the data unless the output isn't smaller.
+/// Returns the codec actually used and the stored data.
+pub fn compress(codec: Compression, data: Bytes) -> (Compression, Bytes) {
+    if codec == Compression::None {
+        return (Compression::None, data);
+    }
+    let compressed = codec.compress(&data);
+    if compressed.len() < data.len() {
+        (codec, compressed)
+    } else {
+        (Compression::None, data)
+    }
+}
+
+/// Counters of the objects compressed by the node.
+#[derive(Default)]
+pub struct Metrics {
+    compressed: AtomicU64,
+    original_bytes: AtomicU64,
+    stored_bytes: AtomicU64,
+}
+impl Metrics {
+    pub fn record(&self, original_len: usize, stored_len: usize) {
+        self.compressed.fetch_add(1, Ordering::Relaxed);
+        self.original_bytes
+            .fetch_add(original_len as u64, Ordering::Relaxed);
+        self.stored_bytes
+            .fetch_add(stored_len as u64, Ordering::Relaxed);
+    }
+    pub fn stats(&self) -> Stats {
+        Stats {
+            compressed: self.compressed.load(Ordering::Relaxed),
+            original_bytes: self.original_bytes.load(Ordering::Relaxed),
+            stored_bytes: self.stored_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Snapshot of the counters.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of the objects stored compressed.
+    pub compressed: u64,
+    /// Total size of the compressed objects before compression.
+    pub original_bytes: u64,
+    /// Total size of the compressed objects after compression.
+    pub stored_bytes: u64,
+}
+
+#[test]
+fn test_compression() {
+    let data = Bytes::from(b"{\"level\":\"info\",\"msg\":\"hello\"}\n".repeat(100));
+    for codec in [Compression::Zstd, Compression::Lz4] {
+        let (used, stored) = compress(codec, data.clone());
+        assert_eq!(used, codec);
+        assert!(stored.len() < data.len());
+        assert_eq!(used.decompress(&stored, data.len()).unwrap(), data);
+        assert!(used.decompress(&stored, data.len() + 1).is_err());
+    }
+
+    // Incompressible data is stored as it is.
+    let data: Bytes = (0..100)
+        .map(|_| rand::random::<u8>())
+        .collect::<Vec<_>>()
+        .into();
+    let (used, stored) = compress(Compression::Zstd, data.clone());
+    assert_eq!(used, Compression::None);
+    assert_eq!(stored, data);
+}
+
+#[test]
+fn test_policy() {
+    let policy = Policy {
+        codec: Compression::Lz4,
+        threshold: 100,
+    };
+    assert_eq!(policy.choose(99), Compression::None);
+    assert_eq!(policy.choose(100), Compression::Lz4);
+    assert_eq!(Policy::default().choose(1 << 20), Compression::None);
+}
@@ -0,0 +1,246 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Instant;
+use tokio::sync::Semaphore;
+
+/// Jobs of the foreground are run before the jobs of the background.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    /// Jobs a user is waiting for. e.g. create and read.
+    Foreground,
+    /// Jobs nobody is waiting for. e.g. rebuilding a lost piece.
+    Background,
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct Queues {
+    foreground: VecDeque<Job>,
+    background: VecDeque<Job>,
+    n_running_background: usize,
+}
+
+/// Counters of the jobs of a priority.
+#[derive(Default)]
+struct Metrics {
+    submitted: AtomicU64,
+    started: AtomicU64,
+    completed: AtomicU64,
+    wait_micros: AtomicU64,
+    run_micros: AtomicU64,
+}
+impl Metrics {
+    fn stats(&self) -> Stats {
+        let submitted = self.submitted.load(Ordering::Relaxed);
+        let started = self.started.load(Ordering::Relaxed);
+        let completed = self.completed.load(Ordering::Relaxed);
+        Stats {
+            queued: submitted.saturating_sub(started),
+            running: started.saturating_sub(completed),
+            completed,
+            wait_micros: self.wait_micros.load(Ordering::Relaxed),
+            run_micros: self.run_micros.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Snapshot of the counters of a priority.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub queued: u64,
+    pub running: u64,
+    pub completed: u64,
+    /// Total time the completed jobs waited in the queue.
+    pub wait_micros: u64,
+    /// Total time the completed jobs ran.
+    pub run_micros: u64,
+}
+
+struct Inner {
+    queues: Mutex<Queues>,
+    cond: Condvar,
+    /// Submitting waits while this many jobs are queued or running.
+    foreground_permits: Semaphore,
+    background_permits: Semaphore,
+    /// Background jobs never occupy more threads than this
+    /// so the foreground always has a thread to run on.
+    max_running_background: usize,
+    foreground_metrics: Metrics,
+    background_metrics: Metrics,
+}
+impl Inner {
+    fn metrics(&self, priority: Priority) -> &Metrics {
+        match priority {
+            Priority::Foreground => &self.foreground_metrics,
+            Priority::Background => &self.background_metrics,
+        }
+    }
+    fn next_job(&self) -> (Priority, Job) {
+        let mut queues = self.queues.lock().unwrap();
+        loop {
+            if let Some(job) = queues.foreground.pop_front() {
+                return (Priority::Foreground, job);
+            }
+            if queues.n_running_background < self.max_running_background {
+                if let Some(job) = queues.background.pop_front() {
+                    queues.n_running_background += 1;
+                    return (Priority::Background, job);
+                }
+            }
+            queues = self.cond.wait(queues).unwrap();
+        }
+    }
+    fn worker(&self) {
+        loop {
+            let (priority, job) = self.next_job();
+            job();
+            if priority == Priority::Background {
+                self.queues.lock().unwrap().n_running_background -= 1;
+                // The background job may be waiting for a thread.
+                self.cond.notify_one();
+            }
+        }
+    }
+}
+
+/// Dedicated threads for the CPU-heavy work such as erasure coding
+/// so it doesn't block the async runtime.
+#[derive(Clone)]
+pub struct CpuPool {
+    inner: Arc<Inner>,
+}
+impl CpuPool {
+    /// `queue_depth` is the number of the jobs of a priority that can be queued or running.
+    /// At least 2 threads are spawned so the background can't take all of them.
+    pub fn new(n_threads: usize, queue_depth: usize) -> Self {
+        let n_threads = std::cmp::max(n_threads, 2);
+        let inner = Arc::new(Inner {
+            queues: Mutex::new(Queues {
+                foreground: VecDeque::new(),
+                background: VecDeque::new(),
+                n_running_background: 0,
+            }),
+            cond: Condvar::new(),
+            foreground_permits: Semaphore::new(queue_depth),
+            background_permits: Semaphore::new(queue_depth),
+            max_running_background: n_threads / 2,
+            foreground_metrics: Metrics::default(),
+            background_metrics: Metrics::default(),
+        });
+        for i in 0..n_threads {
+            let inner = inner.clone();
+            std::thread::Builder::new()
+                .name(format!("cpu-pool-{}", i))
+                .spawn(move || inner.worker())
+                .unwrap();
+        }
+        Self { inner }
+    }
+    /// Run the function in the pool and wait for the result.
+    /// The caller waits for a room in the queue if it is full.
+    pub async fn run<F, R>(&self, priority: Priority, f: F) -> R
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let permits = match priority {
+            Priority::Foreground => &self.inner.foreground_permits,
+            Priority::Background => &self.inner.background_permits,
+        };
+        // Held until the job completes.
+        let _permit = permits.acquire().await.unwrap();
+
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let inner = self.inner.clone();
+        let submitted_at = Instant::now();
+        let job: Job = Box::new(move || {
+            let metrics = inner.metrics(priority);
+            metrics.started.fetch_add(1, Ordering::Relaxed);
+            let started_at = Instant::now();
+            // A panic is sent back to the caller so the thread survives.
+            let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+            let wait = started_at.duration_since(submitted_at).as_micros() as u64;
+            let run = started_at.elapsed().as_micros() as u64;
+            metrics.wait_micros.fetch_add(wait, Ordering::Relaxed);
+            metrics.run_micros.fetch_add(run, Ordering::Relaxed);
+            metrics.completed.fetch_add(1, Ordering::Relaxed);
+            tx.send(res).ok();
+        });
+
+        self.inner
+            .metrics(priority)
+            .submitted
+            .fetch_add(1, Ordering::Relaxed);
+        {
+            let mut queues = self.inner.queues.lock().unwrap();
+            match priority {
+                Priority::Foreground => queues.foreground.push_back(job),
+                Priority::Background => queues.background.push_back(job),
+            }
+        }
+        self.inner.cond.notify_one();
+
+        match rx.await.unwrap() {
+            Ok(r) => r,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+    pub fn stats(&self, priority: Priority) -> Stats {
+        self.inner.metrics(priority).stats()
+    }
+}
+
+#[tokio::test]
+async fn test_cpu_pool() {
+    let pool = CpuPool::new(2, 4);
+    let mut futs = vec![];
+    for i in 0..10u64 {
+        let priority = if i % 2 == 0 {
+            Priority::Foreground
+        } else {
+            Priority::Background
+        };
+        futs.push(pool.run(priority, move || i * 2));
+    }
+    let out = futures::future::join_all(futs).await;
+    assert_eq!(out, (0..10).map(|i| i * 2).collect::<Vec<_>>());
+
+    let stats = pool.stats(Priority::Foreground);
+    assert_eq!(stats.completed, 5);
+    assert_eq!(stats.queued, 0);
+    assert_eq!(stats.running, 0);
+    assert_eq!(pool.stats(Priority::Background).completed, 5);
+
+    // The pool survives a panic in the job.
+    let pool2 = pool.clone();
+    let res = tokio::spawn(async move {
+        pool2
+            .run(Priority::Foreground, || -> u64 { panic!() })
+            .await
+    })
+    .await;
+    assert!(res.is_err());
+    assert_eq!(pool.run(Priority::Foreground, || 1).await, 1);
+}
+
+#[tokio::test]
+async fn test_cpu_pool_foreground_not_starved() {
+    // Even a pool of one thread has a thread for the foreground.
+    let pool = CpuPool::new(1, 4);
+    let (tx, rx) = std::sync::mpsc::channel::<()>();
+    let pool2 = pool.clone();
+    let background = tokio::spawn(async move {
+        pool2
+            .run(Priority::Background, move || rx.recv().unwrap())
+            .await
+    });
+    while pool.stats(Priority::Background).running == 0 {
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+    }
+    let fut = pool.run(Priority::Foreground, || 1);
+    let res = tokio::time::timeout(std::time::Duration::from_secs(5), fut).await;
+    assert_eq!(res.unwrap(), 1);
+    tx.send(()).unwrap();
+    background.await.unwrap();
+}
@@ -1,21 +1,296 @@
 use crate::*;
 use bytes::BytesMut;
+use cpu_pool::{CpuPool, Priority};
+use lol_core::Uri;
+use manifest::{Manifest, Part};
+use piece::{ObjectKind, PieceHeader};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
 #[norpc::service]
 trait IOFront {
-    fn create(key: String, value: Bytes) -> anyhow::Result<()>;
-    fn read(key: String) -> anyhow::Result<Bytes>;
-    fn sanity_check(key: String) -> anyhow::Result<usize>;
+    // Every write to a user key creates a new version and returns the version id.
+    // The internal objects aren't versioned and the version id is empty.
+    // Unless the version is specified, the latest complete version is read.
+    // An expired object is read as missing until the reaper drops it.
+    // A create succeeds once the pieces required by the durability are written.
+    // Unless the codec is specified, the value is compressed by the policy of the node.
+    // Returns the version id and the number of the pieces written.
+    fn create(
+        key: String,
+        value: Bytes,
+        user_meta: BTreeMap<String, String>,
+        expires_at: Option<u64>,
+        durability: Option<Durability>,
+        storage_class: String,
+        compression: Option<Compression>,
+    ) -> std::result::Result<(String, usize), Error>;
+    fn create_manifest(
+        key: String,
+        manifest: Manifest,
+        user_meta: BTreeMap<String, String>,
+    ) -> std::result::Result<(String, usize), Error>;
+    // The results are in the order of the entries.
+    fn batch_create(entries: Vec<CreateEntry>) -> Vec<std::result::Result<String, Error>>;
+    // The results are in the order of the keys.
+    fn batch_read(keys: Vec<String>) -> Vec<std::result::Result<Bytes, Error>>;
+    // Content-addressed create.
+    // Returns the key, the version id and whether the object was written.
+    fn put(
+        value: Bytes,
+        algorithm: HashAlgorithm,
+        user_meta: BTreeMap<String, String>,
+    ) -> std::result::Result<(String, String, bool), Error>;
+    // Returns the upload id.
+    fn initiate_multipart(
+        key: String,
+        user_meta: BTreeMap<String, String>,
+    ) -> std::result::Result<String, Error>;
+    fn upload_part(
+        key: String,
+        upload_id: String,
+        part_number: u32,
+        value: Bytes,
+    ) -> std::result::Result<Part, Error>;
+    // The parts are stitched in the order of the part numbers.
+    fn complete_multipart(
+        key: String,
+        upload_id: String,
+        part_numbers: Vec<u32>,
+    ) -> std::result::Result<(String, usize), Error>;
+    fn abort_multipart(key: String, upload_id: String) -> std::result::Result<(), Error>;
+    // Roll back the stripes of a streaming create that failed before its manifest was written.
+    fn abort_stream(parts: Vec<Part>);
+    fn head(key: String, version_id: Option<String>) -> std::result::Result<ObjectMeta, Error>;
+    // Returns the keys in the page and the cursor to the next page.
+    fn list(
+        prefix: String,
+        start_after: Option<String>,
+        limit: usize,
+    ) -> std::result::Result<(Vec<String>, Option<String>), Error>;
+    // The version ids of the key. Newest first.
+    fn list_versions(key: String) -> std::result::Result<Vec<String>, Error>;
+    // Read the object. The parts are stitched if the object is a manifest.
+    fn read(key: String, version_id: Option<String>) -> std::result::Result<Bytes, Error>;
+    fn read_object(key: String, version_id: Option<String>) -> std::result::Result<Object, Error>;
+    fn read_range(
+        key: String,
+        version_id: Option<String>,
+        offset: u64,
+        length: u64,
+    ) -> std::result::Result<Bytes, Error>;
+    // Delete the version. All versions are deleted if the version isn't specified.
+    fn delete(key: String, version_id: Option<String>) -> std::result::Result<(), Error>;
+    fn sanity_check(key: String) -> std::result::Result<usize, Error>;
     fn set_new_cluster(cluster: ClusterMap);
+    fn cluster() -> ClusterMap;
+    // The objects compressed by this node.
+    fn compression_stats() -> compression::Stats;
 }
 define_client!(IOFront);
 
-pub fn spawn(peer_out_cli: peer_out::ClientT, state: State) -> ClientT {
+/// An entry of `batch_create`. The options are the same as `create`.
+pub struct CreateEntry {
+    pub key: String,
+    pub value: Bytes,
+    pub user_meta: BTreeMap<String, String>,
+    pub expires_at: Option<u64>,
+    pub durability: Option<Durability>,
+    pub storage_class: String,
+    pub compression: Option<Compression>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum HashAlgorithm {
+    Sha256,
+    Blake3,
+}
+impl HashAlgorithm {
+    /// Hex-encoded digest
+    pub fn digest(&self, data: &[u8]) -> String {
+        match self {
+            HashAlgorithm::Sha256 => {
+                use sha2::Digest;
+                hex::encode(sha2::Sha256::digest(data))
+            }
+            HashAlgorithm::Blake3 => blake3::hash(data).to_hex().to_string(),
+        }
+    }
+}
+
+/// An object encoded into its pieces.
+struct EncodedObject {
+    /// Key the pieces are stored under.
+    key: String,
+    version_id: String,
+    /// The holders of the pieces in the order of the index.
+    holders: Vec<Option<Uri>>,
+    pieces: Vec<Piece>,
+    n_required: usize,
+    /// Whether the pieces are dropped if the write fails.
+    rollback: bool,
+}
+
+/// Number of keys listed at once in the internal operations.
+const LIST_LIMIT: usize = 1000;
+
+/// Pieces of a version found in the holders.
+#[derive(Clone, Copy, Debug)]
+struct FoundPieces {
+    n_pieces: usize,
+    /// Parameters the version was coded with.
+    ec: EcParams,
+}
+
+/// Versions of a key with the pieces found.
+type Versions = BTreeMap<String, FoundPieces>;
+
+/// The newest version that has enough pieces to be read.
+/// Each version needs the k it was coded with.
+fn latest_complete(versions: &Versions) -> Option<(String, EcParams)> {
+    versions
+        .iter()
+        .rev()
+        .find(|(_, found)| found.n_pieces >= found.ec.k)
+        .map(|(version_id, found)| (version_id.clone(), found.ec))
+}
+
+/// Parameters of the storage class. Empty for the default of the cluster.
+fn storage_class_ec(
+    cluster: &ClusterMap,
+    storage_class: &str,
+) -> std::result::Result<EcParams, Error> {
+    cluster
+        .storage_class(storage_class)
+        .ok_or_else(|| Error::InvalidArgument(format!("unknown storage class ({})", storage_class)))
+}
+
+/// User objects are stored under a new version.
+/// The internal objects are stored as they are.
+fn assign_version(key: String, meta: &mut ObjectMeta) -> String {
+    if manifest::is_internal_key(&key) {
+        return key;
+    }
+    meta.version_id = versioning::new_version_id();
+    versioning::version_key(&key, &meta.version_id)
+}
+
+/// Erasure-code the value into n pieces.
+fn encode_pieces(value: &[u8], ec: EcParams, kind: ObjectKind, meta: ObjectMeta) -> Vec<Piece> {
+    use reed_solomon_erasure::galois_8::ReedSolomon;
+
+    let header = PieceHeader {
+        len: value.len() as u64,
+        ec,
+        kind,
+        meta,
+    };
+    // With k = 1 every parity shard is a copy of the data.
+    if ec.k == 1 {
+        let plen = piece::shard_len(value.len(), 1);
+        let mut padded = BytesMut::with_capacity(plen);
+        padded.extend_from_slice(value);
+        padded.resize(plen, 0);
+        let piece = Piece::new(piece::encode(&header, &padded));
+        return vec![piece; ec.n];
+    }
+
+    // Pad the value so it can be split into k pieces of the same length.
+    let plen = piece::shard_len(value.len(), ec.k);
+    let mut padded = BytesMut::with_capacity(plen * ec.k);
+    padded.extend_from_slice(value);
+    padded.resize(plen * ec.k, 0);
+    let padded = padded.freeze();
+
+    let r = ReedSolomon::new(ec.k, ec.n - ec.k).unwrap();
+    let mut data = vec![];
+    for i in 0..ec.k {
+        let buf = padded.slice(i * plen..(i + 1) * plen);
+        data.push(buf);
+    }
+    let mut parity = vec![];
+    let zero = vec![0; plen];
+    for _ in 0..(ec.n - ec.k) {
+        let mut buf = BytesMut::with_capacity(plen);
+        buf.extend_from_slice(&zero);
+        parity.push(buf);
+    }
+    r.encode_sep(&data, &mut parity).unwrap();
+
+    let mut out = vec![];
+    for shard in &data {
+        out.push(Piece::new(piece::encode(&header, shard)));
+    }
+    for shard in &parity {
+        out.push(Piece::new(piece::encode(&header, shard)));
+    }
+    out
+}
+
+/// Compress the value with the codec in the metadata and erasure-code it.
+/// The value is stored as it is if it doesn't shrink.
+fn compress_and_encode(
+    value: Bytes,
+    ec: EcParams,
+    kind: ObjectKind,
+    mut meta: ObjectMeta,
+    metrics: &compression::Metrics,
+) -> Vec<Piece> {
+    let original_len = value.len();
+    let (codec, value) = compression::compress(meta.compression, value);
+    if codec != Compression::None {
+        metrics.record(original_len, value.len());
+    }
+    meta.compression = codec;
+    encode_pieces(&value, ec, kind, meta)
+}
+
+/// Merge the data pieces of an object fetched in a batch.
+/// None if the object can't be read this way and should be read
+/// through the rebuild.
+fn merge_data_pieces(pieces: Vec<Option<Piece>>, ec: EcParams) -> Option<Bytes> {
+    let now = unix_time();
+    let mut merged = BytesMut::new();
+    let mut last_header = None;
+    for piece in pieces {
+        let piece = piece?;
+        if !piece.verify() {
+            return None;
+        }
+        let (header, shard) = piece::decode(&piece.data).ok()?;
+        if header.ec != ec || header.kind != ObjectKind::Data || header.meta.is_expired(now) {
+            return None;
+        }
+        merged.extend_from_slice(shard);
+        last_header = Some(header);
+    }
+    let header = last_header?;
+    merged.truncate(header.len as usize);
+    let merged = match header.meta.compression {
+        Compression::None => merged.freeze(),
+        codec => codec.decompress(&merged, header.meta.size as usize).ok()?,
+    };
+    if crc32c::crc32c(&merged) != header.meta.checksum {
+        return None;
+    }
+    Some(merged)
+}
+
+pub enum Object {
+    Data(Bytes),
+    Manifest(Manifest),
+}
+
+pub fn spawn(
+    peer_out_cli: peer_out::ClientT,
+    rollback_queue_cli: rollback_queue::ClientT,
+    state: State,
+) -> ClientT {
     use norpc::runtime::tokio::*;
     let svc = App {
         peer_out_cli,
+        rollback_queue_cli,
         state,
     };
     let svc = IOFrontService::new(svc);
@@ -26,63 +301,831 @@ pub fn spawn(peer_out_cli: peer_out::ClientT, state: State) -> ClientT {
 
 pub struct State {
     cluster: RwLock<ClusterMap>,
+    /// How long a read waits for a piece before requesting another one.
+    hedge_delay: std::time::Duration,
+    cpu_pool: CpuPool,
+    /// How the objects are compressed unless the request chooses the codec.
+    compression: compression::Policy,
+    compression_metrics: Arc<compression::Metrics>,
 }
 impl State {
-    pub fn new() -> Self {
+    pub fn new(
+        hedge_delay: std::time::Duration,
+        cpu_pool: CpuPool,
+        compression: compression::Policy,
+    ) -> Self {
         Self {
             cluster: RwLock::new(ClusterMap::new()),
+            hedge_delay,
+            cpu_pool,
+            compression,
+            compression_metrics: Arc::new(compression::Metrics::default()),
         }
     }
 }
 
 struct App {
     peer_out_cli: peer_out::ClientT,
+    rollback_queue_cli: rollback_queue::ClientT,
     state: State,
 }
 #[norpc::async_trait]
 impl IOFront for App {
-    async fn create(&self, key: String, value: Bytes) -> anyhow::Result<()> {
-        use reed_solomon_erasure::galois_8::ReedSolomon;
-
-        let plen = value.len() / K;
-        let r = ReedSolomon::new(K, N - K).unwrap();
-        let mut data = vec![];
-        for i in 0..K {
-            let buf = value.slice(i * plen..(i + 1) * plen);
-            data.push(buf);
-        }
-        let mut parity = vec![];
-        let zero = vec![0; plen];
-        for _ in 0..(N - K) {
-            let mut buf = BytesMut::with_capacity(plen);
-            buf.extend_from_slice(&zero);
-            parity.push(buf);
-        }
-        r.encode_sep(&data, &mut parity).unwrap();
-
-        let mut piece_data = vec![];
-        data.reverse();
-        for _ in 0..K {
-            piece_data.push(data.pop().unwrap());
-        }
-        parity.reverse();
-        for _ in 0..(N - K) {
-            piece_data.push(parity.pop().unwrap().freeze());
-        }
-        piece_data.reverse();
-
-        let holders = self
-            .state
-            .cluster
-            .read()
+    async fn create(
+        &self,
+        key: String,
+        value: Bytes,
+        user_meta: BTreeMap<String, String>,
+        expires_at: Option<u64>,
+        durability: Option<Durability>,
+        storage_class: String,
+        compression: Option<Compression>,
+    ) -> std::result::Result<(String, usize), Error> {
+        let meta = self.new_meta(&value, user_meta, expires_at, storage_class, compression);
+        self.write_object(key, value, ObjectKind::Data, meta, durability)
+            .await
+    }
+    async fn create_manifest(
+        &self,
+        key: String,
+        manifest: Manifest,
+        user_meta: BTreeMap<String, String>,
+    ) -> std::result::Result<(String, usize), Error> {
+        let meta = ObjectMeta {
+            size: manifest.size(),
+            created_at: unix_time(),
+            checksum: manifest.checksum(),
+            cluster_version: 0,
+            user: user_meta,
+            version_id: String::new(),
+            expires_at: None,
+            storage_class: String::new(),
+            compression: Compression::None,
+        };
+        self.write_object(key, manifest.encode(), ObjectKind::Manifest, meta, None)
+            .await
+    }
+    async fn batch_create(
+        &self,
+        entries: Vec<CreateEntry>,
+    ) -> Vec<std::result::Result<String, Error>> {
+        let cluster = self.state.cluster.read().await.clone();
+        let version = cluster.version();
+
+        // Each entry is encoded the same way as a single create.
+        let mut seen = HashSet::new();
+        let mut out = vec![];
+        let mut encodes = vec![];
+        for (i, entry) in entries.into_iter().enumerate() {
+            if !seen.insert(entry.key.clone()) {
+                out.push(Err(Error::InvalidArgument(format!(
+                    "duplicate key in the batch (key={})",
+                    entry.key
+                ))));
+                continue;
+            }
+            out.push(Ok(String::new()));
+            let meta = self.new_meta(
+                &entry.value,
+                entry.user_meta,
+                entry.expires_at,
+                entry.storage_class,
+                entry.compression,
+            );
+            let (key, value, durability) = (entry.key, entry.value, entry.durability);
+            let cluster = &cluster;
+            encodes.push(async move {
+                let encoded = self
+                    .encode_object(cluster, key, value, ObjectKind::Data, meta, durability)
+                    .await;
+                (i, encoded)
+            });
+        }
+
+        // Pieces are grouped by the holder so each holder is sent only once.
+        let mut batches: HashMap<Uri, Vec<(usize, SendPiece)>> = HashMap::new();
+        let mut objects = vec![];
+        for (i, encoded) in futures::future::join_all(encodes).await {
+            let mut obj = match encoded {
+                Ok(obj) => obj,
+                Err(e) => {
+                    out[i] = Err(e);
+                    continue;
+                }
+            };
+            out[i] = Ok(obj.version_id.clone());
+            let pieces = std::mem::take(&mut obj.pieces);
+            for (index, piece) in pieces.into_iter().enumerate() {
+                if let Some(uri) = obj.holders[index].clone() {
+                    let send_piece = SendPiece {
+                        version,
+                        loc: PieceLocator {
+                            key: obj.key.clone(),
+                            index: index as u8,
+                        },
+                        data: Some(piece),
+                    };
+                    batches.entry(uri).or_default().push((i, send_piece));
+                }
+            }
+            objects.push((i, obj));
+        }
+
+        let mut futs = vec![];
+        for (uri, batch) in batches {
+            let mut out_cli = self.peer_out_cli.clone();
+            futs.push(async move {
+                let (owners, pieces): (Vec<usize>, Vec<SendPiece>) = batch.into_iter().unzip();
+                let res = out_cli.send_pieces(uri, pieces).await;
+                owners.into_iter().zip(res).collect::<Vec<_>>()
+            });
+        }
+        let mut n_ok = vec![0; out.len()];
+        for res in futures::future::join_all(futs).await {
+            for (i, send_res) in res {
+                if send_res.is_ok() {
+                    n_ok[i] += 1;
+                }
+            }
+        }
+
+        for (i, obj) in objects {
+            if let Err(e) = self.check_written(&obj, n_ok[i]).await {
+                out[i] = Err(e);
+            }
+        }
+        out
+    }
+    async fn batch_read(&self, keys: Vec<String>) -> Vec<std::result::Result<Bytes, Error>> {
+        let cluster = self.state.cluster.read().await.clone();
+        let max_n = cluster.max_n();
+
+        // The latest versions are found by asking the holders.
+        let mut dests: HashMap<Uri, Vec<usize>> = HashMap::new();
+        for (i, key) in keys.iter().enumerate() {
+            let holders: HashSet<Uri> = cluster
+                .compute_holders(key.clone(), max_n)
+                .into_iter()
+                .flatten()
+                .collect();
+            for uri in holders {
+                dests.entry(uri).or_default().push(i);
+            }
+        }
+        let versions = self.collect_versions(&keys, dests).await;
+
+        // Only the data pieces are requested, grouped by the holder.
+        let mut batches: HashMap<Uri, Vec<(usize, PieceLocator)>> = HashMap::new();
+        let mut fetched: Vec<(Vec<Option<Piece>>, Option<EcParams>)> =
+            vec![(vec![], None); keys.len()];
+        for (i, key) in keys.iter().enumerate() {
+            let (version_id, ec) = match latest_complete(&versions[i].0) {
+                Some(x) => x,
+                None => continue,
+            };
+            fetched[i] = (vec![None; ec.k], Some(ec));
+            let stored_key = versioning::version_key(key, &version_id);
+            let holders = cluster.compute_holders(stored_key.clone(), ec.n);
+            for index in 0..ec.k {
+                if let Some(uri) = holders[index].clone() {
+                    let loc = PieceLocator {
+                        key: stored_key.clone(),
+                        index: index as u8,
+                    };
+                    batches.entry(uri).or_default().push((i, loc));
+                }
+            }
+        }
+
+        let mut futs = vec![];
+        for (uri, batch) in batches {
+            let mut out_cli = self.peer_out_cli.clone();
+            futs.push(async move {
+                let locs: Vec<PieceLocator> = batch.iter().map(|(_, loc)| loc.clone()).collect();
+                match out_cli.request_pieces(uri, locs).await {
+                    Ok(pieces) => batch.into_iter().zip(pieces).collect::<Vec<_>>(),
+                    Err(_) => vec![],
+                }
+            });
+        }
+        for res in futures::future::join_all(futs).await {
+            for ((i, loc), piece) in res {
+                fetched[i].0[loc.index as usize] = piece;
+            }
+        }
+
+        // Objects that couldn't be merged are read one by one.
+        // e.g. written before the cluster changed or lost some pieces.
+        let mut futs = vec![];
+        for (key, (pieces, ec)) in keys.into_iter().zip(fetched) {
+            futs.push(async move {
+                match ec.and_then(|ec| merge_data_pieces(pieces, ec)) {
+                    Some(data) => Ok(data),
+                    None => self.read(key, None).await,
+                }
+            });
+        }
+        futures::future::join_all(futs).await
+    }
+    async fn put(
+        &self,
+        value: Bytes,
+        algorithm: HashAlgorithm,
+        user_meta: BTreeMap<String, String>,
+    ) -> std::result::Result<(String, String, bool), Error> {
+        let key = algorithm.digest(&value);
+        // The same key always has the same value
+        // so the write can be skipped if no piece of the latest version is lost.
+        match self.latest_version(key.clone()).await {
+            Ok(version_id) => {
+                let stored_key = versioning::version_key(&key, &version_id);
+                if self.count_lost(stored_key).await? == 0 {
+                    return Ok((key, version_id, false));
+                }
+            }
+            Err(Error::NotFound(_)) | Err(Error::DataLoss(_)) => {}
+            Err(e) => return Err(e),
+        }
+        let (version_id, _) = self
+            .create(
+                key.clone(),
+                value,
+                user_meta,
+                None,
+                None,
+                String::new(),
+                None,
+            )
+            .await?;
+        Ok((key, version_id, true))
+    }
+    async fn abort_stream(&self, parts: Vec<Part>) {
+        let cluster = self.state.cluster.read().await.clone();
+        for part in parts {
+            // The stripes are written under the default storage class
+            // but the parameters may have been changed since.
+            let holders = cluster.compute_holders(part.key.clone(), cluster.max_n());
+            let holders = holders.into_iter().flatten().collect();
+            self.rollback(part.key, holders).await;
+        }
+    }
+    async fn head(
+        &self,
+        key: String,
+        version_id: Option<String>,
+    ) -> std::result::Result<ObjectMeta, Error> {
+        let key = self.resolve(key, version_id).await?;
+        let header = self.read_header(key).await?;
+        Ok(header.meta)
+    }
+    async fn list(
+        &self,
+        prefix: String,
+        start_after: Option<String>,
+        limit: usize,
+    ) -> std::result::Result<(Vec<String>, Option<String>), Error> {
+        // The objects are stored under their versions and
+        // the other internal objects are hidden from the user.
+        let mut keys: Vec<String> = vec![];
+        let mut raw_start_after = start_after.clone();
+        loop {
+            let (page, next) = self
+                .list_raw(prefix.clone(), raw_start_after, limit)
+                .await?;
+            for k in page {
+                let key = match versioning::parse_version_key(&k) {
+                    Some((key, _)) => key,
+                    None => continue,
+                };
+                // The versions of the last key of the previous page.
+                if let Some(start_after) = &start_after {
+                    if &key <= start_after {
+                        continue;
+                    }
+                }
+                if keys.last() != Some(&key) {
+                    keys.push(key);
+                }
+            }
+            if keys.len() >= limit {
+                keys.truncate(limit);
+                let next = keys.last().cloned();
+                return Ok((keys, next));
+            }
+            if next.is_none() {
+                return Ok((keys, None));
+            }
+            raw_start_after = next;
+        }
+    }
+    async fn list_versions(&self, key: String) -> std::result::Result<Vec<String>, Error> {
+        let (versions, failed) = self.broadcast_versions(key.clone()).await;
+        if failed {
+            return Err(Error::Unavailable(format!(
+                "failed to ask all members (key={})",
+                key
+            )));
+        }
+        Ok(versions.into_keys().rev().collect())
+    }
+    async fn initiate_multipart(
+        &self,
+        key: String,
+        user_meta: BTreeMap<String, String>,
+    ) -> std::result::Result<String, Error> {
+        let upload_id = multipart::new_upload_id();
+        self.create(
+            multipart::upload_key(&key, &upload_id),
+            Bytes::new(),
+            user_meta,
+            None,
+            None,
+            String::new(),
+            None,
+        )
+        .await?;
+        Ok(upload_id)
+    }
+    async fn upload_part(
+        &self,
+        key: String,
+        upload_id: String,
+        part_number: u32,
+        value: Bytes,
+    ) -> std::result::Result<Part, Error> {
+        if part_number == 0 || part_number > multipart::MAX_PART_NUMBER {
+            return Err(Error::InvalidArgument(format!(
+                "part number should be in 1..={}",
+                multipart::MAX_PART_NUMBER
+            )));
+        }
+        self.find_upload(&key, &upload_id).await?;
+        let part = Part {
+            key: multipart::part_key(&key, &upload_id, part_number),
+            len: value.len() as u64,
+            checksum: crc32c::crc32c(&value),
+        };
+        self.create(
+            part.key.clone(),
+            value,
+            BTreeMap::new(),
+            None,
+            None,
+            String::new(),
+            None,
+        )
+        .await?;
+        Ok(part)
+    }
+    async fn complete_multipart(
+        &self,
+        key: String,
+        upload_id: String,
+        part_numbers: Vec<u32>,
+    ) -> std::result::Result<(String, usize), Error> {
+        if part_numbers.is_empty() {
+            return Err(Error::InvalidArgument("no part is given".to_string()));
+        }
+        if part_numbers.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(Error::InvalidArgument(
+                "part numbers should be in ascending order".to_string(),
+            ));
+        }
+        let upload = self.find_upload(&key, &upload_id).await?;
+        let mut parts = vec![];
+        for part_number in part_numbers {
+            let part_key = multipart::part_key(&key, &upload_id, part_number);
+            let meta = match self.head(part_key.clone(), None).await {
+                Ok(meta) => meta,
+                Err(Error::NotFound(_)) => {
+                    return Err(Error::InvalidArgument(format!(
+                        "part {} is not uploaded",
+                        part_number
+                    )))
+                }
+                Err(e) => return Err(e),
+            };
+            parts.push(Part {
+                key: part_key,
+                len: meta.size,
+                checksum: meta.checksum,
+            });
+        }
+        let written = self
+            .create_manifest(key.clone(), Manifest { parts }, upload.user)
+            .await?;
+        self.cleanup_upload(key, upload_id).await?;
+        Ok(written)
+    }
+    async fn abort_multipart(
+        &self,
+        key: String,
+        upload_id: String,
+    ) -> std::result::Result<(), Error> {
+        self.find_upload(&key, &upload_id).await?;
+        self.cleanup_upload(key, upload_id).await
+    }
+    async fn read(
+        &self,
+        key: String,
+        version_id: Option<String>,
+    ) -> std::result::Result<Bytes, Error> {
+        let key = self.resolve(key, version_id).await?;
+        match self.read_object(key, None).await? {
+            Object::Data(data) => Ok(data),
+            Object::Manifest(manifest) => {
+                let mut merged = BytesMut::new();
+                for part in manifest.parts {
+                    let (_, data) = self
+                        .read_raw(part.key)
+                        .await
+                        .map_err(Error::into_part_error)?;
+                    merged.extend_from_slice(&data);
+                }
+                Ok(merged.freeze())
+            }
+        }
+    }
+    async fn read_object(
+        &self,
+        key: String,
+        version_id: Option<String>,
+    ) -> std::result::Result<Object, Error> {
+        let key = self.resolve(key, version_id).await?;
+        let (header, data) = self.read_raw(key.clone()).await?;
+        match header.kind {
+            ObjectKind::Data => Ok(Object::Data(data)),
+            ObjectKind::Manifest => {
+                let manifest = Manifest::decode(&data).map_err(|_| Error::DataLoss(key))?;
+                Ok(Object::Manifest(manifest))
+            }
+        }
+    }
+    async fn read_range(
+        &self,
+        key: String,
+        version_id: Option<String>,
+        offset: u64,
+        length: u64,
+    ) -> std::result::Result<Bytes, Error> {
+        let key = self.resolve(key, version_id).await?;
+        let header = self.read_header(key.clone()).await?;
+        match header.kind {
+            ObjectKind::Data => self.read_data_range(key, header, offset, length).await,
+            ObjectKind::Manifest => {
+                let (_, data) = self.read_raw(key.clone()).await?;
+                let manifest = Manifest::decode(&data).map_err(|_| Error::DataLoss(key))?;
+                let end = offset.saturating_add(length);
+                let mut merged = BytesMut::new();
+                let mut part_start = 0;
+                for part in manifest.parts {
+                    let part_end = part_start + part.len;
+                    // Only the overlapping parts are read.
+                    if part_start < end && offset < part_end {
+                        let from = offset.saturating_sub(part_start);
+                        let to = std::cmp::min(end, part_end) - part_start;
+                        let part_header = self
+                            .read_header(part.key.clone())
+                            .await
+                            .map_err(Error::into_part_error)?;
+                        let data = self
+                            .read_data_range(part.key, part_header, from, to - from)
+                            .await
+                            .map_err(Error::into_part_error)?;
+                        merged.extend_from_slice(&data);
+                    }
+                    part_start = part_end;
+                }
+                Ok(merged.freeze())
+            }
+        }
+    }
+    async fn delete(
+        &self,
+        key: String,
+        version_id: Option<String>,
+    ) -> std::result::Result<(), Error> {
+        if manifest::is_internal_key(&key) || version_id.is_some() {
+            let key = self.resolve(key, version_id).await?;
+            return self.delete_object(key).await;
+        }
+        let (versions, failed) = self.broadcast_versions(key.clone()).await;
+        if failed {
+            return Err(Error::Unavailable(format!(
+                "failed to ask all members (key={})",
+                key
+            )));
+        }
+        for version_id in versions.into_keys() {
+            self.delete_object(versioning::version_key(&key, &version_id))
+                .await?;
+        }
+        Ok(())
+    }
+    async fn sanity_check(&self, key: String) -> std::result::Result<usize, Error> {
+        let key = self.resolve(key, None).await?;
+        let mut n_lost = self.count_lost(key.clone()).await?;
+        // The most damaged part determines the redundancy of the object.
+        if let Some(manifest) = self.find_manifest(key).await? {
+            for part in manifest.parts {
+                let n = self.count_lost(part.key).await?;
+                n_lost = std::cmp::max(n_lost, n);
+            }
+        }
+        Ok(n_lost)
+    }
+    async fn set_new_cluster(&self, cluster: ClusterMap) {
+        *self.state.cluster.write().await = cluster;
+    }
+    async fn cluster(&self) -> ClusterMap {
+        self.state.cluster.read().await.clone()
+    }
+    async fn compression_stats(&self) -> compression::Stats {
+        self.state.compression_metrics.stats()
+    }
+}
+
+impl App {
+    /// Metadata of a new data object.
+    /// Unless the codec is specified, it is chosen by the policy of the node.
+    fn new_meta(
+        &self,
+        value: &Bytes,
+        user_meta: BTreeMap<String, String>,
+        expires_at: Option<u64>,
+        storage_class: String,
+        compression: Option<Compression>,
+    ) -> ObjectMeta {
+        let compression = compression.unwrap_or_else(|| self.state.compression.choose(value.len()));
+        ObjectMeta {
+            size: value.len() as u64,
+            created_at: unix_time(),
+            checksum: crc32c::crc32c(value),
+            cluster_version: 0,
+            user: user_meta,
+            version_id: String::new(),
+            expires_at,
+            storage_class,
+            compression,
+        }
+    }
+    /// Key the object is stored under.
+    async fn resolve(
+        &self,
+        key: String,
+        version_id: Option<String>,
+    ) -> std::result::Result<String, Error> {
+        if manifest::is_internal_key(&key) {
+            return Ok(key);
+        }
+        let version_id = match version_id {
+            Some(version_id) => {
+                if version_id.is_empty() || manifest::is_internal_key(&version_id) {
+                    return Err(Error::InvalidArgument(format!(
+                        "invalid version id (key={})",
+                        key
+                    )));
+                }
+                version_id
+            }
+            None => self.latest_version(key.clone()).await?,
+        };
+        Ok(versioning::version_key(&key, &version_id))
+    }
+    /// The newest version that has at least k pieces.
+    /// Versions being written or lost are skipped.
+    async fn latest_version(&self, key: String) -> std::result::Result<String, Error> {
+        let cluster = self.state.cluster.read().await.clone();
+        // The holders of any storage class are asked.
+        let holders: HashSet<Uri> = cluster
+            .compute_holders(key.clone(), cluster.max_n())
+            .into_iter()
+            .flatten()
+            .collect();
+        let dests = holders.into_iter().map(|uri| (uri, vec![0])).collect();
+        let (versions, _) = self
+            .collect_versions(&[key.clone()], dests)
             .await
-            .compute_holders(key.clone(), N);
-        let cluster_version = self.state.cluster.read().await.version();
+            .pop()
+            .unwrap();
+        if let Some((version_id, _)) = latest_complete(&versions) {
+            return Ok(version_id);
+        }
+
+        // The pieces may be on other nodes after the cluster changed.
+        let (versions, failed) = self.broadcast_versions(key.clone()).await;
+        if let Some((version_id, _)) = latest_complete(&versions) {
+            return Ok(version_id);
+        }
+        if failed {
+            return Err(Error::Unavailable(format!(
+                "failed to ask all members (key={})",
+                key
+            )));
+        }
+        if !versions.is_empty() {
+            return Err(Error::DataLoss(key));
+        }
+        Err(Error::NotFound(key))
+    }
+    /// Ask all members for the versions of the key.
+    /// Returns the versions and whether any member failed to answer.
+    async fn broadcast_versions(&self, key: String) -> (Versions, bool) {
+        let cluster = self.state.cluster.read().await.clone();
+        let dests = cluster
+            .members()
+            .into_iter()
+            .map(|uri| (uri, vec![0]))
+            .collect();
+        self.collect_versions(&[key], dests).await.pop().unwrap()
+    }
+    /// Ask the nodes for the versions of the keys.
+    /// Each node is asked about the keys of the given indices.
+    /// Returns the versions and whether any node failed to answer for each key.
+    async fn collect_versions(
+        &self,
+        keys: &[String],
+        dests: HashMap<Uri, Vec<usize>>,
+    ) -> Vec<(Versions, bool)> {
         let mut futs = vec![];
-        for i in 0..N {
-            let data = piece_data.pop().unwrap();
-            let key = key.clone();
-            let uri = holders[i as usize].clone();
+        for (uri, indices) in dests {
+            let mut peer_out_cli = self.peer_out_cli.clone();
+            let dest_keys = indices.iter().map(|i| keys[*i].clone()).collect();
+            futs.push(async move {
+                let fut = peer_out_cli.request_versions(uri, dest_keys);
+                let rep = tokio::time::timeout(std::time::Duration::from_secs(5), fut).await;
+                (indices, rep)
+            });
+        }
+        let mut out = vec![(Versions::new(), false); keys.len()];
+        for (indices, rep) in futures::future::join_all(futs).await {
+            match rep {
+                Ok(Ok(versions)) => {
+                    for (i, key_versions) in indices.into_iter().zip(versions) {
+                        for (version_id, n_pieces, ec) in key_versions {
+                            out[i]
+                                .0
+                                .entry(version_id)
+                                .or_insert(FoundPieces { n_pieces: 0, ec })
+                                .n_pieces += n_pieces;
+                        }
+                    }
+                }
+                _ => {
+                    for i in indices {
+                        out[i].1 = true;
+                    }
+                }
+            }
+        }
+        out
+    }
+    /// Delete the object. The parts are deleted if it is a manifest.
+    async fn delete_object(&self, key: String) -> std::result::Result<(), Error> {
+        // Parts are deleted before the manifest so a failed delete can be retried.
+        if let Some(manifest) = self.find_manifest(key.clone()).await? {
+            for part in manifest.parts {
+                self.delete_key(part.key).await?;
+            }
+        }
+        self.delete_key(key).await
+    }
+    /// List the keys including the internal ones.
+    async fn list_raw(
+        &self,
+        prefix: String,
+        start_after: Option<String>,
+        limit: usize,
+    ) -> std::result::Result<(Vec<String>, Option<String>), Error> {
+        let cluster = self.state.cluster.read().await.clone();
+        let mut futs = vec![];
+        for uri in cluster.members() {
+            let mut peer_out_cli = self.peer_out_cli.clone();
+            let prefix = prefix.clone();
+            let start_after = start_after.clone();
+            let fut = async move {
+                peer_out_cli
+                    .request_keys(uri, prefix, start_after, limit)
+                    .await
+            };
+            let fut = tokio::time::timeout(std::time::Duration::from_secs(5), fut);
+            futs.push(fut);
+        }
+
+        // Every member returns its smallest keys so the smallest keys in the union
+        // are the smallest in the cluster.
+        let stream = futures::stream::iter(futs);
+        let n_par = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            * 2;
+        let mut buffered = stream.buffer_unordered(n_par);
+        let mut merged = BTreeSet::new();
+        let mut has_more = false;
+        while let Some(rep) = buffered.next().await {
+            let keys = match rep {
+                Ok(Ok(keys)) => keys,
+                _ => {
+                    return Err(Error::Unavailable(
+                        "failed to list keys from all members".to_string(),
+                    ))
+                }
+            };
+            if keys.len() >= limit {
+                has_more = true;
+            }
+            merged.extend(keys);
+        }
+        if merged.len() > limit {
+            has_more = true;
+        }
+
+        let page: Vec<String> = merged.into_iter().take(limit).collect();
+        let next = if has_more { page.last().cloned() } else { None };
+        Ok((page, next))
+    }
+    /// Returns the metadata of the upload record.
+    async fn find_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+    ) -> std::result::Result<ObjectMeta, Error> {
+        if manifest::is_internal_key(upload_id) {
+            return Err(Error::InvalidArgument("invalid upload id".to_string()));
+        }
+        match self.head(multipart::upload_key(key, upload_id), None).await {
+            Ok(meta) => Ok(meta),
+            Err(Error::NotFound(_)) => Err(Error::NotFound(format!(
+                "{} (upload_id={})",
+                key, upload_id
+            ))),
+            Err(e) => Err(e),
+        }
+    }
+    /// Delete the parts not used by the object and then the upload record.
+    /// The parts are kept if the upload was completed and the object refers to them.
+    async fn cleanup_upload(
+        &self,
+        key: String,
+        upload_id: String,
+    ) -> std::result::Result<(), Error> {
+        let prefix = multipart::parts_prefix(&key, &upload_id);
+        // Any version may refer to the parts.
+        let (versions, failed) = self.broadcast_versions(key.clone()).await;
+        if failed {
+            return Err(Error::Unavailable(format!(
+                "failed to ask all members (key={})",
+                key
+            )));
+        }
+        let mut in_use = HashSet::new();
+        for version_id in versions.keys() {
+            let stored_key = versioning::version_key(&key, version_id);
+            if let Some(manifest) = self.find_manifest(stored_key).await? {
+                for part in manifest.parts {
+                    if part.key.starts_with(&prefix) {
+                        in_use.insert(part.key);
+                    }
+                }
+            }
+        }
+        let mut start_after = None;
+        loop {
+            let (page, next) = self
+                .list_raw(prefix.clone(), start_after, LIST_LIMIT)
+                .await?;
+            for part_key in page {
+                if !in_use.contains(&part_key) {
+                    self.delete_key(part_key).await?;
+                }
+            }
+            if next.is_none() {
+                break;
+            }
+            start_after = next;
+        }
+        // The record is deleted last so the cleanup can be retried.
+        self.delete_key(multipart::upload_key(&key, &upload_id))
+            .await
+    }
+    async fn write_object(
+        &self,
+        key: String,
+        value: Bytes,
+        kind: ObjectKind,
+        meta: ObjectMeta,
+        durability: Option<Durability>,
+    ) -> std::result::Result<(String, usize), Error> {
+        let cluster = self.state.cluster.read().await.clone();
+        let mut obj = self
+            .encode_object(&cluster, key, value, kind, meta, durability)
+            .await?;
+        let cluster_version = cluster.version();
+        let pieces = std::mem::take(&mut obj.pieces);
+        let mut futs = vec![];
+        for (i, data) in pieces.into_iter().enumerate() {
+            let key = obj.key.clone();
+            let uri = obj.holders[i].clone();
             let mut out_cli = self.peer_out_cli.clone();
             futs.push(async move {
                 let version = cluster_version;
@@ -108,20 +1151,111 @@ impl IOFront for App {
                 }
             });
         }
+        let n = futs.len();
         let stream = futures::stream::iter(futs);
-        let mut buffered = stream.buffer_unordered(N);
+        let mut buffered = stream.buffer_unordered(n);
         let mut n_ok = 0;
         while let Some(send_ok) = buffered.next().await {
             if send_ok {
                 n_ok += 1;
             }
         }
-        if n_ok < K {
-            anyhow::bail!("failed to write sufficient pieces: key={}", &key);
+        self.check_written(&obj, n_ok).await?;
+        Ok((obj.version_id, n_ok))
+    }
+    /// Encode the object into the pieces to be sent to its holders.
+    /// Every write goes through here so the options are applied the same way.
+    async fn encode_object(
+        &self,
+        cluster: &ClusterMap,
+        key: String,
+        value: Bytes,
+        kind: ObjectKind,
+        mut meta: ObjectMeta,
+        durability: Option<Durability>,
+    ) -> std::result::Result<EncodedObject, Error> {
+        meta.cluster_version = cluster.version();
+        let durability = durability.unwrap_or(cluster.durability());
+        // A failed write to a new version or a stripe is rolled back.
+        // The other internal objects may be written again under the same key
+        // so their pieces are left to be overwritten or collected.
+        let rollback = !manifest::is_internal_key(&key) || manifest::is_stripe_key(&key);
+        let key = assign_version(key, &mut meta);
+        let version_id = meta.version_id.clone();
+        let ec = storage_class_ec(cluster, &meta.storage_class)?;
+        // Uncompressed replicas are copies so there's nothing to offload to the pool.
+        let offload = ec.k > 1 || meta.compression != Compression::None;
+        let metrics = self.state.compression_metrics.clone();
+        let encode = move || compress_and_encode(value, ec, kind, meta, &metrics);
+        let pieces = if offload {
+            self.state.cpu_pool.run(Priority::Foreground, encode).await
+        } else {
+            encode()
+        };
+        let holders = cluster.compute_holders(key.clone(), ec.n);
+        Ok(EncodedObject {
+            key,
+            version_id,
+            holders,
+            pieces,
+            n_required: durability.required(ec),
+            rollback,
+        })
+    }
+    /// Fails if fewer pieces than required are written.
+    /// The failed write is rolled back.
+    async fn check_written(
+        &self,
+        obj: &EncodedObject,
+        n_ok: usize,
+    ) -> std::result::Result<(), Error> {
+        if n_ok >= obj.n_required {
+            return Ok(());
+        }
+        if obj.rollback {
+            // A failed send may have saved the piece
+            // so every holder is asked to drop the pieces.
+            let holders = obj.holders.iter().flatten().cloned().collect();
+            self.rollback(obj.key.clone(), holders).await;
+        }
+        Err(Error::Unavailable(format!(
+            "failed to write sufficient pieces (key={}, written={}, required={})",
+            &obj.key, n_ok, obj.n_required
+        )))
+    }
+    /// Delete the pieces of a failed write so they don't remain as orphans.
+    /// The tombstone also rejects the pieces still in flight.
+    /// The holders unreachable now are retried by the rollback queue.
+    async fn rollback(&self, key: String, holders: HashSet<Uri>) {
+        let tombstone = Tombstone {
+            key: key.clone(),
+            deleted_at: unix_time(),
+        };
+        let mut futs = vec![];
+        for holder in holders {
+            let mut peer_out_cli = self.peer_out_cli.clone();
+            let tombstone = tombstone.clone();
+            futs.push(async move {
+                let fut = peer_out_cli.send_tombstone(holder.clone(), tombstone);
+                let rep = tokio::time::timeout(std::time::Duration::from_secs(5), fut).await;
+                (holder, matches!(rep, Ok(Ok(()))))
+            });
+        }
+        let n = futs.len();
+        let stream = futures::stream::iter(futs);
+        let mut buffered = stream.buffer_unordered(std::cmp::max(n, 1));
+        while let Some((holder, ok)) = buffered.next().await {
+            if !ok {
+                let task = rollback_queue::RollbackTask {
+                    to: holder,
+                    key: key.clone(),
+                    failed_at: tombstone.deleted_at,
+                };
+                self.rollback_queue_cli.clone().queue_task(task).await;
+            }
         }
-        Ok(())
     }
-    async fn read(&self, key: String) -> anyhow::Result<Bytes> {
+    async fn read_raw(&self, key: String) -> std::result::Result<(PieceHeader, Bytes), Error> {
         let peer_out_cli = self.peer_out_cli.clone();
         let cluster = self.state.cluster.read().await.clone();
         let rebuild = rebuild::Rebuild {
@@ -129,20 +1263,260 @@ impl IOFront for App {
             cluster: cluster.clone(),
             with_parity: false,
             fallback_broadcast: true,
+            read_repair: true,
+            hedge_delay: self.state.hedge_delay,
+            cpu_pool: self.state.cpu_pool.clone(),
+            priority: Priority::Foreground,
         };
-        let pieces = rebuild.rebuild(key.clone()).await?;
-        let mut merged = BytesMut::new();
-        for i in 0..K {
+        let (header, pieces) = rebuild.rebuild(key.clone()).await?;
+        if header.meta.is_expired(unix_time()) {
+            return Err(Error::NotFound(key));
+        }
+        let mut merged = BytesMut::with_capacity(pieces.iter().map(|x| x.len()).sum());
+        for i in 0..header.ec.k {
             let piece_data = &pieces[i];
             merged.extend_from_slice(piece_data);
         }
-        return Ok(merged.freeze());
+        // Strip the padding.
+        merged.truncate(header.len as usize);
+        let mut merged = merged.freeze();
+        if header.meta.compression != Compression::None {
+            let codec = header.meta.compression;
+            let size = header.meta.size as usize;
+            merged = self
+                .state
+                .cpu_pool
+                .run(Priority::Foreground, move || {
+                    codec.decompress(&merged, size)
+                })
+                .await
+                .map_err(|_| Error::DataLoss(key.clone()))?;
+        }
+        if header.kind == ObjectKind::Data && crc32c::crc32c(&merged) != header.meta.checksum {
+            return Err(Error::DataLoss(key));
+        }
+        Ok((header, merged))
+    }
+    /// Find the header from any piece.
+    /// This is much cheaper than reading the whole object.
+    /// The holders are asked at once and the first header found is taken.
+    /// A holder that doesn't answer in time is counted as failed.
+    async fn read_header(&self, key: String) -> std::result::Result<PieceHeader, Error> {
+        let cluster = self.state.cluster.read().await.clone();
+        let n = cluster.max_n();
+        let holders = cluster.compute_holders(key.clone(), n);
+        let mut n_failed = 0;
+        let mut futs = futures::stream::FuturesUnordered::new();
+        for (i, holder) in holders.into_iter().enumerate() {
+            let holder = match holder {
+                Some(holder) => holder,
+                None => {
+                    n_failed += 1;
+                    continue;
+                }
+            };
+            let loc = PieceLocator {
+                key: key.clone(),
+                index: i as u8,
+            };
+            let mut peer_out_cli = self.peer_out_cli.clone();
+            futs.push(async move { peer_out_cli.request_piece_header(holder, loc).await });
+        }
+        let n_asked = futs.len();
+        let mut n_answered = 0;
+        let find = async {
+            while let Some(rep) = futs.next().await {
+                n_answered += 1;
+                match rep {
+                    Ok(Some(header)) => return Some(header),
+                    Ok(None) => {}
+                    Err(_) => n_failed += 1,
+                }
+            }
+            None
+        };
+        let found = tokio::time::timeout(std::time::Duration::from_secs(5), find).await;
+        if let Ok(Some(header)) = found {
+            if header.meta.is_expired(unix_time()) {
+                return Err(Error::NotFound(key));
+            }
+            return Ok(header);
+        }
+        // The holders that didn't answer in time.
+        n_failed += n_asked - n_answered;
+        if n_failed > 0 {
+            return Err(Error::Unavailable(format!(
+                "couldn't ask all holders (key={})",
+                key
+            )));
+        }
+        Err(Error::NotFound(key))
     }
-    async fn sanity_check(&self, key: String) -> anyhow::Result<usize> {
+    /// Read a range of a data object.
+    /// Only the data pieces covering the range are fetched
+    /// unless some of them are missing or the object is compressed.
+    async fn read_data_range(
+        &self,
+        key: String,
+        header: PieceHeader,
+        offset: u64,
+        length: u64,
+    ) -> std::result::Result<Bytes, Error> {
+        // The offsets in the compressed data are unknown.
+        if header.meta.compression != Compression::None {
+            let (_, data) = self.read_raw(key).await?;
+            let len = data.len() as u64;
+            let start = std::cmp::min(offset, len) as usize;
+            let end = std::cmp::min(offset.saturating_add(length), len) as usize;
+            return Ok(data.slice(start..end));
+        }
+        let len = header.len as usize;
+        let start = std::cmp::min(offset, header.len) as usize;
+        let end = std::cmp::min(offset.saturating_add(length), header.len) as usize;
+        if start == end {
+            return Ok(Bytes::new());
+        }
+        let plen = piece::shard_len(len, header.ec.k);
+        let first = start / plen;
+        let last = (end - 1) / plen;
+
         let cluster = self.state.cluster.read().await.clone();
-        let holders = cluster.compute_holders(key.clone(), N);
+        let holders = cluster.compute_holders(key.clone(), header.ec.n);
         let mut futs = vec![];
-        for i in 0..N {
+        for i in first..=last {
+            let mut peer_out_cli = self.peer_out_cli.clone();
+            let uri = holders[i].clone();
+            let loc = PieceLocator {
+                key: key.clone(),
+                index: i as u8,
+            };
+            let fut = async move {
+                let uri = match uri {
+                    Some(uri) => uri,
+                    None => return None,
+                };
+                let fut = peer_out_cli.request_piece(uri, loc);
+                match tokio::time::timeout(std::time::Duration::from_secs(5), fut).await {
+                    Ok(Ok(Some(piece))) if piece.verify() => {
+                        let (_, shard) = piece::decode_bytes(&piece.data).ok()?;
+                        Some(shard)
+                    }
+                    _ => None,
+                }
+            };
+            futs.push(fut);
+        }
+        let found: Vec<Option<Bytes>> = futures::future::join_all(futs).await;
+
+        let shards: Vec<Bytes> = if found.iter().all(|x| x.is_some()) {
+            found.into_iter().map(|x| x.unwrap()).collect()
+        } else {
+            // Fallback
+            let rebuild = rebuild::Rebuild {
+                peer_out_cli: self.peer_out_cli.clone(),
+                cluster,
+                with_parity: false,
+                fallback_broadcast: true,
+                read_repair: true,
+                hedge_delay: self.state.hedge_delay,
+                cpu_pool: self.state.cpu_pool.clone(),
+                priority: Priority::Foreground,
+            };
+            let (_, pieces) = rebuild.rebuild(key).await?;
+            pieces[first..=last].to_vec()
+        };
+        let base = first * plen;
+        // A range within a shard is sliced without copying.
+        if shards.len() == 1 {
+            return Ok(shards[0].slice(start - base..end - base));
+        }
+        let mut merged = BytesMut::new();
+        for shard in &shards {
+            merged.extend_from_slice(shard);
+        }
+        let merged = merged.freeze();
+        Ok(merged.slice(start - base..end - base))
+    }
+    /// Returns the manifest if the object is a manifest.
+    /// Data objects are not read.
+    async fn find_manifest(&self, key: String) -> std::result::Result<Option<Manifest>, Error> {
+        let header = match self.read_header(key.clone()).await {
+            Ok(header) => header,
+            Err(_) => return Ok(None),
+        };
+        match header.kind {
+            ObjectKind::Data => Ok(None),
+            ObjectKind::Manifest => {
+                let (_, data) = self.read_raw(key.clone()).await?;
+                let manifest = Manifest::decode(&data).map_err(|_| Error::DataLoss(key))?;
+                Ok(Some(manifest))
+            }
+        }
+    }
+    async fn delete_key(&self, key: String) -> std::result::Result<(), Error> {
+        let cluster = self.state.cluster.read().await.clone();
+        // The tombstone is also sent to the holders under the current parameters
+        // of every storage class in case some pieces are being written.
+        let n = std::cmp::max(self.object_n(key.clone(), &cluster).await, cluster.max_n());
+        let holders = cluster.compute_holders(key.clone(), n);
+        // A node may hold more than one piece.
+        let mut dests = HashSet::new();
+        for holder in holders {
+            match holder {
+                Some(holder) => {
+                    dests.insert(holder);
+                }
+                None => {
+                    return Err(Error::Unavailable(format!(
+                        "failed to compute the holder node (key={})",
+                        &key
+                    )))
+                }
+            }
+        }
+        let tombstone = Tombstone {
+            key: key.clone(),
+            deleted_at: unix_time(),
+        };
+        let mut futs = vec![];
+        for holder in dests {
+            let mut peer_out_cli = self.peer_out_cli.clone();
+            let tombstone = tombstone.clone();
+            let fut = async move { peer_out_cli.send_tombstone(holder, tombstone).await };
+            let fut = tokio::time::timeout(std::time::Duration::from_secs(5), fut);
+            futs.push(fut);
+        }
+        let stream = futures::stream::iter(futs);
+        let mut buffered = stream.buffer_unordered(n);
+        while let Some(rep) = buffered.next().await {
+            // Every holder should have the tombstone otherwise the deleted pieces
+            // may come back. The client is expected to retry.
+            match rep {
+                Ok(Ok(())) => {}
+                _ => {
+                    return Err(Error::Unavailable(format!(
+                        "failed to delete all pieces (key={})",
+                        &key
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Number of pieces the object was split into.
+    /// If the object isn't found, the current parameter is used.
+    async fn object_n(&self, key: String, cluster: &ClusterMap) -> usize {
+        match self.read_header(key).await {
+            Ok(header) => header.ec.n,
+            Err(_) => cluster.ec().n,
+        }
+    }
+    async fn count_lost(&self, key: String) -> std::result::Result<usize, Error> {
+        let cluster = self.state.cluster.read().await.clone();
+        let n = self.object_n(key.clone(), &cluster).await;
+        let holders = cluster.compute_holders(key.clone(), n);
+        let mut futs = vec![];
+        for i in 0..n {
             let holder = &holders[i];
             match holder {
                 None => {}
@@ -164,7 +1538,7 @@ impl IOFront for App {
         }
         let n_should_found = futs.len();
         let stream = futures::stream::iter(futs);
-        let mut buffered = stream.buffer_unordered(N);
+        let mut buffered = stream.buffer_unordered(n);
         let mut n_found = 0;
         while let Some(rep) = buffered.next().await {
             if rep.is_err() {
@@ -182,7 +1556,103 @@ impl IOFront for App {
         let n_lost = n_should_found - n_found;
         Ok(n_lost)
     }
-    async fn set_new_cluster(&self, cluster: ClusterMap) {
-        *self.state.cluster.write().await = cluster;
+}
+
+#[test]
+fn test_merge_data_pieces() {
+    let ec = EcParams { k: 3, n: 5 };
+    let value = vec![7; 100];
+    let meta = ObjectMeta {
+        size: value.len() as u64,
+        created_at: 0,
+        checksum: crc32c::crc32c(&value),
+        cluster_version: 0,
+        user: BTreeMap::new(),
+        version_id: String::new(),
+        expires_at: None,
+        storage_class: String::new(),
+        compression: Compression::None,
+    };
+    let pieces = encode_pieces(&value, ec, ObjectKind::Data, meta);
+    assert_eq!(pieces.len(), 5);
+    let data_pieces: Vec<Option<Piece>> = pieces[..3].iter().cloned().map(Some).collect();
+    assert_eq!(
+        merge_data_pieces(data_pieces.clone(), ec),
+        Some(Bytes::from(value))
+    );
+
+    // Missing piece
+    let mut missing = data_pieces.clone();
+    missing[1] = None;
+    assert_eq!(merge_data_pieces(missing, ec), None);
+    // Written with other parameters
+    assert_eq!(
+        merge_data_pieces(data_pieces, EcParams { k: 3, n: 6 }),
+        None
+    );
+}
+
+#[test]
+fn test_encode_replicas() {
+    use reed_solomon_erasure::galois_8::ReedSolomon;
+
+    let ec = EcParams { k: 1, n: 3 };
+    let value = vec![7; 100];
+    let meta = ObjectMeta {
+        size: value.len() as u64,
+        created_at: 0,
+        checksum: crc32c::crc32c(&value),
+        cluster_version: 0,
+        user: BTreeMap::new(),
+        version_id: String::new(),
+        expires_at: None,
+        storage_class: "replica3".to_string(),
+        compression: Compression::None,
+    };
+    let pieces = encode_pieces(&value, ec, ObjectKind::Data, meta);
+    assert_eq!(pieces.len(), 3);
+    assert_eq!(pieces[0], pieces[1]);
+    assert_eq!(pieces[0], pieces[2]);
+    for piece in &pieces {
+        assert_eq!(
+            merge_data_pieces(vec![Some(piece.clone())], ec),
+            Some(Bytes::from(value.clone()))
+        );
     }
+
+    // The copies are what Reed-Solomon makes with k = 1.
+    let r = ReedSolomon::new(1, 2).unwrap();
+    let mut parity = vec![vec![0; 100]; 2];
+    r.encode_sep(&[&value[..]], &mut parity).unwrap();
+    assert_eq!(parity[0], value);
+    assert_eq!(parity[1], value);
+}
+
+#[test]
+fn test_merge_compressed_pieces() {
+    let ec = EcParams { k: 3, n: 5 };
+    let value = Bytes::from(vec![7; 1000]);
+    let meta = ObjectMeta {
+        size: value.len() as u64,
+        created_at: 0,
+        checksum: crc32c::crc32c(&value),
+        cluster_version: 0,
+        user: BTreeMap::new(),
+        version_id: String::new(),
+        expires_at: None,
+        storage_class: String::new(),
+        compression: Compression::Zstd,
+    };
+    let metrics = compression::Metrics::default();
+    let pieces = compress_and_encode(value.clone(), ec, ObjectKind::Data, meta, &metrics);
+    let (header, _) = piece::decode(&pieces[0].data).unwrap();
+    assert_eq!(header.meta.compression, Compression::Zstd);
+    assert!(header.len < value.len() as u64);
+    let stats = metrics.stats();
+    assert_eq!(stats.compressed, 1);
+    assert_eq!(stats.original_bytes, 1000);
+    assert_eq!(stats.stored_bytes, header.len);
+
+    let data_pieces: Vec<Option<Piece>> = pieces[..3].iter().cloned().map(Some).collect();
+    assert_eq!(merge_data_pieces(data_pieces, ec), Some(value));
 }
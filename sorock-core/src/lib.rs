@@ -3,6 +3,7 @@
 use anyhow::Result;
 use bytes::Bytes;
 use lol_core::Uri;
+use std::collections::BTreeMap;
 
 #[macro_export]
 macro_rules! define_client {
@@ -15,14 +16,25 @@ macro_rules! define_client {
 
 pub mod cluster_in;
 mod cluster_map;
+pub mod compression;
+pub mod cpu_pool;
 pub mod io_front;
+pub mod manifest;
+pub mod multipart;
+pub mod multipart_gc;
 pub mod peer_in;
 pub mod peer_out;
+mod piece;
 pub mod piece_store;
+pub mod reaper;
 pub mod rebuild_queue;
+pub mod rollback_queue;
 pub mod stabilizer;
 pub mod storage_service;
+pub mod tombstone_gc;
+pub mod versioning;
 use cluster_map::ClusterMap;
+use compression::Compression;
 mod rebuild;
 
 pub mod raft_service;
@@ -33,15 +45,95 @@ pub mod proto_compiled {
     tonic::include_proto!("sorock");
 }
 
-/// Number of data chunks
+/// Default number of data chunks
 pub const K: usize = 4;
-/// Number of data + parity chunks
+/// Default number of data + parity chunks
 pub const N: usize = 8;
 
+/// Erasure-coding parameters of the cluster.
+/// A value is split into `k` data chunks and `n - k` parity chunks are added.
+/// The parameters are recorded in every piece so objects written
+/// under different parameters can be read and rebuilt.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EcParams {
+    pub k: usize,
+    pub n: usize,
+}
+impl Default for EcParams {
+    fn default() -> Self {
+        Self { k: K, n: N }
+    }
+}
+impl EcParams {
+    /// Check if the parameters can be used in a cluster of `n_members` nodes.
+    pub fn validate(&self, n_members: usize) -> anyhow::Result<()> {
+        anyhow::ensure!(self.k >= 1, "k should be at least 1");
+        anyhow::ensure!(self.k < self.n, "n should be greater than k");
+        anyhow::ensure!(
+            self.n <= u8::MAX as usize,
+            "n should be at most {}",
+            u8::MAX
+        );
+        anyhow::ensure!(n_members > 0, "the cluster has no member");
+        // A node holds more than one piece if the cluster is small.
+        // Losing any one node shouldn't lose the data.
+        let max_pieces_per_node = (self.n + n_members - 1) / n_members;
+        anyhow::ensure!(
+            max_pieces_per_node <= self.n - self.k,
+            "{} members are too few for k={} n={}",
+            n_members,
+            self.k,
+            self.n
+        );
+        Ok(())
+    }
+}
+
+/// Number of pieces a create waits to be written before it succeeds.
+/// With `K`, a successful create may have no redundancy until the lost pieces are rebuilt.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Durability {
+    K,
+    KPlusOne,
+    All,
+}
+impl Default for Durability {
+    fn default() -> Self {
+        Self::K
+    }
+}
+impl Durability {
+    pub fn required(&self, ec: EcParams) -> usize {
+        match self {
+            Self::K => ec.k,
+            Self::KPlusOne => ec.k + 1,
+            Self::All => ec.n,
+        }
+    }
+}
+
+/// Check the name of a storage class.
+/// The empty name is reserved for the default of the cluster.
+pub fn validate_storage_class_name(name: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !name.is_empty(),
+        "the name of a storage class should not be empty"
+    );
+    anyhow::ensure!(
+        name.len() <= 64,
+        "the name of a storage class should be at most 64 bytes"
+    );
+    Ok(())
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 enum Command {
     AddNode { uri: URI, cap: f64 },
     RemoveNode { uri: URI },
+    SetEcParams { ec: EcParams },
+    SetDurability { durability: Durability },
+    SetStorageClass { name: String, ec: EcParams },
+    DeleteStorageClass { name: String },
 }
 impl Command {
     fn encode(&self) -> Vec<u8> {
@@ -52,24 +144,143 @@ impl Command {
     }
 }
 
+/// Metadata of an object. It is stored in every piece.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct ObjectMeta {
+    /// Size of the object. For a manifest object, the size of the stitched object.
+    pub size: u64,
+    /// Unix time in seconds.
+    pub created_at: u64,
+    /// CRC32C of the content.
+    pub checksum: u32,
+    /// Version of the cluster when the object was written.
+    pub cluster_version: u64,
+    /// User-supplied key-value pairs.
+    pub user: BTreeMap<String, String>,
+    /// Version of the object. Empty for the internal objects.
+    pub version_id: String,
+    /// Unix time in seconds after which the object is treated as missing.
+    pub expires_at: Option<u64>,
+    /// Storage class the object was written with. Empty for the default of the cluster.
+    pub storage_class: String,
+    /// Codec the content is stored with. The size and the checksum are of the original content.
+    pub compression: Compression,
+}
+impl ObjectMeta {
+    pub fn is_expired(&self, now: u64) -> bool {
+        match self.expires_at {
+            Some(expires_at) => expires_at <= now,
+            None => false,
+        }
+    }
+}
+
 #[derive(Clone, Hash, Debug, PartialEq, Eq)]
 pub struct PieceLocator {
     pub key: String,
     pub index: u8,
 }
 
+/// Piece data with its checksum.
+/// The checksum is computed when the piece is made
+/// and travels with the data so corruption can be detected anywhere.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Piece {
+    pub data: Bytes,
+    /// CRC32C of the data.
+    pub checksum: u32,
+}
+impl Piece {
+    pub fn new(data: Bytes) -> Self {
+        let checksum = crc32c::crc32c(&data);
+        Self { data, checksum }
+    }
+    pub fn verify(&self) -> bool {
+        crc32c::crc32c(&self.data) == self.checksum
+    }
+}
+
 pub struct SendPiece {
     pub version: u64,
     pub loc: PieceLocator,
-    pub data: Option<Bytes>,
+    pub data: Option<Piece>,
 }
-#[derive(thiserror::Error, Debug)]
+#[derive(thiserror::Error, Debug, Clone, Copy)]
 pub enum SendPieceError {
     #[error("send-piece with older version was rejected.")]
     Rejected,
     #[error("failed any way")]
     Failed,
+    #[error("the key was deleted.")]
+    Deleted,
+}
+
+/// Error in reading or writing objects.
+/// Each variant is mapped to a gRPC status code so the client can tell
+/// if the request should be retried.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("not found (key={0})")]
+    NotFound(String),
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    /// Some nodes didn't respond. Retrying later may succeed.
+    #[error("unavailable: {0}")]
+    Unavailable(String),
+    /// The object is found but it can't be restored.
+    #[error("data is lost (key={0})")]
+    DataLoss(String),
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+impl Error {
+    /// A missing part of an existing object means the object is lost.
+    pub fn into_part_error(self) -> Self {
+        match self {
+            Error::NotFound(key) => Error::DataLoss(key),
+            e => e,
+        }
+    }
+}
+
+/// A record left behind by Delete so that pieces held by
+/// lagging nodes will not be resurrected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tombstone {
+    pub key: String,
+    /// Unix time in seconds when the key was deleted.
+    pub deleted_at: u64,
+}
+
+fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Clone, Hash, PartialEq, Eq)]
 struct URI(#[serde(with = "http_serde::uri")] tonic::transport::Uri);
+
+#[test]
+fn test_piece_verify() {
+    let mut piece = Piece::new(Bytes::from(vec![1, 2, 3]));
+    assert!(piece.verify());
+    piece.data = Bytes::from(vec![1, 2, 4]);
+    assert!(!piece.verify());
+}
+
+#[test]
+fn test_ec_params_validate() {
+    let ec = EcParams::default();
+    assert!(ec.validate(8).is_ok());
+    assert!(ec.validate(3).is_ok());
+    assert!(ec.validate(1).is_err());
+    assert!(ec.validate(0).is_err());
+
+    assert!(EcParams { k: 2, n: 3 }.validate(3).is_ok());
+    assert!(EcParams { k: 2, n: 3 }.validate(2).is_err());
+    assert!(EcParams { k: 3, n: 3 }.validate(3).is_err());
+    assert!(EcParams { k: 0, n: 3 }.validate(3).is_err());
+    assert!(EcParams { k: 1, n: 256 }.validate(256).is_err());
+}
@@ -0,0 +1,88 @@
+use crate::*;
+
+/// Size of a stripe in streaming create.
+/// Each stripe is erasure-coded and placed as an independent object.
+pub const STRIPE_SIZE: usize = 4 << 20;
+
+/// Keys containing this character are reserved for the internal objects.
+pub const INTERNAL_SEP: char = '\u{0}';
+
+/// The stream id makes the stripes of different writes to the key distinct.
+pub fn stripe_key(key: &str, stream_id: &str, i: usize) -> String {
+    format!(
+        "{}{}stripe{}{}{}{}",
+        key, INTERNAL_SEP, INTERNAL_SEP, stream_id, INTERNAL_SEP, i
+    )
+}
+
+pub fn is_internal_key(key: &str) -> bool {
+    key.contains(INTERNAL_SEP)
+}
+
+/// A stripe key is never written again because the stream id is unique.
+pub fn is_stripe_key(key: &str) -> bool {
+    key.contains(&format!("{}stripe{}", INTERNAL_SEP, INTERNAL_SEP))
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Part {
+    pub key: String,
+    pub len: u64,
+    /// CRC32C of the part.
+    pub checksum: u32,
+}
+
+/// A manifest object ties the parts together.
+/// Reading the manifest object is the same as reading the parts in order.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    pub parts: Vec<Part>,
+}
+impl Manifest {
+    pub fn encode(&self) -> Bytes {
+        bincode::serialize(self).unwrap().into()
+    }
+    pub fn decode(b: &[u8]) -> anyhow::Result<Self> {
+        Ok(bincode::deserialize(b)?)
+    }
+    /// Length of the stitched object.
+    pub fn size(&self) -> u64 {
+        self.parts.iter().map(|part| part.len).sum()
+    }
+    /// CRC32C of the stitched object.
+    pub fn checksum(&self) -> u32 {
+        let mut crc = crc32c::crc32c(&[]);
+        for part in &self.parts {
+            crc = crc32c::crc32c_combine(crc, part.checksum, part.len as usize);
+        }
+        crc
+    }
+}
+
+#[test]
+fn test_manifest_encode_decode() {
+    let manifest = Manifest {
+        parts: vec![
+            Part {
+                key: stripe_key("a", "s", 0),
+                len: 10,
+                checksum: crc32c::crc32c(&[1; 10]),
+            },
+            Part {
+                key: stripe_key("a", "s", 1),
+                len: 5,
+                checksum: crc32c::crc32c(&[2; 5]),
+            },
+        ],
+    };
+    let decoded = Manifest::decode(&manifest.encode()).unwrap();
+    assert_eq!(decoded, manifest);
+    assert_eq!(decoded.size(), 15);
+    let mut whole = vec![1; 10];
+    whole.extend_from_slice(&[2; 5]);
+    assert_eq!(decoded.checksum(), crc32c::crc32c(&whole));
+    assert!(is_internal_key(&decoded.parts[0].key));
+    assert!(!is_internal_key("a"));
+    assert!(is_stripe_key(&decoded.parts[0].key));
+    assert!(!is_stripe_key("a"));
+}
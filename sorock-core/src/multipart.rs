@@ -0,0 +1,63 @@
+use crate::*;
+use manifest::INTERNAL_SEP;
+
+/// Part numbers are in 1..=MAX_PART_NUMBER.
+pub const MAX_PART_NUMBER: u32 = 10000;
+
+pub fn new_upload_id() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+/// Key of the upload record.
+/// The record is an empty object which carries the user metadata
+/// and the time the upload was initiated.
+pub fn upload_key(key: &str, upload_id: &str) -> String {
+    format!("{}{}upload{}{}", key, INTERNAL_SEP, INTERNAL_SEP, upload_id)
+}
+
+/// Prefix of the keys of the uploaded parts.
+pub fn parts_prefix(key: &str, upload_id: &str) -> String {
+    format!(
+        "{}{}part{}",
+        upload_key(key, upload_id),
+        INTERNAL_SEP,
+        INTERNAL_SEP
+    )
+}
+
+/// The part number is zero-padded so the parts are listed in order.
+pub fn part_key(key: &str, upload_id: &str, part_number: u32) -> String {
+    format!("{}{:05}", parts_prefix(key, upload_id), part_number)
+}
+
+/// Returns the key and the upload id if it is the key of an upload record.
+pub fn parse_upload_key(k: &str) -> Option<(String, String)> {
+    let mut iter = k.split(INTERNAL_SEP);
+    let key = iter.next()?;
+    if iter.next()? != "upload" {
+        return None;
+    }
+    let upload_id = iter.next()?;
+    if iter.next().is_some() {
+        return None;
+    }
+    Some((key.to_string(), upload_id.to_string()))
+}
+
+#[test]
+fn test_multipart_keys() {
+    let id = new_upload_id();
+    assert_eq!(id.len(), 32);
+    assert!(!manifest::is_internal_key(&id));
+
+    let k = upload_key("a", &id);
+    assert!(manifest::is_internal_key(&k));
+    assert_eq!(parse_upload_key(&k), Some(("a".to_string(), id.clone())));
+
+    let p = part_key("a", &id, 2);
+    assert!(p.starts_with(&parts_prefix("a", &id)));
+    assert!(part_key("a", &id, 10) > p);
+    assert_eq!(parse_upload_key(&p), None);
+    assert_eq!(parse_upload_key("a"), None);
+    assert_eq!(parse_upload_key(&manifest::stripe_key("a", &id, 0)), None);
+}
@@ -0,0 +1,112 @@
+use crate::*;
+
+use std::time::Duration;
+
+/// Number of keys listed at once from the piece store.
+const LIST_LIMIT: usize = 1000;
+
+#[norpc::service]
+trait MultipartGc {
+    fn run_once() -> anyhow::Result<()>;
+}
+define_client!(MultipartGc);
+
+pub fn spawn(
+    piece_store_cli: piece_store::ClientT,
+    io_front_cli: io_front::ClientT,
+    state: State,
+) -> ClientT {
+    use norpc::runtime::tokio::*;
+    let svc = App {
+        piece_store_cli,
+        io_front_cli,
+        state,
+    };
+    let svc = MultipartGcService::new(svc);
+    let (chan, server) = ServerBuilder::new(svc).build();
+    tokio::spawn(server.serve());
+    MultipartGcClient::new(chan)
+}
+
+pub fn spawn_tick(mut multipart_gc_cli: ClientT, interval: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            multipart_gc_cli.run_once().await.ok();
+        }
+    });
+}
+
+pub struct State {
+    /// Uploads not completed in this period are aborted.
+    expiry: Duration,
+}
+impl State {
+    pub fn new(expiry: Duration) -> Self {
+        Self { expiry }
+    }
+}
+
+struct App {
+    piece_store_cli: piece_store::ClientT,
+    io_front_cli: io_front::ClientT,
+    state: State,
+}
+#[norpc::async_trait]
+impl MultipartGc for App {
+    /// Abort the expired uploads.
+    /// Only the node holding the first piece of the upload record does it
+    /// so the uploads are not aborted by every holder.
+    /// The upload records are found by listing the local keys page by page.
+    async fn run_once(&self) -> anyhow::Result<()> {
+        let mut piece_store_cli = self.piece_store_cli.clone();
+        let now = unix_time();
+        let n_par = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            * 2;
+        let mut start_after = None;
+        loop {
+            let page = piece_store_cli
+                .list_keys(String::new(), start_after, LIST_LIMIT)
+                .await?;
+            let n = page.len();
+            start_after = page.last().cloned();
+
+            let mut futs = vec![];
+            for k in page {
+                let (key, upload_id) = match multipart::parse_upload_key(&k) {
+                    Some(x) => x,
+                    None => continue,
+                };
+                let loc = PieceLocator {
+                    key: k.clone(),
+                    index: 0,
+                };
+                if !piece_store_cli.piece_exists(loc).await? {
+                    continue;
+                }
+                let mut io_front_cli = self.io_front_cli.clone();
+                let expiry = self.state.expiry.as_secs();
+                let fut = async move {
+                    let meta = io_front_cli.head(k, None).await?;
+                    if now.saturating_sub(meta.created_at) >= expiry {
+                        io_front_cli.abort_multipart(key, upload_id).await?;
+                    }
+                    Ok::<(), Error>(())
+                };
+                futs.push(fut);
+            }
+            let stream = futures::stream::iter(futs);
+            let mut buffered = stream.buffer_unordered(n_par);
+            while buffered.next().await.is_some() {}
+
+            if n < LIST_LIMIT {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,15 +1,33 @@
 use crate::*;
+use piece::PieceHeader;
 use stabilizer::StabilizeTask;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+/// Number of keys listed at once from the piece store.
+const LIST_LIMIT: usize = 1000;
+
 #[norpc::service]
 trait PeerIn {
     fn set_new_cluster(cluster: ClusterMap);
-    fn piece_exists(loc: PieceLocator) -> anyhow::Result<bool>;
+    fn piece_exists(loc: PieceLocator) -> std::result::Result<bool, Error>;
     fn save_piece(piece: SendPiece) -> std::result::Result<(), SendPieceError>;
-    fn find_piece(loc: PieceLocator) -> anyhow::Result<Option<Vec<u8>>>;
-    fn find_any_pieces(key: String) -> anyhow::Result<Vec<(u8, Vec<u8>)>>;
+    fn find_piece(loc: PieceLocator) -> std::result::Result<Option<Piece>, Error>;
+    fn find_piece_header(loc: PieceLocator) -> std::result::Result<Option<PieceHeader>, Error>;
+    fn find_any_pieces(key: String) -> std::result::Result<Vec<(u8, Piece)>, Error>;
+    fn save_tombstone(tombstone: Tombstone) -> std::result::Result<(), Error>;
+    fn key_exists(key: String) -> std::result::Result<bool, Error>;
+    fn find_keys(
+        prefix: String,
+        start_after: Option<String>,
+        limit: usize,
+    ) -> std::result::Result<Vec<String>, Error>;
+    // The versions of each key held in this node with the number of the pieces
+    // and the parameters they were coded with.
+    // The expired versions aren't included.
+    fn find_versions(
+        keys: Vec<String>,
+    ) -> std::result::Result<Vec<Vec<(String, usize, EcParams)>>, Error>;
 }
 define_client!(PeerIn);
 
@@ -53,8 +71,8 @@ impl PeerIn for App {
     async fn set_new_cluster(&self, cluster: ClusterMap) {
         *self.state.cluster.write().await = cluster;
     }
-    async fn piece_exists(&self, loc: PieceLocator) -> anyhow::Result<bool> {
-        self.piece_store_cli.clone().piece_exists(loc).await
+    async fn piece_exists(&self, loc: PieceLocator) -> std::result::Result<bool, Error> {
+        Ok(self.piece_store_cli.clone().piece_exists(loc).await?)
     }
     async fn save_piece(&self, send_piece: SendPiece) -> std::result::Result<(), SendPieceError> {
         let cluster = self.state.cluster.read().await;
@@ -63,11 +81,25 @@ impl PeerIn for App {
             return Err(SendPieceError::Rejected);
         }
         let loc = send_piece.loc;
+        // Pieces of a deleted key must not be resurrected.
+        let tombstone = self
+            .piece_store_cli
+            .clone()
+            .get_tombstone(loc.key.clone())
+            .await
+            .map_err(|_| SendPieceError::Failed)?;
+        if tombstone.is_some() {
+            return Err(SendPieceError::Deleted);
+        }
         match send_piece.data {
-            Some(data) => {
+            Some(piece) => {
+                // Corrupted in transit. The sender will retry.
+                if !piece.verify() {
+                    return Err(SendPieceError::Failed);
+                }
                 self.piece_store_cli
                     .clone()
-                    .put_piece(loc.clone(), data)
+                    .put_piece(loc.clone(), piece)
                     .await
                     .map_err(|_| SendPieceError::Failed)?;
                 self.stabilizer_cli
@@ -84,16 +116,130 @@ impl PeerIn for App {
             }
         }
     }
-    async fn find_piece(&self, loc: PieceLocator) -> anyhow::Result<Option<Vec<u8>>> {
-        let piece = self.piece_store_cli.clone().get_piece(loc).await?;
-        Ok(piece)
+    async fn find_piece(&self, loc: PieceLocator) -> std::result::Result<Option<Piece>, Error> {
+        let piece = match self.piece_store_cli.clone().get_piece(loc.clone()).await {
+            Err(e) if piece_store::is_corrupted(&e) => {
+                self.drop_corrupted(loc).await?;
+                return Ok(None);
+            }
+            piece => piece?,
+        };
+        match piece {
+            Some(piece) if !piece.verify() => {
+                self.drop_corrupted(loc).await?;
+                Ok(None)
+            }
+            piece => Ok(piece),
+        }
+    }
+    async fn find_piece_header(
+        &self,
+        loc: PieceLocator,
+    ) -> std::result::Result<Option<PieceHeader>, Error> {
+        let part = self.piece_store_cli.clone().get_piece_header(loc).await?;
+        match part {
+            Some(part) => Ok(Some(piece::decode_header_part(&part)?)),
+            None => Ok(None),
+        }
+    }
+    async fn find_any_pieces(&self, key: String) -> std::result::Result<Vec<(u8, Piece)>, Error> {
+        let pieces = self.piece_store_cli.clone().get_pieces(key.clone()).await?;
+        let mut out = vec![];
+        for (index, piece) in pieces {
+            if piece.verify() {
+                out.push((index, piece));
+            } else {
+                let loc = PieceLocator {
+                    key: key.clone(),
+                    index,
+                };
+                self.drop_corrupted(loc).await?;
+            }
+        }
+        Ok(out)
+    }
+    async fn save_tombstone(&self, tombstone: Tombstone) -> std::result::Result<(), Error> {
+        let mut piece_store_cli = self.piece_store_cli.clone();
+        let key = tombstone.key.clone();
+        piece_store_cli.put_tombstone(tombstone).await?;
+        piece_store_cli.delete_pieces(key).await?;
+        Ok(())
+    }
+    async fn key_exists(&self, key: String) -> std::result::Result<bool, Error> {
+        Ok(self.piece_store_cli.clone().key_exists(key).await?)
     }
-    async fn find_any_pieces(&self, key: String) -> anyhow::Result<Vec<(u8, Vec<u8>)>> {
-        let pieces = self
+    async fn find_keys(
+        &self,
+        prefix: String,
+        start_after: Option<String>,
+        limit: usize,
+    ) -> std::result::Result<Vec<String>, Error> {
+        let keys = self
             .piece_store_cli
             .clone()
-            .get_pieces(key, N as u8)
+            .list_keys(prefix, start_after, limit)
+            .await?;
+        Ok(keys)
+    }
+    async fn find_versions(
+        &self,
+        keys: Vec<String>,
+    ) -> std::result::Result<Vec<Vec<(String, usize, EcParams)>>, Error> {
+        let mut piece_store_cli = self.piece_store_cli.clone();
+        let now = unix_time();
+        let mut out = vec![];
+        for key in keys {
+            let prefix = versioning::version_prefix(&key);
+            let mut versions = vec![];
+            let mut start_after = None;
+            loop {
+                let page = piece_store_cli
+                    .list_keys(prefix.clone(), start_after, LIST_LIMIT)
+                    .await?;
+                let n = page.len();
+                start_after = page.last().cloned();
+                for k in page {
+                    if let Some((_, version_id)) = versioning::parse_version_key(&k) {
+                        let n_pieces = piece_store_cli.count_pieces(k.clone()).await?;
+                        // Only the headers are read. A header corrupted so badly
+                        // that it can't be decoded is skipped.
+                        let headers: Vec<PieceHeader> = piece_store_cli
+                            .get_piece_headers(k)
+                            .await?
+                            .into_iter()
+                            .filter_map(|(_, part)| piece::decode_header_part(&part).ok())
+                            .collect();
+                        // An expired version is hidden as if it was deleted
+                        // so the older version is read before and after the reaper runs.
+                        if headers.iter().any(|header| header.meta.is_expired(now)) {
+                            continue;
+                        }
+                        if let Some(header) = headers.first() {
+                            versions.push((version_id, n_pieces, header.ec));
+                        }
+                    }
+                }
+                if n < LIST_LIMIT {
+                    break;
+                }
+            }
+            out.push(versions);
+        }
+        Ok(out)
+    }
+}
+
+impl App {
+    /// A corrupted piece is treated as missing.
+    /// It is deleted first because the rebuild skips existing pieces.
+    async fn drop_corrupted(&self, loc: PieceLocator) -> std::result::Result<(), Error> {
+        eprintln!("corrupted piece: {:?}", &loc);
+        self.piece_store_cli
+            .clone()
+            .delete_piece(loc.clone())
             .await?;
-        Ok(pieces)
+        let task = rebuild_queue::RebuildTask { loc };
+        self.rebuild_queue_cli.clone().queue_task(task).await;
+        Ok(())
     }
 }
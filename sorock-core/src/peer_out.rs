@@ -2,9 +2,12 @@ use crate::*;
 use tonic::transport::Channel;
 
 use lol_core::Uri;
+use piece::PieceHeader;
 use proto_compiled::sorock_client::SorockClient;
 use proto_compiled::{
-    IndexedPiece, PieceExistsReq, RequestAnyPiecesReq, RequestPieceReq, SendPieceReq,
+    IndexedPiece, KeyExistsReq, PieceExistsReq, RequestAnyPiecesReq, RequestKeysReq,
+    RequestPieceRep, RequestPieceReq, RequestPiecesReq, RequestVersionsReq, SendPieceReq,
+    SendPiecesReq, SendTombstoneReq,
 };
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -13,9 +16,28 @@ use tokio::sync::RwLock;
 #[norpc::service]
 trait PeerOut {
     fn send_piece(to: Uri, piece: SendPiece) -> std::result::Result<(), SendPieceError>;
-    fn request_piece(to: Uri, loc: PieceLocator) -> anyhow::Result<Option<Vec<u8>>>;
-    fn request_any_pieces(to: Uri, key: String) -> anyhow::Result<Vec<(u8, Vec<u8>)>>;
+    // Send the pieces in one RPC. The results are in the order of the pieces.
+    fn send_pieces(to: Uri, pieces: Vec<SendPiece>)
+        -> Vec<std::result::Result<(), SendPieceError>>;
+    fn request_piece(to: Uri, loc: PieceLocator) -> anyhow::Result<Option<Piece>>;
+    // Request the pieces in one RPC. The results are in the order of the locators.
+    fn request_pieces(to: Uri, locs: Vec<PieceLocator>) -> anyhow::Result<Vec<Option<Piece>>>;
+    fn request_piece_header(to: Uri, loc: PieceLocator) -> anyhow::Result<Option<PieceHeader>>;
+    fn request_any_pieces(to: Uri, key: String) -> anyhow::Result<Vec<(u8, Piece)>>;
     fn piece_exists(to: Uri, loc: PieceLocator) -> anyhow::Result<bool>;
+    fn send_tombstone(to: Uri, tombstone: Tombstone) -> anyhow::Result<()>;
+    fn key_exists(to: Uri, key: String) -> anyhow::Result<bool>;
+    fn request_keys(
+        to: Uri,
+        prefix: String,
+        start_after: Option<String>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<String>>;
+    // The results are in the order of the keys.
+    fn request_versions(
+        to: Uri,
+        keys: Vec<String>,
+    ) -> anyhow::Result<Vec<Vec<(String, usize, EcParams)>>>;
 }
 define_client!(PeerOut);
 
@@ -54,6 +76,35 @@ struct App {
     state: State,
 }
 
+fn send_piece_req(piece: SendPiece) -> SendPieceReq {
+    let (data, checksum) = match piece.data {
+        Some(Piece { data, checksum }) => (Some(data), checksum),
+        None => (None, 0),
+    };
+    SendPieceReq {
+        data,
+        key: piece.loc.key,
+        index: piece.loc.index as u32,
+        version: piece.version,
+        checksum,
+    }
+}
+
+fn send_piece_result(error_code: i32) -> std::result::Result<(), SendPieceError> {
+    match error_code {
+        0 => Ok(()),
+        -1 => Err(SendPieceError::Rejected),
+        -2 => Err(SendPieceError::Failed),
+        -3 => Err(SendPieceError::Deleted),
+        _ => unreachable!(),
+    }
+}
+
+fn request_piece_rep(rep: RequestPieceRep) -> Option<Piece> {
+    let checksum = rep.checksum;
+    rep.data.map(|data| Piece { data, checksum })
+}
+
 #[norpc::async_trait]
 impl PeerOut for App {
     async fn send_piece(
@@ -64,20 +115,32 @@ impl PeerOut for App {
         let chan = self.state.connect(to).await;
         let mut cli = SorockClient::new(chan);
         let rep = cli
-            .send_piece(SendPieceReq {
-                data: piece.data,
-                key: piece.loc.key,
-                index: piece.loc.index as u32,
-                version: piece.version,
-            })
+            .send_piece(send_piece_req(piece))
             .await
             .map_err(|_| SendPieceError::Failed)?;
         let rep = rep.into_inner();
-        match rep.error_code {
-            0 => Ok(()),
-            -1 => Err(SendPieceError::Rejected),
-            -2 => Err(SendPieceError::Failed),
-            _ => unreachable!(),
+        send_piece_result(rep.error_code)
+    }
+    async fn send_pieces(
+        &self,
+        to: Uri,
+        pieces: Vec<SendPiece>,
+    ) -> Vec<std::result::Result<(), SendPieceError>> {
+        let n = pieces.len();
+        let chan = self.state.connect(to).await;
+        let mut cli = SorockClient::new(chan);
+        let req = SendPiecesReq {
+            pieces: pieces.into_iter().map(send_piece_req).collect(),
+        };
+        match cli.send_pieces(req).await {
+            Ok(rep) => {
+                let rep = rep.into_inner();
+                if rep.error_codes.len() != n {
+                    return vec![Err(SendPieceError::Failed); n];
+                }
+                rep.error_codes.into_iter().map(send_piece_result).collect()
+            }
+            Err(_) => vec![Err(SendPieceError::Failed); n],
         }
     }
     async fn piece_exists(&self, to: Uri, loc: PieceLocator) -> anyhow::Result<bool> {
@@ -92,7 +155,7 @@ impl PeerOut for App {
         let rep = rep.into_inner();
         Ok(rep.exists)
     }
-    async fn request_piece(&self, to: Uri, loc: PieceLocator) -> anyhow::Result<Option<Vec<u8>>> {
+    async fn request_piece(&self, to: Uri, loc: PieceLocator) -> anyhow::Result<Option<Piece>> {
         let chan = self.state.connect(to).await;
         let mut cli = SorockClient::new(chan);
         let rep = cli
@@ -102,16 +165,127 @@ impl PeerOut for App {
             })
             .await?;
         let rep = rep.into_inner();
-        Ok(rep.data)
+        Ok(request_piece_rep(rep))
+    }
+    async fn request_pieces(
+        &self,
+        to: Uri,
+        locs: Vec<PieceLocator>,
+    ) -> anyhow::Result<Vec<Option<Piece>>> {
+        let n = locs.len();
+        let chan = self.state.connect(to).await;
+        let mut cli = SorockClient::new(chan);
+        let req = RequestPiecesReq {
+            locs: locs
+                .into_iter()
+                .map(|loc| RequestPieceReq {
+                    key: loc.key,
+                    index: loc.index as u32,
+                })
+                .collect(),
+        };
+        let rep = cli.request_pieces(req).await?;
+        let rep = rep.into_inner();
+        anyhow::ensure!(rep.pieces.len() == n);
+        Ok(rep.pieces.into_iter().map(request_piece_rep).collect())
+    }
+    async fn request_piece_header(
+        &self,
+        to: Uri,
+        loc: PieceLocator,
+    ) -> anyhow::Result<Option<PieceHeader>> {
+        let chan = self.state.connect(to).await;
+        let mut cli = SorockClient::new(chan);
+        let rep = cli
+            .request_piece_header(RequestPieceReq {
+                key: loc.key,
+                index: loc.index as u32,
+            })
+            .await?;
+        let rep = rep.into_inner();
+        match rep.header {
+            Some(header) => Ok(Some(piece::decode_header(&header)?)),
+            None => Ok(None),
+        }
     }
-    async fn request_any_pieces(&self, to: Uri, key: String) -> anyhow::Result<Vec<(u8, Vec<u8>)>> {
+    async fn request_any_pieces(&self, to: Uri, key: String) -> anyhow::Result<Vec<(u8, Piece)>> {
         let chan = self.state.connect(to).await;
         let mut cli = SorockClient::new(chan);
         let rep = cli.request_any_pieces(RequestAnyPiecesReq { key }).await?;
         let rep = rep.into_inner();
         let mut out = vec![];
-        for IndexedPiece { index, data } in rep.pieces {
-            out.push((index as u8, data));
+        for IndexedPiece {
+            index,
+            data,
+            checksum,
+        } in rep.pieces
+        {
+            let piece = Piece { data, checksum };
+            out.push((index as u8, piece));
+        }
+        Ok(out)
+    }
+    async fn send_tombstone(&self, to: Uri, tombstone: Tombstone) -> anyhow::Result<()> {
+        let chan = self.state.connect(to).await;
+        let mut cli = SorockClient::new(chan);
+        cli.send_tombstone(SendTombstoneReq {
+            key: tombstone.key,
+            deleted_at: tombstone.deleted_at,
+        })
+        .await?;
+        Ok(())
+    }
+    async fn key_exists(&self, to: Uri, key: String) -> anyhow::Result<bool> {
+        let chan = self.state.connect(to).await;
+        let mut cli = SorockClient::new(chan);
+        let rep = cli.key_exists(KeyExistsReq { key }).await?;
+        let rep = rep.into_inner();
+        Ok(rep.exists)
+    }
+    async fn request_keys(
+        &self,
+        to: Uri,
+        prefix: String,
+        start_after: Option<String>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<String>> {
+        let chan = self.state.connect(to).await;
+        let mut cli = SorockClient::new(chan);
+        let rep = cli
+            .request_keys(RequestKeysReq {
+                prefix,
+                start_after,
+                limit: limit as u32,
+            })
+            .await?;
+        let rep = rep.into_inner();
+        Ok(rep.keys)
+    }
+    async fn request_versions(
+        &self,
+        to: Uri,
+        keys: Vec<String>,
+    ) -> anyhow::Result<Vec<Vec<(String, usize, EcParams)>>> {
+        let n = keys.len();
+        let chan = self.state.connect(to).await;
+        let mut cli = SorockClient::new(chan);
+        let rep = cli.request_versions(RequestVersionsReq { keys }).await?;
+        let rep = rep.into_inner();
+        anyhow::ensure!(rep.keys.len() == n);
+        let mut out = vec![];
+        for key_versions in rep.keys {
+            let versions = key_versions
+                .versions
+                .into_iter()
+                .map(|v| {
+                    let ec = EcParams {
+                        k: v.k as usize,
+                        n: v.n as usize,
+                    };
+                    (v.version_id, v.n_pieces as usize, ec)
+                })
+                .collect();
+            out.push(versions);
         }
         Ok(out)
     }
@@ -0,0 +1,196 @@
+use crate::*;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObjectKind {
+    Data,
+    /// The value is a `Manifest` that ties other objects together.
+    Manifest,
+}
+
+/// Header stored in front of every piece.
+/// All pieces of an object share the same header.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PieceHeader {
+    /// Length of the stored value before padding.
+    /// It is the compressed length if the object is compressed.
+    pub len: u64,
+    /// Parameters the object was erasure-coded with.
+    pub ec: EcParams,
+    pub kind: ObjectKind,
+    pub meta: ObjectMeta,
+}
+
+/// Version of the encoded header.
+/// When the header is changed, the older versions are kept decodable in `decode_header`.
+const HEADER_VERSION: u8 = 1;
+/// Set in the header length if the header starts with the version.
+/// The first headers were written without it.
+const VERSIONED: u32 = 1 << 31;
+
+/// Layout: [header length (u32 LE) | VERSIONED][version][header][shard]
+pub fn encode(header: &PieceHeader, shard: &[u8]) -> Bytes {
+    let header = encode_header(header);
+    let mut out = bytes::BytesMut::with_capacity(4 + header.len() + shard.len());
+    out.extend_from_slice(&(header.len() as u32 | VERSIONED).to_le_bytes());
+    out.extend_from_slice(&header);
+    out.extend_from_slice(shard);
+    out.freeze()
+}
+
+pub fn decode(piece: &[u8]) -> anyhow::Result<(PieceHeader, &[u8])> {
+    let n = match header_part_len(piece) {
+        Some(n) if n <= piece.len() => n,
+        _ => anyhow::bail!("piece is too short"),
+    };
+    let header = if is_versioned(piece) {
+        decode_header(&piece[4..n])?
+    } else {
+        decode_legacy_header(&piece[4..n])?
+    };
+    Ok((header, &piece[n..]))
+}
+
+/// Same as `decode` but the shard shares the memory with the piece.
+pub fn decode_bytes(piece: &Bytes) -> anyhow::Result<(PieceHeader, Bytes)> {
+    let (header, shard) = decode(piece)?;
+    let shard = piece.slice_ref(shard);
+    Ok((header, shard))
+}
+
+/// Length of `[header length][header]` in front of the piece.
+/// None if the piece is shorter than the length field.
+pub fn header_part_len(piece: &[u8]) -> Option<usize> {
+    if piece.len() < 4 {
+        return None;
+    }
+    let mut header_len = [0; 4];
+    header_len.copy_from_slice(&piece[0..4]);
+    Some(4 + (u32::from_le_bytes(header_len) & !VERSIONED) as usize)
+}
+
+fn is_versioned(piece: &[u8]) -> bool {
+    piece.len() >= 4 && piece[3] & 0x80 != 0
+}
+
+/// `[header length][header]` in front of the piece without the shard.
+/// The piece is returned as is if it is too short to tell.
+pub fn header_part(piece: &Bytes) -> Bytes {
+    match header_part_len(piece) {
+        Some(n) if n <= piece.len() => piece.slice(0..n),
+        _ => piece.clone(),
+    }
+}
+
+/// Decode the header from the bytes returned by `header_part`.
+pub fn decode_header_part(b: &[u8]) -> anyhow::Result<PieceHeader> {
+    let (header, _) = decode(b)?;
+    Ok(header)
+}
+
+/// Layout: [version (u8)][header]
+pub fn encode_header(header: &PieceHeader) -> Vec<u8> {
+    let mut out = vec![HEADER_VERSION];
+    bincode::serialize_into(&mut out, header).unwrap();
+    out
+}
+
+pub fn decode_header(b: &[u8]) -> anyhow::Result<PieceHeader> {
+    match b.first() {
+        Some(&HEADER_VERSION) => Ok(bincode::deserialize(&b[1..])?),
+        Some(v) => anyhow::bail!("unknown version of piece header ({})", v),
+        None => anyhow::bail!("piece header is empty"),
+    }
+}
+
+/// The first header, written without the version.
+/// The pieces written before the header was added are raw shards
+/// that can't be told from a piece with a header, so they are not supported.
+fn decode_legacy_header(b: &[u8]) -> anyhow::Result<PieceHeader> {
+    Ok(bincode::deserialize(b)?)
+}
+
+/// Check the expiry recorded in the header part (`header_part`).
+/// A header that can't be decoded is never expired.
+pub fn is_expired(part: &[u8], now: u64) -> bool {
+    match decode_header_part(part) {
+        Ok(header) => header.meta.is_expired(now),
+        Err(_) => false,
+    }
+}
+
+/// Length of a shard when a value of `len` bytes is split into `k` shards.
+/// Reed-Solomon doesn't accept empty shards so it is at least 1.
+pub fn shard_len(len: usize, k: usize) -> usize {
+    std::cmp::max(1, (len + k - 1) / k)
+}
+
+#[test]
+fn test_piece_encode_decode() {
+    let header = PieceHeader {
+        len: 5,
+        ec: EcParams { k: 2, n: 3 },
+        kind: ObjectKind::Data,
+        meta: ObjectMeta {
+            size: 5,
+            created_at: 1,
+            checksum: 2,
+            cluster_version: 3,
+            user: [("a".to_string(), "b".to_string())].into_iter().collect(),
+            version_id: "v".to_string(),
+            expires_at: Some(4),
+            storage_class: "c".to_string(),
+            compression: Compression::Zstd,
+        },
+    };
+    let piece = encode(&header, &[1, 2, 3]);
+    let (decoded, shard) = decode(&piece).unwrap();
+    assert_eq!(decoded, header);
+    assert_eq!(shard, &[1, 2, 3]);
+
+    let (decoded, shard) = decode_bytes(&piece).unwrap();
+    assert_eq!(decoded, header);
+    assert_eq!(shard, Bytes::from(vec![1, 2, 3]));
+    assert_eq!(shard.as_ptr(), piece[piece.len() - 3..].as_ptr());
+
+    let piece = encode(&header, &[]);
+    let (_, shard) = decode(&piece).unwrap();
+    assert!(shard.is_empty());
+
+    let piece = Piece::new(encode(&header, &[1]));
+    assert!(!is_expired(&piece.data, 3));
+    assert!(is_expired(&piece.data, 4));
+    assert!(!is_expired(&[1], 4));
+
+    let part = header_part(&piece.data);
+    assert_eq!(header_part_len(&piece.data), Some(part.len()));
+    assert_eq!(part.len(), piece.data.len() - 1);
+    assert_eq!(decode_header_part(&part).unwrap(), header);
+    assert!(decode_header_part(&part[..part.len() - 1]).is_err());
+
+    assert!(decode(&[1]).is_err());
+    assert!(decode(&[8, 0, 0, 0, 1]).is_err());
+
+    // A piece whose header was written without the version.
+    let legacy = bincode::serialize(&header).unwrap();
+    let mut piece = (legacy.len() as u32).to_le_bytes().to_vec();
+    piece.extend_from_slice(&legacy);
+    piece.extend_from_slice(&[1, 2, 3]);
+    let (decoded, shard) = decode(&piece).unwrap();
+    assert_eq!(decoded, header);
+    assert_eq!(shard, &[1, 2, 3]);
+
+    // A header of a newer version is not read as another layout.
+    let mut b = encode_header(&header);
+    assert_eq!(decode_header(&b).unwrap(), header);
+    b[0] = HEADER_VERSION + 1;
+    assert!(decode_header(&b).is_err());
+}
+
+#[test]
+fn test_shard_len() {
+    assert_eq!(shard_len(0, 4), 1);
+    assert_eq!(shard_len(1, 4), 1);
+    assert_eq!(shard_len(4, 4), 1);
+    assert_eq!(shard_len(5, 4), 2);
+    assert_eq!(shard_len(16, 4), 4);
+}
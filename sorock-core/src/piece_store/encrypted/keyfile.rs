@@ -0,0 +1,131 @@
+use super::KeyProvider;
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+
+const NONCE_LEN: usize = 24;
+
+/// Master keys in a local file. One key per line: `<id> <hex-encoded 32 bytes>`.
+/// The last key is the current one.
+/// A key can be removed once no data key is wrapped by it.
+pub struct KeyfileProvider {
+    keys: HashMap<String, XChaCha20Poly1305>,
+    current: String,
+}
+impl KeyfileProvider {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let mut keys = HashMap::new();
+        let mut current = None;
+        for line in std::fs::read_to_string(path)?.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (id, key) = line
+                .split_once(' ')
+                .ok_or_else(|| anyhow::anyhow!("malformed line in the keyfile"))?;
+            let key = hex::decode(key.trim())?;
+            anyhow::ensure!(key.len() == 32, "master key should be 32 bytes ({})", id);
+            keys.insert(
+                id.to_string(),
+                XChaCha20Poly1305::new(Key::from_slice(&key)),
+            );
+            current = Some(id.to_string());
+        }
+        let current = current.ok_or_else(|| anyhow::anyhow!("no master key in the keyfile"))?;
+        Ok(Self { keys, current })
+    }
+    /// Append a new random master key and return its id.
+    /// The file is created if missing and only the owner can read it.
+    pub fn add_key(path: &Path) -> anyhow::Result<String> {
+        use std::os::unix::fs::OpenOptionsExt;
+        let id = hex::encode(rand::random::<[u8; 8]>());
+        let key: [u8; 32] = rand::random();
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(path)?;
+        writeln!(file, "{} {}", id, hex::encode(key))?;
+        file.sync_all()?;
+        Ok(id)
+    }
+    pub fn remove_key(path: &Path, id: &str) -> anyhow::Result<()> {
+        let content = std::fs::read_to_string(path)?;
+        let lines: Vec<&str> = content
+            .lines()
+            .filter(|line| line.split(' ').next() != Some(id))
+            .collect();
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, lines.join("\n") + "\n")?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+    fn cipher(&self, id: &str) -> anyhow::Result<&XChaCha20Poly1305> {
+        self.keys
+            .get(id)
+            .ok_or_else(|| anyhow::anyhow!("master key not found ({})", id))
+    }
+}
+#[norpc::async_trait]
+impl KeyProvider for KeyfileProvider {
+    fn current_key_id(&self) -> String {
+        self.current.clone()
+    }
+    /// Layout: [nonce][ciphertext]
+    async fn wrap(&self, data_key: &[u8]) -> anyhow::Result<(String, Vec<u8>)> {
+        let cipher = self.cipher(&self.current)?;
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let payload = Payload {
+            msg: data_key,
+            aad: self.current.as_bytes(),
+        };
+        let ciphertext = cipher
+            .encrypt(XNonce::from_slice(&nonce), payload)
+            .map_err(|_| anyhow::anyhow!("failed to wrap the data key"))?;
+        let mut out = nonce.to_vec();
+        out.extend_from_slice(&ciphertext);
+        Ok((self.current.clone(), out))
+    }
+    async fn unwrap(&self, master_key_id: &str, wrapped: &[u8]) -> anyhow::Result<Vec<u8>> {
+        anyhow::ensure!(wrapped.len() >= NONCE_LEN, "wrapped key is too short");
+        let cipher = self.cipher(master_key_id)?;
+        let payload = Payload {
+            msg: &wrapped[NONCE_LEN..],
+            aad: master_key_id.as_bytes(),
+        };
+        cipher
+            .decrypt(XNonce::from_slice(&wrapped[..NONCE_LEN]), payload)
+            .map_err(|_| anyhow::anyhow!("failed to unwrap the data key"))
+    }
+}
+
+#[tokio::test]
+async fn test_keyfile_provider() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("keyfile");
+    assert!(KeyfileProvider::open(&path).is_err());
+
+    let k1 = KeyfileProvider::add_key(&path)?;
+    let provider = KeyfileProvider::open(&path)?;
+    let (id, wrapped) = provider.wrap(&[1; 32]).await?;
+    assert_eq!(id, k1);
+    assert_eq!(provider.unwrap(&id, &wrapped).await?, vec![1; 32]);
+
+    let k2 = KeyfileProvider::add_key(&path)?;
+    let provider = KeyfileProvider::open(&path)?;
+    assert_eq!(provider.current_key_id(), k2);
+    // Keys wrapped by the older master key can still be unwrapped.
+    assert_eq!(provider.unwrap(&k1, &wrapped).await?, vec![1; 32]);
+    // The id is authenticated.
+    assert!(provider.unwrap(&k2, &wrapped).await.is_err());
+
+    KeyfileProvider::remove_key(&path, &k1)?;
+    let provider = KeyfileProvider::open(&path)?;
+    assert!(provider.unwrap(&k1, &wrapped).await.is_err());
+    assert_eq!(provider.current_key_id(), k2);
+
+    Ok(())
+}
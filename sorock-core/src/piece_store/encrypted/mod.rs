@@ -0,0 +1,435 @@
+use crate::*;
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+mod keyfile;
+pub use keyfile::KeyfileProvider;
+
+/// Encrypt the pieces at rest in any piece store.
+/// Only the piece data is encrypted. The keys and the tombstones are stored as they are.
+/// The checksum in the inner store is of the encrypted piece so it tells nothing about the content.
+pub fn spawn(inner: piece_store::ClientT, state: State) -> piece_store::ClientT {
+    use norpc::runtime::tokio::*;
+    let svc = App { inner, state };
+    let svc = piece_store::PieceStoreService::new(svc);
+    let (chan, server) = ServerBuilder::new(svc).build();
+    tokio::spawn(server.serve());
+    piece_store::PieceStoreClient::new(chan)
+}
+
+/// Wraps the data keys with master keys kept out of the store.
+#[norpc::async_trait]
+pub trait KeyProvider: Send + Sync {
+    /// Id of the master key the data keys are wrapped with now.
+    fn current_key_id(&self) -> String;
+    /// Wrap the data key with the current master key.
+    /// Returns the id of the master key and the wrapped key.
+    async fn wrap(&self, data_key: &[u8]) -> anyhow::Result<(String, Vec<u8>)>;
+    async fn unwrap(&self, master_key_id: &str, wrapped: &[u8]) -> anyhow::Result<Vec<u8>>;
+}
+
+/// Version of the layout of the encrypted piece.
+const VERSION: u8 = 1;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = 1 + 4 + NONCE_LEN;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+struct WrappedKey {
+    master_key_id: String,
+    wrapped: Vec<u8>,
+}
+
+/// The data keys wrapped by the key provider.
+/// They are stored apart from the pieces so re-wrapping them doesn't rewrite the pieces.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default)]
+struct Keyring {
+    /// The data key new pieces are encrypted with.
+    active: u32,
+    keys: BTreeMap<u32, WrappedKey>,
+}
+
+pub struct State {
+    provider: Arc<dyn KeyProvider>,
+    /// The keyring is kept in memory if the path isn't given.
+    keyring_path: Option<PathBuf>,
+    keyring: Keyring,
+    ciphers: HashMap<u32, XChaCha20Poly1305>,
+}
+impl State {
+    /// Load the keyring or create one with a new data key.
+    pub async fn new(
+        provider: Arc<dyn KeyProvider>,
+        keyring_path: Option<PathBuf>,
+    ) -> anyhow::Result<Self> {
+        let keyring = match &keyring_path {
+            Some(path) if path.exists() => bincode::deserialize(&std::fs::read(path)?)?,
+            _ => Keyring::default(),
+        };
+        let mut state = Self {
+            provider,
+            keyring_path,
+            keyring,
+            ciphers: HashMap::new(),
+        };
+        for (id, key) in &state.keyring.keys {
+            let data_key = state
+                .provider
+                .unwrap(&key.master_key_id, &key.wrapped)
+                .await?;
+            anyhow::ensure!(data_key.len() == 32, "data key should be 32 bytes ({})", id);
+            let cipher = XChaCha20Poly1305::new(Key::from_slice(&data_key));
+            state.ciphers.insert(*id, cipher);
+        }
+        if state.keyring.keys.is_empty() {
+            state.add_data_key().await?;
+        }
+        Ok(state)
+    }
+    /// Re-wrap the data keys wrapped by an older master key.
+    /// The pieces are not rewritten. Returns the number of the keys re-wrapped.
+    ///
+    /// The state is owned by the service after `spawn` so the keys are rotated
+    /// before spawning, i.e. when the node is restarted with a new master key.
+    pub async fn rotate(&mut self) -> anyhow::Result<usize> {
+        let current = self.provider.current_key_id();
+        let mut n = 0;
+        for key in self.keyring.keys.values_mut() {
+            if key.master_key_id == current {
+                continue;
+            }
+            let data_key = self
+                .provider
+                .unwrap(&key.master_key_id, &key.wrapped)
+                .await?;
+            let (master_key_id, wrapped) = self.provider.wrap(&data_key).await?;
+            *key = WrappedKey {
+                master_key_id,
+                wrapped,
+            };
+            n += 1;
+        }
+        if n > 0 {
+            self.save()?;
+        }
+        Ok(n)
+    }
+    async fn add_data_key(&mut self) -> anyhow::Result<()> {
+        let data_key: [u8; 32] = rand::random();
+        let (master_key_id, wrapped) = self.provider.wrap(&data_key).await?;
+        let id = self.keyring.keys.keys().last().map(|x| x + 1).unwrap_or(0);
+        self.keyring.keys.insert(
+            id,
+            WrappedKey {
+                master_key_id,
+                wrapped,
+            },
+        );
+        self.keyring.active = id;
+        self.save()?;
+        self.ciphers
+            .insert(id, XChaCha20Poly1305::new(Key::from_slice(&data_key)));
+        Ok(())
+    }
+    /// The new keyring replaces the old one at once so a crash doesn't leave it half-written.
+    fn save(&self) -> anyhow::Result<()> {
+        if let Some(path) = &self.keyring_path {
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, bincode::serialize(&self.keyring)?)?;
+            std::fs::rename(&tmp, path)?;
+        }
+        Ok(())
+    }
+    /// Layout: [version (u8)][data key id (u32 LE)][nonce][ciphertext]
+    /// The location is authenticated so a piece can't be swapped with another.
+    fn encrypt(&self, loc: &PieceLocator, data: &[u8]) -> anyhow::Result<Bytes> {
+        let id = self.keyring.active;
+        let cipher = &self.ciphers[&id];
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let aad = aad(loc);
+        let ciphertext = cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: data,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("failed to encrypt the piece"))?;
+        let mut out = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+        out.push(VERSION);
+        out.extend_from_slice(&id.to_le_bytes());
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&ciphertext);
+        Ok(out.into())
+    }
+    /// None if the piece is damaged or moved from another location.
+    /// A data key missing in the keyring is an error because no piece can be read without it.
+    fn decrypt(&self, loc: &PieceLocator, data: &[u8]) -> anyhow::Result<Option<Bytes>> {
+        if data.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        anyhow::ensure!(data[0] == VERSION, "unknown layout of encrypted piece");
+        let mut id = [0; 4];
+        id.copy_from_slice(&data[1..5]);
+        let id = u32::from_le_bytes(id);
+        let cipher = self
+            .ciphers
+            .get(&id)
+            .ok_or_else(|| anyhow::anyhow!("unknown data key ({})", id))?;
+        let nonce = XNonce::from_slice(&data[5..HEADER_LEN]);
+        let aad = aad(loc);
+        let plaintext = cipher.decrypt(
+            nonce,
+            Payload {
+                msg: &data[HEADER_LEN..],
+                aad: &aad,
+            },
+        );
+        Ok(plaintext.ok().map(Bytes::from))
+    }
+    /// The decryption authenticates the piece
+    /// so the checksum of the plaintext is computed after it.
+    /// A piece that fails to decrypt is `Corrupted`.
+    fn decrypt_piece(&self, loc: &PieceLocator, piece: Piece) -> anyhow::Result<Piece> {
+        match self.decrypt(loc, &piece.data)? {
+            Some(data) => Ok(Piece::new(data)),
+            None => Err(piece_store::Corrupted(loc.clone()).into()),
+        }
+    }
+}
+
+fn aad(loc: &PieceLocator) -> Vec<u8> {
+    let mut out = loc.key.as_bytes().to_vec();
+    out.push(loc.index);
+    out
+}
+
+struct App {
+    inner: piece_store::ClientT,
+    state: State,
+}
+#[norpc::async_trait]
+impl piece_store::PieceStore for App {
+    async fn get_pieces(&self, key: String) -> anyhow::Result<Vec<(u8, Piece)>> {
+        let pieces = self.inner.clone().get_pieces(key.clone()).await?;
+        let mut out = vec![];
+        for (index, piece) in pieces {
+            let loc = PieceLocator {
+                key: key.clone(),
+                index,
+            };
+            match self.state.decrypt_piece(&loc, piece) {
+                Ok(piece) => out.push((index, piece)),
+                // The others are still returned.
+                // The piece is dropped when it is read alone.
+                Err(e) if piece_store::is_corrupted(&e) => {
+                    eprintln!("failed to decrypt piece: {:?}", &loc);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(out)
+    }
+    async fn get_piece(&self, loc: PieceLocator) -> anyhow::Result<Option<Piece>> {
+        let piece = match self.inner.clone().get_piece(loc.clone()).await? {
+            Some(piece) => piece,
+            None => return Ok(None),
+        };
+        Ok(Some(self.state.decrypt_piece(&loc, piece)?))
+    }
+    /// The header is encrypted with the shard so the whole piece is decrypted.
+    /// The header of a piece that fails to decrypt is treated as missing.
+    async fn get_piece_header(&self, loc: PieceLocator) -> anyhow::Result<Option<Bytes>> {
+        match self.get_piece(loc).await {
+            Ok(piece) => Ok(piece.map(|piece| piece::header_part(&piece.data))),
+            Err(e) if piece_store::is_corrupted(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    async fn get_piece_headers(&self, key: String) -> anyhow::Result<Vec<(u8, Bytes)>> {
+        let pieces = self.get_pieces(key).await?;
+        let out = pieces
+            .into_iter()
+            .map(|(index, piece)| (index, piece::header_part(&piece.data)))
+            .collect();
+        Ok(out)
+    }
+    async fn put_piece(&self, loc: PieceLocator, piece: Piece) -> anyhow::Result<()> {
+        let data = self.state.encrypt(&loc, &piece.data)?;
+        self.inner.clone().put_piece(loc, Piece::new(data)).await
+    }
+    async fn delete_piece(&self, loc: PieceLocator) -> anyhow::Result<()> {
+        self.inner.clone().delete_piece(loc).await
+    }
+    async fn delete_pieces(&self, key: String) -> anyhow::Result<()> {
+        self.inner.clone().delete_pieces(key).await
+    }
+    async fn piece_exists(&self, loc: PieceLocator) -> anyhow::Result<bool> {
+        self.inner.clone().piece_exists(loc).await
+    }
+    async fn key_exists(&self, key: String) -> anyhow::Result<bool> {
+        self.inner.clone().key_exists(key).await
+    }
+    async fn count_pieces(&self, key: String) -> anyhow::Result<usize> {
+        self.inner.clone().count_pieces(key).await
+    }
+    async fn keys(&self) -> anyhow::Result<Vec<String>> {
+        self.inner.clone().keys().await
+    }
+    async fn list_keys(
+        &self,
+        prefix: String,
+        start_after: Option<String>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<String>> {
+        self.inner
+            .clone()
+            .list_keys(prefix, start_after, limit)
+            .await
+    }
+    async fn put_tombstone(&self, tombstone: Tombstone) -> anyhow::Result<()> {
+        self.inner.clone().put_tombstone(tombstone).await
+    }
+    async fn get_tombstone(&self, key: String) -> anyhow::Result<Option<Tombstone>> {
+        self.inner.clone().get_tombstone(key).await
+    }
+    async fn delete_tombstone(&self, key: String) -> anyhow::Result<()> {
+        self.inner.clone().delete_tombstone(key).await
+    }
+    async fn tombstones(&self) -> anyhow::Result<Vec<Tombstone>> {
+        self.inner.clone().tombstones().await
+    }
+}
+
+#[cfg(test)]
+async fn spawn_for_test(dir: &std::path::Path) -> (piece_store::ClientT, piece_store::ClientT) {
+    let keyfile = dir.join("keyfile");
+    KeyfileProvider::add_key(&keyfile).unwrap();
+    let provider = Arc::new(KeyfileProvider::open(&keyfile).unwrap());
+    let state = State::new(provider, Some(dir.join("keyring")))
+        .await
+        .unwrap();
+    let inner = piece_store::mem::spawn(piece_store::mem::State::new());
+    (spawn(inner.clone(), state), inner)
+}
+
+#[tokio::test]
+async fn test_piece_store_encrypted() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let (cli, _) = spawn_for_test(dir.path()).await;
+    piece_store::test_piece_store(cli).await
+}
+
+#[tokio::test]
+async fn test_piece_headers_encrypted() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let (cli, _) = spawn_for_test(dir.path()).await;
+    piece_store::test_piece_headers(cli).await
+}
+
+#[tokio::test]
+async fn test_list_keys_encrypted() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let (cli, _) = spawn_for_test(dir.path()).await;
+    piece_store::test_list_keys(cli).await
+}
+
+#[tokio::test]
+async fn test_tombstone_store_encrypted() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let (cli, _) = spawn_for_test(dir.path()).await;
+    piece_store::test_tombstone_store(cli).await
+}
+
+#[tokio::test]
+async fn test_encryption_at_rest() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let (mut cli, mut inner) = spawn_for_test(dir.path()).await;
+    let loc = |index| PieceLocator {
+        key: "a".to_string(),
+        index,
+    };
+    let piece = Piece::new(Bytes::from(vec![7; 100]));
+    cli.put_piece(loc(0), piece.clone()).await?;
+
+    // The inner store only sees the ciphertext.
+    let stored = inner.get_piece(loc(0)).await?.unwrap();
+    assert_ne!(stored.data, piece.data);
+    // The checksum is of the ciphertext.
+    assert!(stored.verify());
+    assert_ne!(stored.checksum, piece.checksum);
+    assert!(!stored
+        .data
+        .windows(piece.data.len())
+        .any(|w| w == &piece.data[..]));
+    assert_eq!(cli.get_piece(loc(0)).await?, Some(piece.clone()));
+
+    // Tampered data fails to decrypt.
+    let mut tampered = stored.data.to_vec();
+    let last = tampered.len() - 1;
+    tampered[last] ^= 1;
+    inner
+        .put_piece(
+            loc(1),
+            Piece {
+                data: tampered.into(),
+                checksum: stored.checksum,
+            },
+        )
+        .await?;
+    let err = cli.get_piece(loc(1)).await.unwrap_err();
+    assert!(piece_store::is_corrupted(&err));
+    // So does a piece moved to another location.
+    inner.put_piece(loc(2), stored).await?;
+    let err = cli.get_piece(loc(2)).await.unwrap_err();
+    assert!(piece_store::is_corrupted(&err));
+
+    // The pieces that fail are left out and the good ones are returned.
+    let pieces = cli.get_pieces("a".to_string()).await?;
+    assert_eq!(pieces, vec![(0, piece.clone())]);
+    assert_eq!(cli.get_piece_headers("a".to_string()).await?.len(), 1);
+    assert_eq!(cli.get_piece_header(loc(1)).await?, None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_key_rotation() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let keyfile = dir.path().join("keyfile");
+    let keyring = dir.path().join("keyring");
+    let old_key = KeyfileProvider::add_key(&keyfile)?;
+    let provider = Arc::new(KeyfileProvider::open(&keyfile)?);
+    let state = State::new(provider, Some(keyring.clone())).await?;
+    let inner = piece_store::mem::spawn(piece_store::mem::State::new());
+    let mut cli = spawn(inner.clone(), state);
+    let loc = PieceLocator {
+        key: "a".to_string(),
+        index: 0,
+    };
+    let piece = Piece::new(Bytes::from(vec![7; 100]));
+    cli.put_piece(loc.clone(), piece.clone()).await?;
+    let stored = inner.clone().get_piece(loc.clone()).await?;
+
+    // A new master key is added and the data keys are re-wrapped.
+    let new_key = KeyfileProvider::add_key(&keyfile)?;
+    let provider = Arc::new(KeyfileProvider::open(&keyfile)?);
+    assert_eq!(provider.current_key_id(), new_key);
+    let mut state = State::new(provider, Some(keyring.clone())).await?;
+    assert_eq!(state.rotate().await?, 1);
+    assert_eq!(state.rotate().await?, 0);
+
+    // The old master key is no longer needed.
+    KeyfileProvider::remove_key(&keyfile, &old_key)?;
+    let provider = Arc::new(KeyfileProvider::open(&keyfile)?);
+    let state = State::new(provider, Some(keyring)).await?;
+    let mut cli = spawn(inner.clone(), state);
+    assert_eq!(cli.get_piece(loc.clone()).await?, Some(piece));
+    // The piece wasn't rewritten.
+    assert_eq!(inner.clone().get_piece(loc).await?, stored);
+
+    Ok(())
+}
@@ -1,7 +1,6 @@
 use crate::*;
 
-use bytes::Bytes;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
@@ -20,80 +19,94 @@ async fn test_piece_store_hashmap() -> anyhow::Result<()> {
     piece_store::test_piece_store(cli).await
 }
 
+#[tokio::test]
+async fn test_list_keys_hashmap() -> anyhow::Result<()> {
+    let cli = spawn(State::new());
+    piece_store::test_list_keys(cli).await
+}
+
+#[tokio::test]
+async fn test_piece_headers_hashmap() -> anyhow::Result<()> {
+    let cli = spawn(State::new());
+    piece_store::test_piece_headers(cli).await
+}
+
+#[tokio::test]
+async fn test_tombstone_store_hashmap() -> anyhow::Result<()> {
+    let cli = spawn(State::new());
+    piece_store::test_tombstone_store(cli).await
+}
+
 struct Bucket {
-    objects: Vec<Option<Bytes>>,
+    objects: BTreeMap<u8, Piece>,
 }
 impl Bucket {
     fn new() -> Self {
         Self {
-            objects: vec![None; N],
+            objects: BTreeMap::new(),
         }
     }
 }
 
 pub struct State {
     buckets: RwLock<HashMap<String, Bucket>>,
+    tombstones: RwLock<HashMap<String, u64>>,
 }
 impl State {
     pub fn new() -> Self {
         Self {
             buckets: RwLock::new(HashMap::new()),
+            tombstones: RwLock::new(HashMap::new()),
         }
     }
     async fn piece_exists(&self, loc: PieceLocator) -> bool {
         let buckets = self.buckets.read().await;
         let bucket = buckets.get(&loc.key);
         match bucket {
-            Some(bucket) => bucket.objects[loc.index as usize].is_some(),
+            Some(bucket) => bucket.objects.contains_key(&loc.index),
             None => false,
         }
     }
-    async fn get_pieces(&self, key: String, n: u8) -> Vec<(u8, Bytes)> {
+    async fn get_pieces(&self, key: String) -> Vec<(u8, Piece)> {
         let buckets = self.buckets.read().await;
         let bucket = buckets.get(&key);
         match bucket {
             None => vec![],
             Some(bucket) => {
-                let pieces = &bucket.objects;
                 let mut out = vec![];
-                for i in 0..n {
-                    if let Some(piece) = &pieces[i as usize] {
-                        out.push((i, piece.clone()))
-                    }
+                for (i, piece) in &bucket.objects {
+                    out.push((*i, piece.clone()))
                 }
                 out
             }
         }
     }
-    async fn get_piece(&self, loc: PieceLocator) -> Option<Bytes> {
+    async fn get_piece(&self, loc: PieceLocator) -> Option<Piece> {
         let buckets = self.buckets.read().await;
         let bucket = buckets.get(&loc.key);
         match bucket {
-            Some(bucket) => bucket.objects[loc.index as usize].clone(),
+            Some(bucket) => bucket.objects.get(&loc.index).cloned(),
             None => None,
         }
     }
-    async fn put_piece(&self, loc: PieceLocator, data: Bytes) {
+    async fn put_piece(&self, loc: PieceLocator, piece: Piece) {
         let mut buckets = self.buckets.write().await;
         let bucket = buckets.entry(loc.key).or_insert(Bucket::new());
-        bucket.objects[loc.index as usize] = Some(data);
+        bucket.objects.insert(loc.index, piece);
     }
     async fn delete_piece(&self, loc: PieceLocator) {
         let mut buckets = self.buckets.write().await;
         let bucket = buckets.entry(loc.key.clone()).or_insert(Bucket::new());
-        bucket.objects[loc.index as usize] = None;
+        bucket.objects.remove(&loc.index);
 
         // If the bucket doesn't have anything in it remove it.
-        let mut all_none = true;
-        for i in 0..N {
-            if bucket.objects[i].is_some() {
-                all_none = false;
-            }
-        }
-        if all_none {
+        if bucket.objects.is_empty() {
             buckets.remove(&loc.key);
         }
     }
+    async fn delete_pieces(&self, key: String) {
+        self.buckets.write().await.remove(&key);
+    }
     async fn keys(&self) -> Vec<String> {
         let buckets = self.buckets.read().await;
         let mut out = vec![];
@@ -102,6 +115,65 @@ impl State {
         }
         out
     }
+    async fn list_keys(
+        &self,
+        prefix: String,
+        start_after: Option<String>,
+        limit: usize,
+    ) -> Vec<String> {
+        let buckets = self.buckets.read().await;
+        let mut out = vec![];
+        for k in buckets.keys() {
+            if !k.starts_with(&prefix) {
+                continue;
+            }
+            if let Some(start_after) = &start_after {
+                if k <= start_after {
+                    continue;
+                }
+            }
+            out.push(k.clone());
+        }
+        out.sort();
+        out.truncate(limit);
+        out
+    }
+    async fn key_exists(&self, key: String) -> bool {
+        self.buckets.read().await.contains_key(&key)
+    }
+    async fn count_pieces(&self, key: String) -> usize {
+        match self.buckets.read().await.get(&key) {
+            Some(bucket) => bucket.objects.len(),
+            None => 0,
+        }
+    }
+    async fn put_tombstone(&self, tombstone: Tombstone) {
+        let mut tombstones = self.tombstones.write().await;
+        tombstones
+            .entry(tombstone.key)
+            .or_insert(tombstone.deleted_at);
+    }
+    async fn get_tombstone(&self, key: String) -> Option<Tombstone> {
+        let tombstones = self.tombstones.read().await;
+        tombstones.get(&key).map(|deleted_at| Tombstone {
+            key,
+            deleted_at: *deleted_at,
+        })
+    }
+    async fn delete_tombstone(&self, key: String) {
+        self.tombstones.write().await.remove(&key);
+    }
+    async fn tombstones(&self) -> Vec<Tombstone> {
+        let tombstones = self.tombstones.read().await;
+        let mut out = vec![];
+        for (k, deleted_at) in tombstones.iter() {
+            out.push(Tombstone {
+                key: k.clone(),
+                deleted_at: *deleted_at,
+            });
+        }
+        out
+    }
 }
 
 struct App {
@@ -109,38 +181,69 @@ struct App {
 }
 #[norpc::async_trait]
 impl piece_store::PieceStore for App {
-    async fn get_pieces(&self, key: String, n: u8) -> anyhow::Result<Vec<(u8, Vec<u8>)>> {
-        let pieces = self.state.get_pieces(key, n).await;
-        let mut out = vec![];
-        for (i, data) in pieces {
-            let mut buf = vec![];
-            buf.extend_from_slice(&data);
-            out.push((i, buf));
-        }
-        Ok(out)
+    async fn get_pieces(&self, key: String) -> anyhow::Result<Vec<(u8, Piece)>> {
+        Ok(self.state.get_pieces(key).await)
+    }
+    async fn get_piece(&self, loc: PieceLocator) -> anyhow::Result<Option<Piece>> {
+        Ok(self.state.get_piece(loc).await)
+    }
+    async fn get_piece_header(&self, loc: PieceLocator) -> anyhow::Result<Option<Bytes>> {
+        let piece = self.state.get_piece(loc).await;
+        Ok(piece.map(|piece| piece::header_part(&piece.data)))
     }
-    async fn get_piece(&self, loc: PieceLocator) -> anyhow::Result<Option<Vec<u8>>> {
-        let buf = self.state.get_piece(loc).await;
-        let buf = buf.map(|buf| {
-            let mut out = vec![];
-            out.extend_from_slice(&buf);
-            out
-        });
-        Ok(buf)
+    async fn get_piece_headers(&self, key: String) -> anyhow::Result<Vec<(u8, Bytes)>> {
+        let pieces = self.state.get_pieces(key).await;
+        let out = pieces
+            .into_iter()
+            .map(|(index, piece)| (index, piece::header_part(&piece.data)))
+            .collect();
+        Ok(out)
     }
     async fn piece_exists(&self, loc: PieceLocator) -> anyhow::Result<bool> {
         Ok(self.state.piece_exists(loc).await)
     }
-    async fn put_piece(&self, loc: PieceLocator, data: Bytes) -> anyhow::Result<()> {
-        self.state.put_piece(loc, data).await;
+    async fn put_piece(&self, loc: PieceLocator, piece: Piece) -> anyhow::Result<()> {
+        self.state.put_piece(loc, piece).await;
         Ok(())
     }
     async fn delete_piece(&self, loc: PieceLocator) -> anyhow::Result<()> {
         self.state.delete_piece(loc).await;
         Ok(())
     }
+    async fn delete_pieces(&self, key: String) -> anyhow::Result<()> {
+        self.state.delete_pieces(key).await;
+        Ok(())
+    }
     async fn keys(&self) -> anyhow::Result<Vec<String>> {
         let out = self.state.keys().await;
         Ok(out)
     }
+    async fn list_keys(
+        &self,
+        prefix: String,
+        start_after: Option<String>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<String>> {
+        Ok(self.state.list_keys(prefix, start_after, limit).await)
+    }
+    async fn key_exists(&self, key: String) -> anyhow::Result<bool> {
+        Ok(self.state.key_exists(key).await)
+    }
+    async fn count_pieces(&self, key: String) -> anyhow::Result<usize> {
+        Ok(self.state.count_pieces(key).await)
+    }
+    async fn put_tombstone(&self, tombstone: Tombstone) -> anyhow::Result<()> {
+        self.state.put_tombstone(tombstone).await;
+        Ok(())
+    }
+    async fn get_tombstone(&self, key: String) -> anyhow::Result<Option<Tombstone>> {
+        Ok(self.state.get_tombstone(key).await)
+    }
+    async fn delete_tombstone(&self, key: String) -> anyhow::Result<()> {
+        self.state.delete_tombstone(key).await;
+        Ok(())
+    }
+    async fn tombstones(&self) -> anyhow::Result<Vec<Tombstone>> {
+        Ok(self.state.tombstones().await)
+    }
 }
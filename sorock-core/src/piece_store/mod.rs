@@ -1,23 +1,58 @@
 use crate::*;
 
+pub mod encrypted;
 pub mod mem;
 pub mod sqlite;
 
+/// A piece that is found but can't be read, e.g. one that fails to decrypt.
+/// The caller drops it and rebuilds it like a piece whose checksum doesn't match.
+#[derive(thiserror::Error, Debug)]
+#[error("corrupted piece ({0:?})")]
+pub struct Corrupted(pub PieceLocator);
+
+pub fn is_corrupted(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<Corrupted>().is_some()
+}
+
 #[norpc::service]
 trait PieceStore {
-    fn get_pieces(key: String, n: u8) -> anyhow::Result<Vec<(u8, Vec<u8>)>>;
-    fn get_piece(loc: PieceLocator) -> anyhow::Result<Option<Vec<u8>>>;
-    fn put_piece(loc: PieceLocator, data: Bytes) -> anyhow::Result<()>;
+    // The checksum is stored as is. Verifying it is the caller's job.
+    // The pieces that can't be read are left out.
+    fn get_pieces(key: String) -> anyhow::Result<Vec<(u8, Piece)>>;
+    // `Corrupted` if the piece can't be read.
+    fn get_piece(loc: PieceLocator) -> anyhow::Result<Option<Piece>>;
+    // Only the header part in front of the piece (`piece::header_part`).
+    // It can't be verified because the checksum covers the whole piece.
+    fn get_piece_header(loc: PieceLocator) -> anyhow::Result<Option<Bytes>>;
+    // The header parts of all pieces of the key.
+    fn get_piece_headers(key: String) -> anyhow::Result<Vec<(u8, Bytes)>>;
+    fn put_piece(loc: PieceLocator, piece: Piece) -> anyhow::Result<()>;
     fn delete_piece(loc: PieceLocator) -> anyhow::Result<()>;
+    // Delete all pieces of the key.
+    fn delete_pieces(key: String) -> anyhow::Result<()>;
     fn piece_exists(loc: PieceLocator) -> anyhow::Result<bool>;
+    fn key_exists(key: String) -> anyhow::Result<bool>;
+    // Number of pieces of the key.
+    fn count_pieces(key: String) -> anyhow::Result<usize>;
     fn keys() -> anyhow::Result<Vec<String>>;
+    // Sorted keys with the prefix that come after `start_after`.
+    fn list_keys(
+        prefix: String,
+        start_after: Option<String>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<String>>;
+    // Put a tombstone. If the key already has one, the older one is kept.
+    fn put_tombstone(tombstone: Tombstone) -> anyhow::Result<()>;
+    fn get_tombstone(key: String) -> anyhow::Result<Option<Tombstone>>;
+    fn delete_tombstone(key: String) -> anyhow::Result<()>;
+    fn tombstones() -> anyhow::Result<Vec<Tombstone>>;
 }
 define_client!(PieceStore);
 
 #[cfg(test)]
 async fn test_piece_store(mut cli: piece_store::ClientT) -> anyhow::Result<()> {
     assert_eq!(cli.keys().await?.len(), 0);
-    assert_eq!(cli.get_pieces("a".to_string(), 8).await?, vec![]);
+    assert_eq!(cli.get_pieces("a".to_string()).await?, vec![]);
     assert_eq!(
         cli.piece_exists(PieceLocator {
             key: "a".to_string(),
@@ -33,11 +68,11 @@ async fn test_piece_store(mut cli: piece_store::ClientT) -> anyhow::Result<()> {
             key: "a".to_string(),
             index: 1,
         },
-        vec![0, 0, 0, 0].into(),
+        Piece::new(vec![0, 0, 0, 0].into()),
     )
     .await?;
     assert_eq!(cli.keys().await?.len(), 1);
-    assert_eq!(cli.get_pieces("a".to_string(), 8).await?.len(), 1);
+    assert_eq!(cli.get_pieces("a".to_string()).await?.len(), 1);
     assert_eq!(
         cli.piece_exists(PieceLocator {
             key: "a".to_string(),
@@ -61,11 +96,11 @@ async fn test_piece_store(mut cli: piece_store::ClientT) -> anyhow::Result<()> {
             key: "a".to_string(),
             index: 2,
         },
-        vec![0, 0, 0, 0].into(),
+        Piece::new(vec![0, 0, 0, 0].into()),
     )
     .await?;
     assert_eq!(cli.keys().await?.len(), 1);
-    assert_eq!(cli.get_pieces("a".to_string(), 8).await?.len(), 2);
+    assert_eq!(cli.get_pieces("a".to_string()).await?.len(), 2);
     assert_eq!(
         cli.piece_exists(PieceLocator {
             key: "a".to_string(),
@@ -81,7 +116,7 @@ async fn test_piece_store(mut cli: piece_store::ClientT) -> anyhow::Result<()> {
             key: "b".to_string(),
             index: 3,
         },
-        vec![0, 0, 0, 0].into(),
+        Piece::new(vec![0, 0, 0, 0].into()),
     )
     .await?;
     assert_eq!(cli.keys().await?.len(), 2);
@@ -94,6 +129,55 @@ async fn test_piece_store(mut cli: piece_store::ClientT) -> anyhow::Result<()> {
         false
     );
 
+    // The checksum is stored with the piece.
+    let piece = Piece {
+        data: vec![1, 2, 3].into(),
+        checksum: 100,
+    };
+    cli.put_piece(
+        PieceLocator {
+            key: "c".to_string(),
+            index: 0,
+        },
+        piece.clone(),
+    )
+    .await?;
+    assert_eq!(
+        cli.get_piece(PieceLocator {
+            key: "c".to_string(),
+            index: 0
+        })
+        .await?,
+        Some(piece.clone())
+    );
+    assert_eq!(cli.get_pieces("c".to_string()).await?, vec![(0, piece)]);
+
+    // Putting the piece again replaces it.
+    let piece = Piece::new(vec![4, 5].into());
+    cli.put_piece(
+        PieceLocator {
+            key: "c".to_string(),
+            index: 0,
+        },
+        piece.clone(),
+    )
+    .await?;
+    assert_eq!(
+        cli.get_piece(PieceLocator {
+            key: "c".to_string(),
+            index: 0
+        })
+        .await?,
+        Some(piece.clone())
+    );
+    assert_eq!(cli.get_pieces("c".to_string()).await?, vec![(0, piece)]);
+    assert_eq!(cli.count_pieces("c".to_string()).await?, 1);
+    cli.delete_piece(PieceLocator {
+        key: "c".to_string(),
+        index: 0,
+    })
+    .await?;
+
     // delete (a,1)
     cli.delete_piece(PieceLocator {
         key: "a".to_string(),
@@ -101,7 +185,9 @@ async fn test_piece_store(mut cli: piece_store::ClientT) -> anyhow::Result<()> {
     })
     .await?;
     assert_eq!(cli.keys().await?.len(), 2);
-    assert_eq!(cli.get_pieces("a".to_string(), 8).await?.len(), 1);
+    assert_eq!(cli.get_pieces("a".to_string()).await?.len(), 1);
+    assert_eq!(cli.count_pieces("a".to_string()).await?, 1);
+    assert_eq!(cli.count_pieces("x".to_string()).await?, 0);
 
     // delete (a,2)
     cli.delete_piece(PieceLocator {
@@ -110,7 +196,201 @@ async fn test_piece_store(mut cli: piece_store::ClientT) -> anyhow::Result<()> {
     })
     .await?;
     assert_eq!(cli.keys().await?.len(), 1);
-    assert_eq!(cli.get_pieces("a".to_string(), 8).await?.len(), 0);
+    assert_eq!(cli.get_pieces("a".to_string()).await?.len(), 0);
+    assert_eq!(cli.key_exists("a".to_string()).await?, false);
+    assert_eq!(cli.key_exists("b".to_string()).await?, true);
+
+    // delete all pieces of b
+    cli.delete_pieces("b".to_string()).await?;
+    assert_eq!(cli.keys().await?.len(), 0);
+    assert_eq!(cli.key_exists("b".to_string()).await?, false);
+
+    Ok(())
+}
+
+#[cfg(test)]
+async fn test_list_keys(mut cli: piece_store::ClientT) -> anyhow::Result<()> {
+    for key in ["b/2", "a/1", "b/1", "c", "b/3"] {
+        for index in 0..2 {
+            cli.put_piece(
+                PieceLocator {
+                    key: key.to_string(),
+                    index,
+                },
+                Piece::new(vec![0, 0, 0, 0].into()),
+            )
+            .await?;
+        }
+    }
+    assert_eq!(
+        cli.list_keys("".to_string(), None, 10).await?,
+        vec!["a/1", "b/1", "b/2", "b/3", "c"]
+    );
+    assert_eq!(
+        cli.list_keys("".to_string(), None, 2).await?,
+        vec!["a/1", "b/1"]
+    );
+    assert_eq!(
+        cli.list_keys("b/".to_string(), None, 10).await?,
+        vec!["b/1", "b/2", "b/3"]
+    );
+    assert_eq!(
+        cli.list_keys("b/".to_string(), Some("b/1".to_string()), 10)
+            .await?,
+        vec!["b/2", "b/3"]
+    );
+    assert_eq!(
+        cli.list_keys("b/".to_string(), Some("b/3".to_string()), 10)
+            .await?,
+        Vec::<String>::new()
+    );
+    assert_eq!(
+        cli.list_keys("d".to_string(), None, 10).await?,
+        Vec::<String>::new()
+    );
+
+    // Internal keys
+    for key in ["e\0x\01", "e\0x\02", "e\0y\01"] {
+        cli.put_piece(
+            PieceLocator {
+                key: key.to_string(),
+                index: 0,
+            },
+            Piece::new(vec![0, 0, 0, 0].into()),
+        )
+        .await?;
+    }
+    assert_eq!(
+        cli.list_keys("e\0x\0".to_string(), None, 10).await?,
+        vec!["e\0x\01", "e\0x\02"]
+    );
+    assert_eq!(
+        cli.list_keys("e\0x\0".to_string(), Some("e\0x\01".to_string()), 10)
+            .await?,
+        vec!["e\0x\02"]
+    );
+
+    // Keys right after the range of the prefix
+    for key in ["b0", "e\0x\u{10FFFF}", "e\0y"] {
+        cli.put_piece(
+            PieceLocator {
+                key: key.to_string(),
+                index: 0,
+            },
+            Piece::new(vec![0, 0, 0, 0].into()),
+        )
+        .await?;
+    }
+    assert_eq!(
+        cli.list_keys("b/".to_string(), None, 10).await?,
+        vec!["b/1", "b/2", "b/3"]
+    );
+    assert_eq!(
+        cli.list_keys("e\0x\0".to_string(), None, 10).await?,
+        vec!["e\0x\01", "e\0x\02"]
+    );
+    assert_eq!(
+        cli.list_keys("e\0x".to_string(), None, 10).await?,
+        vec!["e\0x\01", "e\0x\02", "e\0x\u{10FFFF}"]
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+async fn test_piece_headers(mut cli: piece_store::ClientT) -> anyhow::Result<()> {
+    let header = |user_len: usize| piece::PieceHeader {
+        len: 3,
+        ec: EcParams { k: 1, n: 2 },
+        kind: piece::ObjectKind::Data,
+        meta: ObjectMeta {
+            user: [("a".to_string(), "x".repeat(user_len))]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        },
+    };
+    let loc = |index| PieceLocator {
+        key: "a".to_string(),
+        index,
+    };
+    // The second header is larger than the shard and any prefetch.
+    let small = header(1);
+    let large = header(100_000);
+    cli.put_piece(loc(0), Piece::new(piece::encode(&small, &[1, 2, 3])))
+        .await?;
+    cli.put_piece(loc(1), Piece::new(piece::encode(&large, &[1, 2, 3])))
+        .await?;
+
+    let part = cli.get_piece_header(loc(0)).await?.unwrap();
+    assert_eq!(piece::decode_header_part(&part)?, small);
+    let part = cli.get_piece_header(loc(1)).await?.unwrap();
+    assert_eq!(piece::decode_header_part(&part)?, large);
+    assert_eq!(cli.get_piece_header(loc(2)).await?, None);
+
+    let mut parts = cli.get_piece_headers("a".to_string()).await?;
+    parts.sort_by_key(|(index, _)| *index);
+    assert_eq!(parts.len(), 2);
+    assert_eq!(piece::decode_header_part(&parts[0].1)?, small);
+    assert_eq!(piece::decode_header_part(&parts[1].1)?, large);
+    assert!(cli.get_piece_headers("b".to_string()).await?.is_empty());
+
+    // A piece too short to have a header is returned as is.
+    cli.put_piece(loc(2), Piece::new(vec![1].into())).await?;
+    let part = cli.get_piece_header(loc(2)).await?.unwrap();
+    assert_eq!(part, Bytes::from(vec![1]));
+    assert!(piece::decode_header_part(&part).is_err());
+
+    Ok(())
+}
+
+#[cfg(test)]
+async fn test_tombstone_store(mut cli: piece_store::ClientT) -> anyhow::Result<()> {
+    assert_eq!(cli.tombstones().await?.len(), 0);
+    assert_eq!(cli.get_tombstone("a".to_string()).await?, None);
+
+    // put a
+    cli.put_tombstone(Tombstone {
+        key: "a".to_string(),
+        deleted_at: 10,
+    })
+    .await?;
+    assert_eq!(cli.tombstones().await?.len(), 1);
+    assert_eq!(
+        cli.get_tombstone("a".to_string()).await?,
+        Some(Tombstone {
+            key: "a".to_string(),
+            deleted_at: 10,
+        })
+    );
+
+    // put a again. The older one is kept.
+    cli.put_tombstone(Tombstone {
+        key: "a".to_string(),
+        deleted_at: 20,
+    })
+    .await?;
+    assert_eq!(cli.tombstones().await?.len(), 1);
+    assert_eq!(
+        cli.get_tombstone("a".to_string())
+            .await?
+            .unwrap()
+            .deleted_at,
+        10
+    );
+
+    // put b
+    cli.put_tombstone(Tombstone {
+        key: "b".to_string(),
+        deleted_at: 30,
+    })
+    .await?;
+    assert_eq!(cli.tombstones().await?.len(), 2);
+
+    // delete a
+    cli.delete_tombstone("a".to_string()).await?;
+    assert_eq!(cli.tombstones().await?.len(), 1);
+    assert_eq!(cli.get_tombstone("a".to_string()).await?, None);
 
     Ok(())
 }
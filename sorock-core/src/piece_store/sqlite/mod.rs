@@ -47,47 +47,164 @@ impl State {
         let db_pool = SqlitePool::connect_with(options).await.unwrap();
         let q = include_str!("./schema.sql");
         db_pool.execute(q).await.unwrap();
+        migrate(&db_pool).await.unwrap();
 
         Self { db_pool }
     }
 }
+
+/// Bring a table created by an older version up to the current schema.
+async fn migrate(db_pool: &SqlitePool) -> anyhow::Result<()> {
+    // The checksum was added later.
+    // The rows put before have no checksum and are read as unverified.
+    let q = "select count(*) from pragma_table_info('sorockdb') where name = 'checksum'";
+    let rec: (i32,) = sqlx::query_as(q).fetch_one(db_pool).await?;
+    if rec.0 == 0 {
+        let q = "alter table sorockdb add column checksum integer";
+        sqlx::query(q).execute(db_pool).await?;
+    }
+
+    // (key, idx) wasn't unique so a piece put again had more than one row.
+    // The last row put is kept.
+    let q = "select count(*) from pragma_index_list('sorockdb') where name = 'idx_piece'";
+    let rec: (i32,) = sqlx::query_as(q).fetch_one(db_pool).await?;
+    if rec.0 == 0 {
+        let mut tx = db_pool.begin().await?;
+        let q =
+            "delete from sorockdb where id not in (select max(id) from sorockdb group by key, idx)";
+        sqlx::query(q).execute(&mut tx).await?;
+        let q = "create unique index idx_piece on sorockdb (key, idx)";
+        sqlx::query(q).execute(&mut tx).await?;
+        tx.commit().await?;
+    }
+    Ok(())
+}
 #[derive(sqlx::FromRow, Debug)]
 struct Rec {
     idx: i64,
     data: Vec<u8>,
+    /// None if the row was put before the checksum was added.
+    checksum: Option<i64>,
+}
+impl Rec {
+    /// A piece without the checksum can't be verified
+    /// so the checksum is computed from the data as it is.
+    fn into_piece(self) -> Piece {
+        match self.checksum {
+            Some(checksum) => Piece {
+                data: self.data.into(),
+                checksum: checksum as u32,
+            },
+            None => Piece::new(self.data.into()),
+        }
+    }
+}
+#[derive(sqlx::FromRow, Debug)]
+struct HeaderRec {
+    idx: i64,
+    data: Vec<u8>,
 }
 #[derive(sqlx::FromRow, Debug)]
 struct Key {
     key: String,
 }
+#[derive(sqlx::FromRow, Debug)]
+struct TombstoneRec {
+    key: String,
+    deleted_at: i64,
+}
+/// Bytes read from the front of a piece to find the header.
+/// A larger header is read again with its length.
+const HEADER_PREFETCH: usize = 4096;
+
+/// The smallest string greater than all the strings with the prefix
+/// so the prefix can be scanned as a range of the index.
+/// None if there is no such string.
+fn prefix_upper_bound(prefix: &str) -> Option<String> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    while let Some(c) = chars.pop() {
+        // The surrogates aren't chars.
+        let next = match c as u32 {
+            0xD7FF => Some('\u{E000}'),
+            x => char::from_u32(x + 1),
+        };
+        if let Some(next) = next {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
 struct App {
     state: State,
 }
+impl App {
+    /// The header parts of the pieces of the key. All the pieces if `index` is None.
+    async fn read_header_parts(
+        &self,
+        key: String,
+        index: Option<u8>,
+    ) -> anyhow::Result<Vec<(u8, Bytes)>> {
+        let q = "select idx, substr(data, 1, $3) as data from sorockdb where key = $1 and ($2 is null or idx = $2)";
+        let recs = sqlx::query_as::<_, HeaderRec>(q)
+            .bind(key.clone())
+            .bind(index)
+            .bind(HEADER_PREFETCH as i64)
+            .fetch_all(&self.state.db_pool)
+            .await?;
+        let mut out = vec![];
+        for HeaderRec { idx, data } in recs {
+            let data = match piece::header_part_len(&data) {
+                Some(n) if n > data.len() && data.len() == HEADER_PREFETCH => {
+                    let rec = sqlx::query_as::<_, HeaderRec>(q)
+                        .bind(key.clone())
+                        .bind(idx)
+                        .bind(n as i64)
+                        .fetch_optional(&self.state.db_pool)
+                        .await?;
+                    match rec {
+                        Some(rec) => rec.data,
+                        // Deleted in the meantime.
+                        None => continue,
+                    }
+                }
+                _ => data,
+            };
+            out.push((idx as u8, piece::header_part(&data.into())));
+        }
+        Ok(out)
+    }
+}
 #[norpc::async_trait]
 impl piece_store::PieceStore for App {
-    async fn get_pieces(&self, key: String, n: u8) -> anyhow::Result<Vec<(u8, Vec<u8>)>> {
-        let q = "select idx, data from sorockdb where key = $1";
+    async fn get_pieces(&self, key: String) -> anyhow::Result<Vec<(u8, Piece)>> {
+        let q = "select idx, data, checksum from sorockdb where key = $1";
         let recs = sqlx::query_as::<_, Rec>(q)
             .bind(key)
             .fetch_all(&self.state.db_pool)
             .await?;
         let mut out = vec![];
-        for Rec { idx, data, .. } in recs {
-            out.push((idx as u8, data));
+        for rec in recs {
+            out.push((rec.idx as u8, rec.into_piece()));
         }
         Ok(out)
     }
-    async fn get_piece(&self, loc: PieceLocator) -> anyhow::Result<Option<Vec<u8>>> {
-        let q = "select idx, data from sorockdb where key = $1 and idx = $2";
+    async fn get_piece(&self, loc: PieceLocator) -> anyhow::Result<Option<Piece>> {
+        let q = "select idx, data, checksum from sorockdb where key = $1 and idx = $2";
         let rec = sqlx::query_as::<_, Rec>(q)
             .bind(loc.key)
             .bind(loc.index)
             .fetch_optional(&self.state.db_pool)
             .await?;
-        match rec {
-            None => Ok(None),
-            Some(rec) => Ok(Some(rec.data)),
-        }
+        Ok(rec.map(|rec| rec.into_piece()))
+    }
+    async fn get_piece_header(&self, loc: PieceLocator) -> anyhow::Result<Option<Bytes>> {
+        let mut parts = self.read_header_parts(loc.key, Some(loc.index)).await?;
+        Ok(parts.pop().map(|(_, part)| part))
+    }
+    async fn get_piece_headers(&self, key: String) -> anyhow::Result<Vec<(u8, Bytes)>> {
+        self.read_header_parts(key, None).await
     }
     async fn piece_exists(&self, loc: PieceLocator) -> anyhow::Result<bool> {
         let q = "select count(*) from sorockdb where key = $1 and idx = $2";
@@ -98,12 +215,14 @@ impl piece_store::PieceStore for App {
             .await?;
         Ok(rec.0 > 0)
     }
-    async fn put_piece(&self, loc: PieceLocator, data: Bytes) -> anyhow::Result<()> {
-        let q = "insert into sorockdb (key, idx, data) values ($1, $2, $3)";
+    async fn put_piece(&self, loc: PieceLocator, piece: Piece) -> anyhow::Result<()> {
+        let q =
+            "insert or replace into sorockdb (key, idx, data, checksum) values ($1, $2, $3, $4)";
         sqlx::query(q)
             .bind(loc.key)
             .bind(loc.index)
-            .bind(data.as_ref())
+            .bind(piece.data.as_ref())
+            .bind(piece.checksum as i64)
             .execute(&self.state.db_pool)
             .await?;
         Ok(())
@@ -117,6 +236,14 @@ impl piece_store::PieceStore for App {
             .await?;
         Ok(())
     }
+    async fn delete_pieces(&self, key: String) -> anyhow::Result<()> {
+        let q = "delete from sorockdb where key = $1";
+        sqlx::query(q)
+            .bind(key)
+            .execute(&self.state.db_pool)
+            .await?;
+        Ok(())
+    }
     async fn keys(&self) -> anyhow::Result<Vec<String>> {
         let q = "select key from sorockdb";
         let keys = sqlx::query_as::<_, Key>(q)
@@ -129,6 +256,97 @@ impl piece_store::PieceStore for App {
         let out = out.into_iter().collect();
         Ok(out)
     }
+    async fn list_keys(
+        &self,
+        prefix: String,
+        start_after: Option<String>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<String>> {
+        // Scanned as a range so the index is used.
+        // The keys are compared bytewise which is the order of the chars.
+        let q = "select distinct key from sorockdb where key >= $1 and ($2 is null or key < $2) and ($3 is null or key > $3) order by key limit $4";
+        let upper = prefix_upper_bound(&prefix);
+        let keys = sqlx::query_as::<_, Key>(q)
+            .bind(prefix)
+            .bind(upper)
+            .bind(start_after)
+            .bind(limit as i64)
+            .fetch_all(&self.state.db_pool)
+            .await?;
+        let out = keys.into_iter().map(|key| key.key).collect();
+        Ok(out)
+    }
+    async fn key_exists(&self, key: String) -> anyhow::Result<bool> {
+        let q = "select count(*) from sorockdb where key = $1";
+        let rec: (i32,) = sqlx::query_as(q)
+            .bind(key)
+            .fetch_one(&self.state.db_pool)
+            .await?;
+        Ok(rec.0 > 0)
+    }
+    async fn count_pieces(&self, key: String) -> anyhow::Result<usize> {
+        let q = "select count(*) from sorockdb where key = $1";
+        let rec: (i32,) = sqlx::query_as(q)
+            .bind(key)
+            .fetch_one(&self.state.db_pool)
+            .await?;
+        Ok(rec.0 as usize)
+    }
+    async fn put_tombstone(&self, tombstone: Tombstone) -> anyhow::Result<()> {
+        let q = "insert or ignore into tombstone (key, deleted_at) values ($1, $2)";
+        sqlx::query(q)
+            .bind(tombstone.key)
+            .bind(tombstone.deleted_at as i64)
+            .execute(&self.state.db_pool)
+            .await?;
+        Ok(())
+    }
+    async fn get_tombstone(&self, key: String) -> anyhow::Result<Option<Tombstone>> {
+        let q = "select key, deleted_at from tombstone where key = $1";
+        let rec = sqlx::query_as::<_, TombstoneRec>(q)
+            .bind(key)
+            .fetch_optional(&self.state.db_pool)
+            .await?;
+        Ok(rec.map(|rec| Tombstone {
+            key: rec.key,
+            deleted_at: rec.deleted_at as u64,
+        }))
+    }
+    async fn delete_tombstone(&self, key: String) -> anyhow::Result<()> {
+        let q = "delete from tombstone where key = $1";
+        sqlx::query(q)
+            .bind(key)
+            .execute(&self.state.db_pool)
+            .await?;
+        Ok(())
+    }
+    async fn tombstones(&self) -> anyhow::Result<Vec<Tombstone>> {
+        let q = "select key, deleted_at from tombstone";
+        let recs = sqlx::query_as::<_, TombstoneRec>(q)
+            .fetch_all(&self.state.db_pool)
+            .await?;
+        let mut out = vec![];
+        for TombstoneRec { key, deleted_at } in recs {
+            out.push(Tombstone {
+                key,
+                deleted_at: deleted_at as u64,
+            });
+        }
+        Ok(out)
+    }
+}
+
+#[test]
+fn test_prefix_upper_bound() {
+    assert_eq!(prefix_upper_bound(""), None);
+    assert_eq!(prefix_upper_bound("b/"), Some("b0".to_string()));
+    assert_eq!(prefix_upper_bound("a\0"), Some("a\u{1}".to_string()));
+    assert_eq!(
+        prefix_upper_bound("a\u{D7FF}"),
+        Some("a\u{E000}".to_string())
+    );
+    assert_eq!(prefix_upper_bound("a\u{10FFFF}"), Some("b".to_string()));
+    assert_eq!(prefix_upper_bound("\u{10FFFF}"), None);
 }
 
 #[tokio::test]
@@ -138,6 +356,27 @@ async fn test_sqlite_store_mem() -> anyhow::Result<()> {
     piece_store::test_piece_store(cli).await
 }
 
+#[tokio::test]
+async fn test_sqlite_list_keys_mem() -> anyhow::Result<()> {
+    let state = State::new(StoreType::Memory).await;
+    let cli = spawn(state);
+    piece_store::test_list_keys(cli).await
+}
+
+#[tokio::test]
+async fn test_sqlite_piece_headers_mem() -> anyhow::Result<()> {
+    let state = State::new(StoreType::Memory).await;
+    let cli = spawn(state);
+    piece_store::test_piece_headers(cli).await
+}
+
+#[tokio::test]
+async fn test_sqlite_tombstone_store_mem() -> anyhow::Result<()> {
+    let state = State::new(StoreType::Memory).await;
+    let cli = spawn(state);
+    piece_store::test_tombstone_store(cli).await
+}
+
 #[tokio::test]
 async fn test_sqlite_store_dir() -> anyhow::Result<()> {
     let tempdir = tempfile::tempdir()?;
@@ -146,3 +385,91 @@ async fn test_sqlite_store_dir() -> anyhow::Result<()> {
     let cli = spawn(state);
     piece_store::test_piece_store(cli).await
 }
+
+/// Open the file a store in `root_dir` uses without the migration.
+#[cfg(test)]
+async fn connect_raw(root_dir: &std::path::Path) -> anyhow::Result<SqlitePool> {
+    let path = root_dir.with_file_name("store.db");
+    let options = SqliteConnectOptions::from_str(&format!("sqlite://{}", path.to_str().unwrap()))?
+        .create_if_missing(true);
+    Ok(SqlitePool::connect_with(options).await?)
+}
+
+#[tokio::test]
+async fn test_sqlite_migrate_duplicates() -> anyhow::Result<()> {
+    let tempdir = tempfile::tempdir()?;
+    let root_dir = tempdir.path().join("data");
+
+    // An older table without the unique index has the piece twice.
+    let db_pool = connect_raw(&root_dir).await?;
+    db_pool
+        .execute("create table sorockdb (id integer primary key, key text, idx integer, data blob, checksum integer); create index idx_key on sorockdb (key);")
+        .await?;
+    for data in [vec![1], vec![2]] {
+        let piece = Piece::new(data.into());
+        sqlx::query("insert into sorockdb (key, idx, data, checksum) values ($1, $2, $3, $4)")
+            .bind("a")
+            .bind(0)
+            .bind(piece.data.as_ref())
+            .bind(piece.checksum as i64)
+            .execute(&db_pool)
+            .await?;
+    }
+    db_pool.close().await;
+
+    let state = State::new(StoreType::Directory { root_dir }).await;
+    let mut cli = spawn(state);
+    let loc = PieceLocator {
+        key: "a".to_string(),
+        index: 0,
+    };
+    assert_eq!(
+        cli.get_piece(loc.clone()).await?,
+        Some(Piece::new(vec![2].into()))
+    );
+    assert_eq!(cli.count_pieces("a".to_string()).await?, 1);
+
+    cli.put_piece(loc.clone(), Piece::new(vec![3].into()))
+        .await?;
+    assert_eq!(cli.get_piece(loc).await?, Some(Piece::new(vec![3].into())));
+    assert_eq!(cli.count_pieces("a".to_string()).await?, 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sqlite_migrate_checksum() -> anyhow::Result<()> {
+    let tempdir = tempfile::tempdir()?;
+    let root_dir = tempdir.path().join("data");
+
+    // The oldest table has no checksum column.
+    let db_pool = connect_raw(&root_dir).await?;
+    db_pool
+        .execute("create table sorockdb (id integer primary key, key text, idx integer, data blob); create index idx_key on sorockdb (key);")
+        .await?;
+    sqlx::query("insert into sorockdb (key, idx, data) values ($1, $2, $3)")
+        .bind("a")
+        .bind(0)
+        .bind(vec![1u8, 2, 3])
+        .execute(&db_pool)
+        .await?;
+    db_pool.close().await;
+
+    let state = State::new(StoreType::Directory { root_dir }).await;
+    let mut cli = spawn(state);
+    let loc = PieceLocator {
+        key: "a".to_string(),
+        index: 0,
+    };
+    let piece = Piece::new(vec![1, 2, 3].into());
+    assert_eq!(cli.get_piece(loc.clone()).await?, Some(piece.clone()));
+    assert_eq!(cli.get_pieces("a".to_string()).await?, vec![(0, piece)]);
+
+    // A new piece has the checksum.
+    let piece = Piece {
+        data: vec![4].into(),
+        checksum: 0,
+    };
+    cli.put_piece(loc.clone(), piece.clone()).await?;
+    assert_eq!(cli.get_piece(loc).await?, Some(piece));
+    Ok(())
+}
@@ -2,6 +2,13 @@ create table if not exists sorockdb (
 	id integer primary key,
 	key text,
 	idx integer,
-	data blob
+	data blob,
+	checksum integer
 );
-create index if not exists idx_key on sorockdb (key);
\ No newline at end of file
+create index if not exists idx_key on sorockdb (key);
+-- The unique index on (key, idx) is created by `migrate`
+-- because a table of an older version may have duplicates.
+create table if not exists tombstone (
+	key text primary key,
+	deleted_at integer
+);
\ No newline at end of file
@@ -3,7 +3,7 @@ use anyhow::Result;
 
 use cluster_map::Change;
 use lol_core::simple::RaftAppSimple;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
@@ -13,14 +13,113 @@ struct Snapshot {
     uri_map: HashMap<URI, u64>,
     next_id: u64,
     version: u64,
+    ec: EcParams,
+    durability: Durability,
+    storage_classes: BTreeMap<String, EcParams>,
 }
+/// Put in front of a versioned snapshot.
+/// A snapshot written before starts with the table
+/// whose length can't be this large.
+const SNAPSHOT_MAGIC: [u8; 8] = *b"\xffSNAPSHT";
+/// Version of the encoded snapshot.
+/// When a field is added, the older versions are kept decodable in `Snapshot::decode`.
+const SNAPSHOT_VERSION: u8 = 1;
+
 impl Snapshot {
+    /// Layout: [magic][version (u8)][snapshot]
     fn encode(&self) -> Vec<u8> {
-        bincode::serialize(&self).unwrap()
+        let mut out = SNAPSHOT_MAGIC.to_vec();
+        out.push(SNAPSHOT_VERSION);
+        bincode::serialize_into(&mut out, self).unwrap();
+        out
+    }
+    fn decode(b: &[u8]) -> anyhow::Result<Self> {
+        let b = match b.strip_prefix(&SNAPSHOT_MAGIC[..]) {
+            Some(b) => b,
+            None => return decode_unversioned_snapshot(b),
+        };
+        match b.split_first() {
+            Some((&SNAPSHOT_VERSION, b)) => Ok(bincode::deserialize(b)?),
+            Some((v, _)) => anyhow::bail!("unknown version of snapshot ({})", v),
+            None => anyhow::bail!("snapshot is empty"),
+        }
+    }
+}
+
+/// Snapshot written before the erasure-coding parameters were added.
+#[derive(serde::Deserialize, serde::Serialize)]
+struct SnapshotV0 {
+    table: asura::Table,
+    uri_map: HashMap<URI, u64>,
+    next_id: u64,
+    version: u64,
+}
+/// `SnapshotV0` with the erasure-coding parameters.
+#[derive(serde::Deserialize, serde::Serialize)]
+struct SnapshotV0Ec {
+    table: asura::Table,
+    uri_map: HashMap<URI, u64>,
+    next_id: u64,
+    version: u64,
+    ec: EcParams,
+}
+/// `SnapshotV0Ec` with the durability.
+#[derive(serde::Deserialize, serde::Serialize)]
+struct SnapshotV0Durability {
+    table: asura::Table,
+    uri_map: HashMap<URI, u64>,
+    next_id: u64,
+    version: u64,
+    ec: EcParams,
+    durability: Durability,
+}
+
+/// The snapshots written before the version was added.
+/// Their fields were appended one by one so the longest layout
+/// which takes the whole bytes is decoded and the missing fields are the defaults.
+fn decode_unversioned_snapshot(b: &[u8]) -> anyhow::Result<Snapshot> {
+    use bincode::Options;
+    let exact = || {
+        bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .reject_trailing_bytes()
+    };
+    // The last layout before the version is the same as the version 1.
+    if let Ok(x) = exact().deserialize::<Snapshot>(b) {
+        return Ok(x);
     }
-    fn decode(b: &[u8]) -> Self {
-        bincode::deserialize(b).unwrap()
+    if let Ok(x) = exact().deserialize::<SnapshotV0Durability>(b) {
+        return Ok(Snapshot {
+            table: x.table,
+            uri_map: x.uri_map,
+            next_id: x.next_id,
+            version: x.version,
+            ec: x.ec,
+            durability: x.durability,
+            storage_classes: BTreeMap::new(),
+        });
     }
+    if let Ok(x) = exact().deserialize::<SnapshotV0Ec>(b) {
+        return Ok(Snapshot {
+            table: x.table,
+            uri_map: x.uri_map,
+            next_id: x.next_id,
+            version: x.version,
+            ec: x.ec,
+            durability: Durability::default(),
+            storage_classes: BTreeMap::new(),
+        });
+    }
+    let x: SnapshotV0 = exact().deserialize(b)?;
+    Ok(Snapshot {
+        table: x.table,
+        uri_map: x.uri_map,
+        next_id: x.next_id,
+        version: x.version,
+        ec: EcParams::default(),
+        durability: Durability::default(),
+        storage_classes: BTreeMap::new(),
+    })
 }
 
 pub struct State {
@@ -29,6 +128,9 @@ pub struct State {
     next_id: u64,
     version: u64,
     last_change: Change,
+    ec: EcParams,
+    durability: Durability,
+    storage_classes: BTreeMap<String, EcParams>,
 }
 impl State {
     fn new() -> Self {
@@ -38,6 +140,9 @@ impl State {
             next_id: 0,
             version: 0,
             last_change: Change::Set,
+            ec: EcParams::default(),
+            durability: Durability::default(),
+            storage_classes: BTreeMap::new(),
         }
     }
     fn add_node(&mut self, uri: URI, cap: f64) {
@@ -59,13 +164,50 @@ impl State {
             self.last_change = Change::Remove(uri.0)
         }
     }
+    fn set_ec_params(&mut self, ec: EcParams) {
+        // The cluster may have changed since the request was validated.
+        if let Err(e) = ec.validate(self.uri_map.len()) {
+            eprintln!("ignored invalid ec params: {}", e);
+            return;
+        }
+        if self.ec != ec {
+            self.ec = ec;
+            self.version += 1;
+            self.last_change = Change::Set;
+        }
+    }
+    /// The placement doesn't change so the version isn't bumped.
+    fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability;
+    }
+    fn set_storage_class(&mut self, name: String, ec: EcParams) {
+        // The cluster may have changed since the request was validated.
+        if let Err(e) = ec.validate(self.uri_map.len()) {
+            eprintln!("ignored invalid storage class {}: {}", name, e);
+            return;
+        }
+        // The objects written in the class keep the parameters recorded in their pieces
+        // so the placement doesn't change.
+        self.storage_classes.insert(name, ec);
+    }
+    fn delete_storage_class(&mut self, name: String) {
+        self.storage_classes.remove(&name);
+    }
     fn make_cluster_map(&self) -> ClusterMap {
         let cluster = asura::Cluster::from_table(self.cluster.dump_table());
         let mut idmap = HashMap::new();
         for (k, v) in &self.uri_map {
             idmap.insert(*v, k.clone());
         }
-        ClusterMap::build(self.version, self.last_change.clone(), cluster, idmap)
+        ClusterMap::build(
+            self.version,
+            self.last_change.clone(),
+            cluster,
+            idmap,
+            self.ec,
+            self.durability,
+            self.storage_classes.clone(),
+        )
     }
 }
 
@@ -88,6 +230,16 @@ impl RaftAppSimple for App {
         match command {
             Command::AddNode { uri, cap } => self.state.write().await.add_node(uri, cap),
             Command::RemoveNode { uri } => self.state.write().await.remove_node(uri),
+            Command::SetEcParams { ec } => self.state.write().await.set_ec_params(ec),
+            Command::SetDurability { durability } => {
+                self.state.write().await.set_durability(durability)
+            }
+            Command::SetStorageClass { name, ec } => {
+                self.state.write().await.set_storage_class(name, ec)
+            }
+            Command::DeleteStorageClass { name } => {
+                self.state.write().await.delete_storage_class(name)
+            }
         }
 
         let cm = self.state.read().await.make_cluster_map();
@@ -100,11 +252,17 @@ impl RaftAppSimple for App {
         let uri_map = reader.uri_map.clone();
         let next_id = reader.next_id;
         let version = reader.version;
+        let ec = reader.ec;
+        let durability = reader.durability;
+        let storage_classes = reader.storage_classes.clone();
         let snapshot = Snapshot {
             table,
             uri_map,
             next_id,
             version,
+            ec,
+            durability,
+            storage_classes,
         };
         Ok((vec![], Some(Snapshot::encode(&snapshot))))
         // Ok((vec![], None))
@@ -113,7 +271,7 @@ impl RaftAppSimple for App {
         let init_state = match snapshot {
             None => State::new(),
             Some(snapshot) => {
-                let snapshot = Snapshot::decode(&snapshot);
+                let snapshot = Snapshot::decode(&snapshot)?;
                 let cluster = asura::Cluster::from_table(snapshot.table);
                 let next_id = snapshot.next_id;
                 let version = snapshot.version;
@@ -123,6 +281,9 @@ impl RaftAppSimple for App {
                     uri_map: snapshot.uri_map,
                     next_id,
                     last_change: Change::Set,
+                    ec: snapshot.ec,
+                    durability: snapshot.durability,
+                    storage_classes: snapshot.storage_classes,
                 }
             }
         };
@@ -146,3 +307,74 @@ impl RaftAppSimple for App {
         unimplemented!()
     }
 }
+
+#[test]
+fn test_snapshot_decode() {
+    let uri = URI("http://n0:50000".parse().unwrap());
+    let uri_map: HashMap<URI, u64> = [(uri.clone(), 0)].into_iter().collect();
+    let ec = EcParams { k: 2, n: 3 };
+
+    let snapshot = Snapshot {
+        table: asura::Cluster::new().dump_table(),
+        uri_map: uri_map.clone(),
+        next_id: 1,
+        version: 2,
+        ec,
+        durability: Durability::All,
+        storage_classes: [("a".to_string(), ec)].into_iter().collect(),
+    };
+    let decoded = Snapshot::decode(&snapshot.encode()).unwrap();
+    assert!(decoded.uri_map == uri_map);
+    assert_eq!((decoded.next_id, decoded.version), (1, 2));
+    assert_eq!(decoded.ec, ec);
+    assert_eq!(decoded.durability, Durability::All);
+    assert_eq!(decoded.storage_classes, snapshot.storage_classes);
+
+    // Written with the storage classes but without the version.
+    let decoded = Snapshot::decode(&bincode::serialize(&snapshot).unwrap()).unwrap();
+    assert_eq!(decoded.durability, Durability::All);
+    assert_eq!(decoded.storage_classes, snapshot.storage_classes);
+
+    // The snapshot of the baseline has no parameters.
+    let v0 = SnapshotV0 {
+        table: asura::Cluster::new().dump_table(),
+        uri_map: uri_map.clone(),
+        next_id: 1,
+        version: 2,
+    };
+    let decoded = Snapshot::decode(&bincode::serialize(&v0).unwrap()).unwrap();
+    assert!(decoded.uri_map == uri_map);
+    assert_eq!((decoded.next_id, decoded.version), (1, 2));
+    assert_eq!(decoded.ec, EcParams::default());
+    assert_eq!(decoded.durability, Durability::default());
+    assert!(decoded.storage_classes.is_empty());
+
+    let v0 = SnapshotV0Ec {
+        table: asura::Cluster::new().dump_table(),
+        uri_map: uri_map.clone(),
+        next_id: 1,
+        version: 2,
+        ec,
+    };
+    let decoded = Snapshot::decode(&bincode::serialize(&v0).unwrap()).unwrap();
+    assert_eq!(decoded.ec, ec);
+    assert_eq!(decoded.durability, Durability::default());
+
+    let v0 = SnapshotV0Durability {
+        table: asura::Cluster::new().dump_table(),
+        uri_map: uri_map.clone(),
+        next_id: 1,
+        version: 2,
+        ec,
+        durability: Durability::All,
+    };
+    let decoded = Snapshot::decode(&bincode::serialize(&v0).unwrap()).unwrap();
+    assert_eq!(decoded.ec, ec);
+    assert_eq!(decoded.durability, Durability::All);
+    assert!(decoded.storage_classes.is_empty());
+
+    assert!(Snapshot::decode(&[1, 2, 3]).is_err());
+    let mut b = snapshot.encode();
+    b[SNAPSHOT_MAGIC.len()] = SNAPSHOT_VERSION + 1;
+    assert!(Snapshot::decode(&b).is_err());
+}
@@ -0,0 +1,71 @@
+use crate::*;
+
+use std::time::Duration;
+
+/// Number of keys listed at once from the piece store.
+const LIST_LIMIT: usize = 1000;
+
+#[norpc::service]
+trait Reaper {
+    fn run_once() -> anyhow::Result<()>;
+}
+define_client!(Reaper);
+
+pub fn spawn(piece_store_cli: piece_store::ClientT) -> ClientT {
+    use norpc::runtime::tokio::*;
+    let svc = App { piece_store_cli };
+    let svc = ReaperService::new(svc);
+    let (chan, server) = ServerBuilder::new(svc).build();
+    tokio::spawn(server.serve());
+    ReaperClient::new(chan)
+}
+
+pub fn spawn_tick(mut reaper_cli: ClientT, interval: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            reaper_cli.run_once().await.ok();
+        }
+    });
+}
+
+struct App {
+    piece_store_cli: piece_store::ClientT,
+}
+#[norpc::async_trait]
+impl Reaper for App {
+    /// Drop the local pieces of the expired objects.
+    /// Every holder finds the same expiry in its own pieces
+    /// so no tombstone or coordination with other nodes is needed.
+    /// A piece moved or rebuilt after the expiry is dropped in the next round.
+    ///
+    /// A round reads the header of every local piece, listing the keys page by page,
+    /// so its cost grows with the number of pieces, not with the number of expired ones.
+    /// The expired objects are already read as missing
+    /// so the round can be run rarely at the cost of keeping their pieces longer.
+    async fn run_once(&self) -> anyhow::Result<()> {
+        let mut piece_store_cli = self.piece_store_cli.clone();
+        let now = unix_time();
+        let mut start_after = None;
+        loop {
+            let page = piece_store_cli
+                .list_keys(String::new(), start_after, LIST_LIMIT)
+                .await?;
+            let n = page.len();
+            start_after = page.last().cloned();
+            for key in page {
+                // Only the headers are read.
+                // A piece dropped by a corrupted header is rebuilt from the others.
+                let headers = piece_store_cli.get_piece_headers(key.clone()).await?;
+                if headers.iter().any(|(_, part)| piece::is_expired(part, now)) {
+                    piece_store_cli.delete_pieces(key).await?;
+                }
+            }
+            if n < LIST_LIMIT {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
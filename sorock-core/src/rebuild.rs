@@ -1,4 +1,6 @@
 use crate::*;
+use cpu_pool::{CpuPool, Priority};
+use piece::PieceHeader;
 use std::time::Duration;
 
 pub struct Rebuild {
@@ -6,67 +8,118 @@ pub struct Rebuild {
     pub peer_out_cli: peer_out::ClientT,
     pub with_parity: bool,
     pub fallback_broadcast: bool,
+    /// Ask the holders found missing their pieces to rebuild them.
+    pub read_repair: bool,
+    /// How long to wait for a piece before requesting another one.
+    pub hedge_delay: Duration,
+    pub cpu_pool: CpuPool,
+    pub priority: Priority,
+}
+
+/// Default of the hedge delay.
+pub const HEDGE_DELAY: Duration = Duration::from_millis(50);
+
+/// Request the piece of the index from its holder.
+/// Returns the holder and the piece if the holder answered.
+async fn request_piece(
+    mut peer_out_cli: peer_out::ClientT,
+    key: String,
+    index: usize,
+    uri: Option<Uri>,
+) -> (usize, Option<(Uri, Option<Piece>)>) {
+    let uri = match uri {
+        Some(uri) => uri,
+        None => return (index, None),
+    };
+    let loc = PieceLocator {
+        key,
+        index: index as u8,
+    };
+    let fut = peer_out_cli.request_piece(uri.clone(), loc);
+    match tokio::time::timeout(Duration::from_secs(5), fut).await {
+        Ok(Ok(piece)) => (index, Some((uri, piece))),
+        _ => (index, None),
+    }
 }
 impl Rebuild {
-    pub async fn rebuild(self, key: String) -> anyhow::Result<Vec<Vec<u8>>> {
-        let holders = self.cluster.compute_holders(key.clone(), N);
-        let mut futs = vec![];
-        for i in 0..N {
-            let mut peer_out_cli = self.peer_out_cli.clone();
-            let key = key.clone();
-            let uri = holders[i as usize].clone();
-            let fut = async move {
-                match uri {
-                    Some(uri) => peer_out_cli.request_any_pieces(uri, key).await,
-                    None => {
-                        anyhow::bail!("failed to compute the holder node key={}", &key);
-                    }
-                }
-            };
-            let fut = tokio::time::timeout(Duration::from_secs(5), fut);
-            futs.push(fut);
-        }
+    /// Returns the header and the shards. The headers are stripped from the shards.
+    /// The pieces are decoded with the parameters recorded in their headers.
+    pub async fn rebuild(
+        self,
+        key: String,
+    ) -> std::result::Result<(PieceHeader, Vec<Bytes>), Error> {
+        // The holders under the largest n hold the pieces of any storage class.
+        // Objects written before the cluster changed may be found only by broadcasting.
+        let n = self.cluster.max_n();
+        let holders = self.cluster.compute_holders(key.clone(), n);
+        // k is a guess until the first piece tells the parameters of the object.
+        let default_k = self.cluster.ec().k;
 
-        let stream = futures::stream::iter(futs);
-        let mut buffered = stream.buffer_unordered(N);
-        let mut data = vec![None; N];
-        let mut n_found = 0;
-        while let Some(rep) = buffered.next().await {
-            if rep.is_err() {
-                continue;
-            }
-            let rep = rep.unwrap();
-            if rep.is_err() {
-                continue;
-            }
-            let pieces = rep.unwrap();
-            for (i, piece_data) in pieces {
-                if data[i as usize] == None {
-                    data[i as usize] = Some(piece_data);
-                    n_found += 1;
-                }
-            }
-            if n_found >= K {
-                use reed_solomon_erasure::galois_8::ReedSolomon;
-                let r = ReedSolomon::new(K, N - K).unwrap();
-                if self.with_parity {
-                    r.reconstruct(&mut data).unwrap();
-                } else {
-                    r.reconstruct_data(&mut data).unwrap();
-                    for _ in 0..(N - K) {
-                        data.pop();
+        // The data pieces are requested first because they don't need decoding.
+        // A parity piece is requested for every failed request
+        // and for every request that doesn't finish within the hedge delay.
+        // The first k pieces win and the other requests are cancelled.
+        let request = |index: usize| {
+            request_piece(
+                self.peer_out_cli.clone(),
+                key.clone(),
+                index,
+                holders[index].clone(),
+            )
+        };
+        let mut futs = futures::stream::FuturesUnordered::new();
+        let mut next_index = 0;
+        while next_index < std::cmp::min(default_k, n) {
+            futs.push(request(next_index));
+            next_index += 1;
+        }
+        // Zero period panics.
+        let hedge_delay = std::cmp::max(self.hedge_delay, Duration::from_millis(1));
+        let mut hedge =
+            tokio::time::interval_at(tokio::time::Instant::now() + hedge_delay, hedge_delay);
+        let mut shards = Shards::new();
+        // Holders that answered without the piece of their index.
+        let mut missing = vec![];
+        while !futs.is_empty() {
+            tokio::select! {
+                Some((index, rep)) = futs.next() => {
+                    match rep {
+                        Some((_, Some(piece))) => shards.add(index as u8, piece),
+                        Some((uri, None)) => missing.push((index, uri)),
+                        None => {}
+                    }
+                    if let Some((header, data)) = shards.take_if_ready() {
+                        self.repair(&key, missing, &header);
+                        let data = self.reconstruct(header.ec, data).await?;
+                        return Ok((header, data));
+                    }
+                    // Keep enough requests in flight to find k pieces.
+                    // This also replaces the failed request.
+                    let (k, n) = shards.ec().map(|ec| (ec.k, ec.n)).unwrap_or((default_k, n));
+                    while futs.len() + shards.n_found < k && next_index < n {
+                        futs.push(request(next_index));
+                        next_index += 1;
                     }
                 }
-                let mut out = vec![];
-                for x in data {
-                    out.push(x.unwrap());
+                _ = hedge.tick() => {
+                    let n = shards.ec().map(|ec| ec.n).unwrap_or(n);
+                    let n_pending = futs.len();
+                    for _ in 0..n_pending {
+                        if next_index < n {
+                            futs.push(request(next_index));
+                            next_index += 1;
+                        }
+                    }
                 }
-                return Ok(out);
             }
         }
+        drop(futs);
 
         if !self.fallback_broadcast {
-            anyhow::bail!("couldn't find enough piece without broadcasting.");
+            return Err(Error::Unavailable(format!(
+                "couldn't find enough piece without broadcasting (key={})",
+                key
+            )));
         }
 
         // broadcast (fallback)
@@ -81,47 +134,259 @@ impl Rebuild {
             futs.push(fut);
         }
         let stream = futures::stream::iter(futs);
-        let n_par = std::thread::available_parallelism().unwrap().get() * 2;
+        let n_par = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            * 2;
         let mut buffered = stream.buffer_unordered(n_par);
-        let mut data = vec![None; N];
-        let mut n_found = 0;
+        let mut shards = Shards::new();
+        let mut n_failed = 0;
         while let Some(rep) = buffered.next().await {
             if rep.is_err() {
+                n_failed += 1;
                 continue;
             }
             let rep = rep.unwrap();
             if rep.is_err() {
+                n_failed += 1;
                 continue;
             }
             let pieces = rep.unwrap();
-            for piece in pieces {
-                let (index, piece_data) = piece;
-                if data[index as usize] == None {
-                    data[index as usize] = Some(piece_data);
-                    n_found += 1;
-                }
+            for (index, piece) in pieces {
+                shards.add(index, piece);
             }
-            if n_found >= K {
-                use reed_solomon_erasure::galois_8::ReedSolomon;
-                let r = ReedSolomon::new(K, N - K).unwrap();
-                if self.with_parity {
-                    r.reconstruct(&mut data).unwrap();
-                } else {
-                    r.reconstruct_data(&mut data).unwrap();
-                    for _ in 0..(N - K) {
-                        data.pop();
-                    }
-                }
-                let mut out = vec![];
-                for x in data {
-                    out.push(x.unwrap());
-                }
-                return Ok(out);
+            if let Some((header, data)) = shards.take_if_ready() {
+                self.repair(&key, missing, &header);
+                let data = self.reconstruct(header.ec, data).await?;
+                return Ok((header, data));
             }
         }
 
-        anyhow::bail!("couldn't rebuild pieces (key={}). data is lost", key);
+        // The missing pieces may be in the nodes that didn't respond.
+        if n_failed > 0 {
+            return Err(Error::Unavailable(format!(
+                "{} nodes didn't respond (key={})",
+                n_failed, key
+            )));
+        }
+        if shards.header.is_none() {
+            return Err(Error::NotFound(key));
+        }
+        Err(Error::DataLoss(key))
+    }
+    /// Decoding is CPU-heavy so it runs in the pool.
+    async fn reconstruct(
+        &self,
+        ec: EcParams,
+        data: Vec<Option<Bytes>>,
+    ) -> anyhow::Result<Vec<Bytes>> {
+        let with_parity = self.with_parity;
+        // Replicas are restored by copying.
+        if ec.k == 1 || !needs_decoding(ec, &data, with_parity) {
+            return reconstruct(ec, data, with_parity);
+        }
+        self.cpu_pool
+            .run(self.priority, move || reconstruct(ec, data, with_parity))
+            .await
     }
+    /// Queue the rebuilds of the missing pieces in their holders
+    /// so frequently read objects heal without waiting for a membership change.
+    /// The read doesn't wait for the requests.
+    fn repair(&self, key: &str, missing: Vec<(usize, Uri)>, header: &PieceHeader) {
+        if !self.read_repair {
+            return;
+        }
+        let version = self.cluster.version();
+        // The holders were computed with the largest n
+        // so the indices beyond the object's n aren't pieces.
+        for (i, uri) in missing.into_iter().filter(|(i, _)| *i < header.ec.n) {
+            let mut peer_out_cli = self.peer_out_cli.clone();
+            let send_piece = SendPiece {
+                version,
+                loc: PieceLocator {
+                    key: key.to_string(),
+                    index: i as u8,
+                },
+                data: None,
+            };
+            tokio::spawn(async move {
+                peer_out_cli.send_piece(uri, send_piece).await.ok();
+            });
+        }
+    }
+}
+
+/// Shards collected from the pieces of an object.
+struct Shards {
+    header: Option<PieceHeader>,
+    data: Vec<Option<Bytes>>,
+    n_found: usize,
+}
+impl Shards {
+    fn new() -> Self {
+        Self {
+            header: None,
+            data: vec![],
+            n_found: 0,
+        }
+    }
+    fn add(&mut self, index: u8, piece: Piece) {
+        // A corrupted piece is treated as missing.
+        if !piece.verify() {
+            return;
+        }
+        let (piece_header, shard) = match piece::decode_bytes(&piece.data) {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+        let ec = piece_header.ec;
+        let index = index as usize;
+        if index >= ec.n {
+            return;
+        }
+        match &self.header {
+            None => {
+                self.data = vec![None; ec.n];
+                self.header = Some(piece_header);
+            }
+            // Pieces coded with different parameters or of another generation
+            // of the key can't be mixed.
+            Some(header) if header.ec != ec || header.len != piece_header.len => return,
+            Some(_) => {}
+        }
+        if self.data[index] != None {
+            return;
+        }
+        // The codec needs the shards of the same length.
+        let first = self.data.iter().flatten().next();
+        if matches!(first, Some(first) if first.len() != shard.len()) {
+            return;
+        }
+        self.data[index] = Some(shard);
+        self.n_found += 1;
+    }
+    /// Parameters of the object if any piece is found.
+    fn ec(&self) -> Option<EcParams> {
+        self.header.as_ref().map(|header| header.ec)
+    }
+    /// Returns the header and the shards if enough pieces are found.
+    fn take_if_ready(&mut self) -> Option<(PieceHeader, Vec<Option<Bytes>>)> {
+        let ec = self.header.as_ref()?.ec;
+        if self.n_found < ec.k {
+            return None;
+        }
+        let data = std::mem::take(&mut self.data);
+        Some((self.header.take().unwrap(), data))
+    }
+}
+
+/// Number of the shards returned.
+fn n_needed(ec: EcParams, with_parity: bool) -> usize {
+    if with_parity {
+        ec.n
+    } else {
+        ec.k
+    }
+}
+
+/// False if all the shards to return are found.
+fn needs_decoding(ec: EcParams, data: &[Option<Bytes>], with_parity: bool) -> bool {
+    data[..n_needed(ec, with_parity)]
+        .iter()
+        .any(|x| x.is_none())
+}
+
+/// Restore the missing shards from any k shards.
+/// The parity shards are dropped unless `with_parity`.
+/// The shards found are returned without copying.
+fn reconstruct(
+    ec: EcParams,
+    data: Vec<Option<Bytes>>,
+    with_parity: bool,
+) -> anyhow::Result<Vec<Bytes>> {
+    use reed_solomon_erasure::galois_8::ReedSolomon;
+    if !needs_decoding(ec, &data, with_parity) {
+        return Ok(data
+            .into_iter()
+            .take(n_needed(ec, with_parity))
+            .map(|x| x.unwrap())
+            .collect());
+    }
+
+    // Every shard is a copy of the data.
+    if ec.k == 1 {
+        let found = match data.iter().flatten().next() {
+            Some(found) => found.clone(),
+            None => anyhow::bail!("no shard is found"),
+        };
+        return Ok(data
+            .into_iter()
+            .take(n_needed(ec, with_parity))
+            .map(|x| x.unwrap_or_else(|| found.clone()))
+            .collect());
+    }
+
+    // The codec needs mutable buffers.
+    let mut data: Vec<Option<Vec<u8>>> = data.into_iter().map(|x| x.map(|x| x.to_vec())).collect();
+    let r = ReedSolomon::new(ec.k, ec.n - ec.k)?;
+    let res = if with_parity {
+        r.reconstruct(&mut data)
+    } else {
+        r.reconstruct_data(&mut data)
+    };
+    res.map_err(|e| anyhow::anyhow!("failed to reconstruct the shards: {:?}", e))?;
+    data.truncate(n_needed(ec, with_parity));
+    Ok(data.into_iter().map(|x| Bytes::from(x.unwrap())).collect())
+}
+
+#[test]
+fn test_reconstruct() {
+    let ec = EcParams { k: 2, n: 3 };
+    let data = vec![Bytes::from(vec![1, 2]), Bytes::from(vec![3, 4])];
+    let mut parity = vec![vec![0; 2]];
+    let r = reed_solomon_erasure::galois_8::ReedSolomon::new(2, 1).unwrap();
+    r.encode_sep(&data, &mut parity).unwrap();
+    let parity = Bytes::from(parity.pop().unwrap());
+
+    // The data shards are returned as they are.
+    let out = reconstruct(
+        ec,
+        vec![Some(data[0].clone()), Some(data[1].clone()), None],
+        false,
+    )
+    .unwrap();
+    assert_eq!(out, data);
+    assert_eq!(out[0].as_ptr(), data[0].as_ptr());
+
+    let out = reconstruct(
+        ec,
+        vec![None, Some(data[1].clone()), Some(parity.clone())],
+        false,
+    )
+    .unwrap();
+    assert_eq!(out, data);
+    let out = reconstruct(
+        ec,
+        vec![Some(data[0].clone()), None, Some(parity.clone())],
+        true,
+    )
+    .unwrap();
+    assert_eq!(out, vec![data[0].clone(), data[1].clone(), parity.clone()]);
+
+    // Shards of different lengths are an error, not a panic.
+    let res = reconstruct(
+        ec,
+        vec![None, Some(data[1].clone()), Some(Bytes::from(vec![0; 3]))],
+        false,
+    );
+    assert!(res.is_err());
+
+    // Replicas are restored from any copy.
+    let ec = EcParams { k: 1, n: 3 };
+    let out = reconstruct(ec, vec![None, None, Some(data[0].clone())], true).unwrap();
+    assert_eq!(out, vec![data[0].clone(); 3]);
+    let out = reconstruct(ec, vec![None, Some(data[0].clone()), None], false).unwrap();
+    assert_eq!(out, vec![data[0].clone()]);
 }
 
 #[test]
@@ -142,3 +407,31 @@ fn test_reed_solomon_huge_data() {
         assert_eq!(parity[i], vec![1; 1 << n]);
     }
 }
+
+#[test]
+fn test_shards_reject_mismatch() {
+    let header = PieceHeader {
+        len: 4,
+        ec: EcParams { k: 2, n: 3 },
+        kind: piece::ObjectKind::Data,
+        meta: ObjectMeta::default(),
+    };
+    let mut shards = Shards::new();
+    shards.add(0, Piece::new(piece::encode(&header, &[1, 2])));
+    // A shard of another length is dropped.
+    shards.add(1, Piece::new(piece::encode(&header, &[3, 4, 5])));
+    assert_eq!(shards.n_found, 1);
+    // A piece of another generation is dropped.
+    let other = PieceHeader {
+        len: 6,
+        ..header.clone()
+    };
+    shards.add(1, Piece::new(piece::encode(&other, &[3, 4, 5])));
+    assert_eq!(shards.n_found, 1);
+    assert!(shards.take_if_ready().is_none());
+
+    shards.add(1, Piece::new(piece::encode(&header, &[3, 4])));
+    let (got, data) = shards.take_if_ready().unwrap();
+    assert_eq!(got, header);
+    assert_eq!(data[1], Some(Bytes::from(vec![3, 4])));
+}
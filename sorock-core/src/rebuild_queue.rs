@@ -1,10 +1,11 @@
 use crate::*;
 
+use cpu_pool::{CpuPool, Priority};
 use rebuild::Rebuild;
 use stabilizer::StabilizeTask;
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 
 #[norpc::service]
@@ -48,15 +49,35 @@ pub fn spawn_tick(mut rebuild_queue_cli: ClientT, interval: Duration) {
 pub struct RebuildTask {
     pub loc: PieceLocator,
 }
+
+/// A task failed this many times is dropped.
+/// The piece is queued again by the next stabilization or read.
+const MAX_ATTEMPTS: u32 = 10;
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Delay before retrying a task failed `n_failed` times.
+fn backoff(n_failed: u32) -> Duration {
+    let d = MIN_BACKOFF * 2u32.saturating_pow(n_failed.saturating_sub(1));
+    std::cmp::min(d, MAX_BACKOFF)
+}
+
+struct Attempt {
+    n_failed: u32,
+    next_at: Instant,
+}
+
 pub struct State {
     cluster: RwLock<ClusterMap>,
-    queue: RwLock<HashSet<RebuildTask>>,
+    queue: RwLock<HashMap<PieceLocator, Attempt>>,
+    cpu_pool: CpuPool,
 }
 impl State {
-    pub fn new() -> Self {
+    pub fn new(cpu_pool: CpuPool) -> Self {
         Self {
             cluster: RwLock::new(ClusterMap::new()),
-            queue: RwLock::new(HashSet::new()),
+            queue: RwLock::new(HashMap::new()),
+            cpu_pool,
         }
     }
 }
@@ -70,48 +91,89 @@ struct App {
 #[norpc::async_trait]
 impl RebuildQueue for App {
     async fn flush_queue(&self) {
-        let cur_queue: Vec<RebuildTask> = self.state.queue.write().await.drain().collect();
+        // The tasks waiting for the backoff are left in the queue.
+        let now = Instant::now();
+        let cur_queue: Vec<(PieceLocator, u32)> = {
+            let mut queue = self.state.queue.write().await;
+            let ready: Vec<PieceLocator> = queue
+                .iter()
+                .filter(|(_, attempt)| attempt.next_at <= now)
+                .map(|(loc, _)| loc.clone())
+                .collect();
+            ready
+                .into_iter()
+                .map(|loc| {
+                    let attempt = queue.remove(&loc).unwrap();
+                    (loc, attempt.n_failed)
+                })
+                .collect()
+        };
         // eprintln!("flush_queue: len = {}", cur_queue.len());
 
         let cur_cluster = self.state.cluster.read().await.clone();
 
-        let futs = cur_queue.into_iter().map(|task| {
-            let loc = task.loc;
+        let futs = cur_queue.into_iter().map(|(loc, n_failed)| {
             let exec = ExecRebuild {
                 peer_out_cli: self.peer_out_cli.clone(),
                 piece_store_cli: self.piece_store_cli.clone(),
                 stabilizer_cli: self.stabilizer_cli.clone(),
                 cur_cluster: cur_cluster.clone(),
+                cpu_pool: self.state.cpu_pool.clone(),
             };
-            exec.exec(loc)
+            async move { (exec.exec(loc).await, n_failed) }
         });
 
         let mut failed_tasks = vec![];
         let stream = futures::stream::iter(futs);
-        let n_par = std::thread::available_parallelism().unwrap().get() * 2;
+        let n_par = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            * 2;
         let mut buffered = stream.buffer_unordered(n_par);
-        while let Some(rep) = buffered.next().await {
+        while let Some((rep, n_failed)) = buffered.next().await {
             match rep {
                 Ok(()) => {}
+                // Nothing to rebuild.
+                Err(RebuildError::NotFound(_)) => {}
                 Err(RebuildError::Failed(loc)) => {
-                    let task = RebuildTask { loc };
-                    failed_tasks.push(task);
+                    let n_failed = n_failed + 1;
+                    if n_failed >= MAX_ATTEMPTS {
+                        eprintln!("gave up rebuilding piece: {:?}", &loc);
+                        continue;
+                    }
+                    failed_tasks.push((loc, n_failed));
                 }
             }
         }
         drop(buffered);
 
-        // Requeue the failed tasks.
+        // Requeue the failed tasks after the backoff.
+        let now = Instant::now();
         let mut queue = self.state.queue.write().await;
-        for x in failed_tasks {
-            queue.insert(x);
+        for (loc, n_failed) in failed_tasks {
+            queue.insert(
+                loc,
+                Attempt {
+                    n_failed,
+                    next_at: now + backoff(n_failed),
+                },
+            );
         }
     }
     async fn set_new_cluster(&self, cluster: ClusterMap) {
         *self.state.cluster.write().await = cluster;
     }
+    /// A task already queued keeps its backoff.
     async fn queue_task(&self, task: RebuildTask) {
-        self.state.queue.write().await.insert(task);
+        self.state
+            .queue
+            .write()
+            .await
+            .entry(task.loc)
+            .or_insert(Attempt {
+                n_failed: 0,
+                next_at: Instant::now(),
+            });
     }
 }
 
@@ -119,6 +181,9 @@ impl RebuildQueue for App {
 pub enum RebuildError {
     #[error("failed")]
     Failed(PieceLocator),
+    /// The key is not found or deleted. The task is dropped.
+    #[error("not found")]
+    NotFound(PieceLocator),
 }
 
 struct ExecRebuild {
@@ -126,9 +191,20 @@ struct ExecRebuild {
     stabilizer_cli: stabilizer::ClientT,
     peer_out_cli: peer_out::ClientT,
     piece_store_cli: piece_store::ClientT,
+    cpu_pool: CpuPool,
 }
 impl ExecRebuild {
     async fn exec(mut self, loc: PieceLocator) -> std::result::Result<(), RebuildError> {
+        // Never rebuild a piece of a deleted key.
+        let tombstone = self
+            .piece_store_cli
+            .get_tombstone(loc.key.clone())
+            .await
+            .map_err(|_| RebuildError::Failed(loc.clone()))?;
+        if tombstone.is_some() {
+            return Err(RebuildError::NotFound(loc));
+        }
+
         let check_exists = self
             .piece_store_cli
             .piece_exists(loc.clone())
@@ -147,15 +223,26 @@ impl ExecRebuild {
                     cluster: self.cur_cluster,
                     with_parity: true,
                     fallback_broadcast: true,
+                    read_repair: false,
+                    hedge_delay: rebuild::HEDGE_DELAY,
+                    cpu_pool: self.cpu_pool.clone(),
+                    // Rebuilding a lost piece shouldn't slow down the user requests.
+                    priority: Priority::Background,
                 };
                 let key = loc.key.clone();
-                let mut pieces = rebuild
-                    .rebuild(key)
-                    .await
-                    .map_err(|_| RebuildError::Failed(loc.clone()))?;
-                let piece_data = pieces.swap_remove(loc.index as usize);
+                let (header, mut pieces) = match rebuild.rebuild(key).await {
+                    Ok(x) => x,
+                    Err(Error::NotFound(_)) => return Err(RebuildError::NotFound(loc)),
+                    Err(_) => return Err(RebuildError::Failed(loc)),
+                };
+                // The object doesn't have such piece.
+                if loc.index as usize >= pieces.len() {
+                    return Ok(());
+                }
+                let shard = pieces.swap_remove(loc.index as usize);
+                let piece = Piece::new(piece::encode(&header, &shard));
                 self.piece_store_cli
-                    .put_piece(loc.clone(), piece_data.into())
+                    .put_piece(loc.clone(), piece)
                     .await
                     .map_err(|_| RebuildError::Failed(loc.clone()))?;
 
@@ -169,3 +256,12 @@ impl ExecRebuild {
         }
     }
 }
+
+#[test]
+fn test_backoff() {
+    assert_eq!(backoff(1), Duration::from_secs(1));
+    assert_eq!(backoff(2), Duration::from_secs(2));
+    assert_eq!(backoff(4), Duration::from_secs(8));
+    assert_eq!(backoff(MAX_ATTEMPTS), MAX_BACKOFF);
+    assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+}
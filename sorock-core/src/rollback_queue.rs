@@ -0,0 +1,114 @@
+use crate::*;
+
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+#[norpc::service]
+trait RollbackQueue {
+    fn flush_queue();
+    fn set_new_cluster(cluster: ClusterMap);
+    fn queue_task(task: RollbackTask);
+}
+define_client!(RollbackQueue);
+
+pub fn spawn(peer_out_cli: peer_out::ClientT, state: State) -> ClientT {
+    use norpc::runtime::tokio::*;
+    let svc = App {
+        peer_out_cli,
+        state,
+    };
+    let svc = RollbackQueueService::new(svc);
+    let (chan, server) = ServerBuilder::new(svc).build();
+    tokio::spawn(server.serve());
+    RollbackQueueClient::new(chan)
+}
+
+pub fn spawn_tick(mut rollback_queue_cli: ClientT, interval: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            rollback_queue_cli.flush_queue().await;
+        }
+    });
+}
+
+/// A holder that may have some pieces of a failed write.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct RollbackTask {
+    pub to: Uri,
+    pub key: String,
+    /// Unix time in seconds when the write failed.
+    pub failed_at: u64,
+}
+pub struct State {
+    cluster: RwLock<ClusterMap>,
+    queue: RwLock<HashSet<RollbackTask>>,
+}
+impl State {
+    pub fn new() -> Self {
+        Self {
+            cluster: RwLock::new(ClusterMap::new()),
+            queue: RwLock::new(HashSet::new()),
+        }
+    }
+}
+
+struct App {
+    peer_out_cli: peer_out::ClientT,
+    state: State,
+}
+#[norpc::async_trait]
+impl RollbackQueue for App {
+    /// Send the tombstones again to the holders that were unreachable.
+    /// A holder removed from the cluster has lost its pieces so the task is dropped.
+    async fn flush_queue(&self) {
+        let cur_queue: Vec<RollbackTask> = self.state.queue.write().await.drain().collect();
+        let members = self.state.cluster.read().await.members();
+
+        let futs = cur_queue
+            .into_iter()
+            .filter(|task| members.contains(&task.to))
+            .map(|task| {
+                let mut peer_out_cli = self.peer_out_cli.clone();
+                async move {
+                    let tombstone = Tombstone {
+                        key: task.key.clone(),
+                        deleted_at: task.failed_at,
+                    };
+                    let fut = peer_out_cli.send_tombstone(task.to.clone(), tombstone);
+                    match tokio::time::timeout(Duration::from_secs(5), fut).await {
+                        Ok(Ok(())) => None,
+                        _ => Some(task),
+                    }
+                }
+            });
+
+        let stream = futures::stream::iter(futs);
+        let n_par = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            * 2;
+        let mut buffered = stream.buffer_unordered(n_par);
+        let mut failed_tasks = vec![];
+        while let Some(rep) = buffered.next().await {
+            if let Some(task) = rep {
+                failed_tasks.push(task);
+            }
+        }
+        drop(buffered);
+
+        // Requeue the failed tasks.
+        let mut queue = self.state.queue.write().await;
+        for x in failed_tasks {
+            queue.insert(x);
+        }
+    }
+    async fn set_new_cluster(&self, cluster: ClusterMap) {
+        *self.state.cluster.write().await = cluster;
+    }
+    async fn queue_task(&self, task: RollbackTask) {
+        self.state.queue.write().await.insert(task);
+    }
+}
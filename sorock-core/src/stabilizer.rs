@@ -100,7 +100,10 @@ impl Stabilizer for App {
 
         let mut failed_tasks = vec![];
         let stream = futures::stream::iter(futs);
-        let n_par = std::thread::available_parallelism().unwrap().get() * 2;
+        let n_par = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            * 2;
         let mut buffered = stream.buffer_unordered(n_par);
         while let Some(rep) = buffered.next().await {
             match rep {
@@ -130,6 +133,11 @@ impl Stabilizer for App {
         for key in keys {
             init_queue.insert(StabilizeTask { key });
         }
+        // Tombstones should be moved to the new holders as well.
+        let tombstones = self.piece_store_cli.clone().tombstones().await?;
+        for tombstone in tombstones {
+            init_queue.insert(StabilizeTask { key: tombstone.key });
+        }
         *self.state.queue.write().await = init_queue.into_iter().collect();
 
         Ok(())
@@ -150,10 +158,24 @@ struct ExecStabilize {
 }
 impl ExecStabilize {
     async fn exec(self, key: String) -> std::result::Result<(), StabilizeError> {
-        let placements = self.cur_cluster.compute_holders(key.clone(), N);
+        let tombstone = self
+            .piece_store_cli
+            .clone()
+            .get_tombstone(key.clone())
+            .await
+            .map_err(|_| StabilizeError::Failed(key.clone()))?;
+        if let Some(tombstone) = tombstone {
+            return self.exec_tombstone(tombstone).await;
+        }
+
+        let n = self
+            .object_n(key.clone())
+            .await
+            .map_err(|_| StabilizeError::Failed(key.clone()))?;
+        let placements = self.cur_cluster.compute_holders(key.clone(), n);
         // dbg!(&old_placement, &new_placement);
         let mut actions = vec![];
-        for index in 0..N {
+        for index in 0..n {
             let holder = &placements[index as usize];
             if let Some(holder) = holder {
                 // Sending piece to myself will results in deleting the piece
@@ -178,10 +200,24 @@ impl ExecStabilize {
             let mut peer_out_cli = self.peer_out_cli.clone();
             let cluster_version = self.cur_cluster.version();
             let fut = async move {
-                let data = piece_store_cli
-                    .get_piece(loc.clone())
-                    .await
-                    .map_err(|_| SendPieceError::Failed)?;
+                // A corrupted piece is never moved.
+                // It is dropped and the new holder is asked to rebuild it instead.
+                let data = match piece_store_cli.get_piece(loc.clone()).await {
+                    Err(e) if piece_store::is_corrupted(&e) => {
+                        eprintln!("corrupted piece: {:?}", &loc);
+                        piece_store_cli.delete_piece(loc.clone()).await.ok();
+                        None
+                    }
+                    data => data.map_err(|_| SendPieceError::Failed)?,
+                };
+                let data = match data {
+                    Some(piece) if !piece.verify() => {
+                        eprintln!("corrupted piece: {:?}", &loc);
+                        piece_store_cli.delete_piece(loc.clone()).await.ok();
+                        None
+                    }
+                    data => data,
+                };
                 if let Some(data) = data {
                     // eprintln!("found send-piece some");
                     // Sending piece to myself will results in deleting the piece
@@ -193,7 +229,7 @@ impl ExecStabilize {
                                 SendPiece {
                                     version: cluster_version,
                                     loc: loc.clone(),
-                                    data: Some(data.into()),
+                                    data: Some(data),
                                 },
                             )
                             .await?;
@@ -225,15 +261,89 @@ impl ExecStabilize {
         }
 
         let stream = futures::stream::iter(futs);
-        let n_par = std::thread::available_parallelism().unwrap().get() * 2;
+        let n_par = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            * 2;
         let mut buffered = stream.buffer_unordered(n_par);
+        let mut deleted = false;
         while let Some(rep) = buffered.next().await {
             match rep {
                 Ok(()) => {}
                 Err(SendPieceError::Rejected) => return Err(StabilizeError::Rejected),
+                Err(SendPieceError::Deleted) => deleted = true,
                 _ => return Err(StabilizeError::Failed(key)),
             }
         }
+        drop(buffered);
+
+        // This node is lagging behind the deletion.
+        // Leave a tombstone and drop the remaining pieces.
+        if deleted {
+            let tombstone = Tombstone {
+                key: key.clone(),
+                deleted_at: unix_time(),
+            };
+            self.drop_pieces(tombstone)
+                .await
+                .map_err(|_| StabilizeError::Failed(key))?;
+        }
+        Ok(())
+    }
+    async fn exec_tombstone(self, tombstone: Tombstone) -> std::result::Result<(), StabilizeError> {
+        let key = tombstone.key.clone();
+        // The key may be of any storage class.
+        let placements = self
+            .cur_cluster
+            .compute_holders(key.clone(), self.cur_cluster.max_n());
+        let mut dests = HashSet::new();
+        for holder in placements.into_iter().flatten() {
+            if holder != self.this_uri {
+                dests.insert(holder);
+            }
+        }
+
+        let mut futs = vec![];
+        for to in dests {
+            let mut peer_out_cli = self.peer_out_cli.clone();
+            let tombstone = tombstone.clone();
+            let fut = async move { peer_out_cli.send_tombstone(to, tombstone).await };
+            futs.push(fut);
+        }
+        let stream = futures::stream::iter(futs);
+        let n_par = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            * 2;
+        let mut buffered = stream.buffer_unordered(n_par);
+        while let Some(rep) = buffered.next().await {
+            if rep.is_err() {
+                return Err(StabilizeError::Failed(key));
+            }
+        }
+        drop(buffered);
+
+        self.drop_pieces(tombstone)
+            .await
+            .map_err(|_| StabilizeError::Failed(key))?;
+        Ok(())
+    }
+    /// Number of pieces the object was split into.
+    /// It is found in the header of the local pieces.
+    async fn object_n(&self, key: String) -> anyhow::Result<usize> {
+        let headers = self.piece_store_cli.clone().get_piece_headers(key).await?;
+        for (_, part) in headers {
+            if let Ok(header) = piece::decode_header_part(&part) {
+                return Ok(header.ec.n);
+            }
+        }
+        Ok(self.cur_cluster.ec().n)
+    }
+    async fn drop_pieces(&self, tombstone: Tombstone) -> anyhow::Result<()> {
+        let mut piece_store_cli = self.piece_store_cli.clone();
+        let key = tombstone.key.clone();
+        piece_store_cli.put_tombstone(tombstone).await?;
+        piece_store_cli.delete_pieces(key).await?;
         Ok(())
     }
 }
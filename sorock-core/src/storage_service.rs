@@ -3,19 +3,276 @@ use crate::*;
 mod proto_compiled {
     tonic::include_proto!("sorock");
 }
+use bytes::BytesMut;
+use cpu_pool::{CpuPool, Priority};
+use io_front::Object;
+use manifest::{Manifest, Part};
 use proto_compiled::{
-    sorock_server::Sorock, AddNodeReq, ConfigRep, ConfigReq, CreateReq, DeleteReq, IndexedPiece,
-    PieceExistsRep, PieceExistsReq, ReadRep, ReadReq, RemoveNodeReq, RequestAnyPiecesRep,
-    RequestAnyPiecesReq, RequestPieceRep, RequestPieceReq, SanityCheckRep, SanityCheckReq,
-    SendPieceRep, SendPieceReq,
+    sorock_server::Sorock, AbortMultipartReq, AddNodeReq, BatchCreateRep, BatchCreateReq,
+    BatchReadEntry, BatchReadRep, BatchReadReq, BatchStatus, CompleteMultipartReq,
+    CompressionStats, ConfigRep, ConfigReq, CpuPoolStats, CreateRep, CreateReq, CreateStreamReq,
+    DeleteReq, DeleteStorageClassReq, HeadRep, HeadReq, IndexedPiece, InitiateMultipartRep,
+    InitiateMultipartReq, KeyExistsRep, KeyExistsReq, KeyVersions, ListRep, ListReq,
+    ListStorageClassesRep, ListVersionsRep, ListVersionsReq, PieceExistsRep, PieceExistsReq,
+    PutRep, PutReq, ReadRangeReq, ReadRep, ReadReq, RemoveNodeReq, RequestAnyPiecesRep,
+    RequestAnyPiecesReq, RequestKeysRep, RequestKeysReq, RequestPieceHeaderRep, RequestPieceRep,
+    RequestPieceReq, RequestPiecesRep, RequestPiecesReq, RequestVersionsRep, RequestVersionsReq,
+    SanityCheckRep, SanityCheckReq, SendPieceRep, SendPieceReq, SendPiecesRep, SendPiecesReq,
+    SendTombstoneReq, SetDurabilityReq, SetEcParamsReq, StatsRep, StorageClass, UploadPartRep,
+    UploadPartReq, VersionPieces,
 };
+use std::collections::BTreeMap;
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::transport::{Channel, Endpoint};
 
+/// Number of keys in a message of List.
+const LIST_PAGE_SIZE: usize = 1000;
+
+impl From<Error> for tonic::Status {
+    fn from(e: Error) -> Self {
+        let message = e.to_string();
+        match e {
+            Error::NotFound(_) => tonic::Status::not_found(message),
+            Error::InvalidArgument(_) => tonic::Status::invalid_argument(message),
+            Error::Unavailable(_) => tonic::Status::unavailable(message),
+            Error::DataLoss(_) => tonic::Status::data_loss(message),
+            Error::Internal(_) => tonic::Status::internal(message),
+        }
+    }
+}
+
+/// Ok has the version id.
+fn batch_status(key: String, res: std::result::Result<String, Error>) -> BatchStatus {
+    match res {
+        Ok(version_id) => BatchStatus {
+            key,
+            code: tonic::Code::Ok as i32,
+            message: String::new(),
+            version_id,
+        },
+        Err(e) => {
+            let status = tonic::Status::from(e);
+            BatchStatus {
+                key,
+                code: status.code() as i32,
+                message: status.message().to_string(),
+                version_id: String::new(),
+            }
+        }
+    }
+}
+
+/// Empty version id means the latest version.
+fn version_id(version_id: String) -> Option<String> {
+    if version_id.is_empty() {
+        None
+    } else {
+        Some(version_id)
+    }
+}
+
+/// `None` means the default of the cluster.
+fn durability(x: i32) -> Result<Option<Durability>, tonic::Status> {
+    use proto_compiled::Durability as D;
+    match D::from_i32(x) {
+        Some(D::Default) => Ok(None),
+        Some(D::K) => Ok(Some(Durability::K)),
+        Some(D::KPlusOne) => Ok(Some(Durability::KPlusOne)),
+        Some(D::All) => Ok(Some(Durability::All)),
+        None => Err(tonic::Status::invalid_argument("unknown durability.")),
+    }
+}
+
+fn compression(x: i32) -> Result<Option<Compression>, tonic::Status> {
+    use proto_compiled::Compression as C;
+    match C::from_i32(x) {
+        Some(C::Default) => Ok(None),
+        Some(C::None) => Ok(Some(Compression::None)),
+        Some(C::Zstd) => Ok(Some(Compression::Zstd)),
+        Some(C::Lz4) => Ok(Some(Compression::Lz4)),
+        None => Err(tonic::Status::invalid_argument("unknown compression.")),
+    }
+}
+
+/// The options of an entry are checked the same as Create.
+fn create_entry(req: CreateReq) -> std::result::Result<io_front::CreateEntry, Error> {
+    if manifest::is_internal_key(&req.key) {
+        return Err(reserved_key_error(&req.key));
+    }
+    let invalid = |e: tonic::Status| Error::InvalidArgument(e.message().to_string());
+    Ok(io_front::CreateEntry {
+        key: req.key,
+        value: req.data,
+        user_meta: req.metadata.into_iter().collect(),
+        expires_at: if req.expires_at == 0 {
+            None
+        } else {
+            Some(req.expires_at)
+        },
+        durability: durability(req.durability).map_err(invalid)?,
+        storage_class: req.storage_class,
+        compression: compression(req.compression).map_err(invalid)?,
+    })
+}
+
+fn cpu_pool_stats(stats: cpu_pool::Stats) -> CpuPoolStats {
+    CpuPoolStats {
+        queued: stats.queued,
+        running: stats.running,
+        completed: stats.completed,
+        wait_micros: stats.wait_micros,
+        run_micros: stats.run_micros,
+    }
+}
+
+fn compression_stats(stats: compression::Stats) -> CompressionStats {
+    CompressionStats {
+        compressed: stats.compressed,
+        original_bytes: stats.original_bytes,
+        stored_bytes: stats.stored_bytes,
+    }
+}
+
+fn reserved_key_error(key: &str) -> Error {
+    Error::InvalidArgument(format!("the key is reserved (key={})", key))
+}
+
+/// Write the stream as stripes tied by a manifest.
+/// The stripes written are pushed to `parts` so they can be rolled back on failure.
+async fn create_stream(
+    cli: &mut io_front::ClientT,
+    stream: &mut tonic::Streaming<CreateStreamReq>,
+    parts: &mut Vec<Part>,
+) -> Result<CreateRep, tonic::Status> {
+    let stream_id = versioning::new_version_id();
+
+    // The object is split into stripes so that only one stripe
+    // is held in memory at a time.
+    let mut key = None;
+    let mut user_meta = BTreeMap::new();
+    let mut buf = BytesMut::new();
+    while let Some(req) = stream.message().await? {
+        if key.is_none() {
+            if manifest::is_internal_key(&req.key) {
+                return Err(tonic::Status::invalid_argument("the key is reserved."));
+            }
+            key = Some(req.key);
+            user_meta = req.metadata.into_iter().collect();
+        }
+        let key = key.as_ref().unwrap();
+        buf.extend_from_slice(&req.data);
+        while buf.len() >= manifest::STRIPE_SIZE {
+            let stripe = buf.split_to(manifest::STRIPE_SIZE).freeze();
+            let part = Part {
+                key: manifest::stripe_key(key, &stream_id, parts.len()),
+                len: stripe.len() as u64,
+                checksum: crc32c::crc32c(&stripe),
+            };
+            cli.create(
+                part.key.clone(),
+                stripe,
+                BTreeMap::new(),
+                None,
+                None,
+                String::new(),
+                None,
+            )
+            .await?;
+            parts.push(part);
+        }
+    }
+    let key = key.ok_or_else(|| tonic::Status::invalid_argument("the stream is empty."))?;
+
+    // Small object doesn't need a manifest.
+    if parts.is_empty() {
+        let (version_id, n_pieces) = cli
+            .create(
+                key,
+                buf.freeze(),
+                user_meta,
+                None,
+                None,
+                String::new(),
+                None,
+            )
+            .await?;
+        return Ok(CreateRep {
+            version_id,
+            n_pieces: n_pieces as u32,
+        });
+    }
+
+    if !buf.is_empty() {
+        let stripe = buf.freeze();
+        let part = Part {
+            key: manifest::stripe_key(&key, &stream_id, parts.len()),
+            len: stripe.len() as u64,
+            checksum: crc32c::crc32c(&stripe),
+        };
+        cli.create(
+            part.key.clone(),
+            stripe,
+            BTreeMap::new(),
+            None,
+            None,
+            String::new(),
+            None,
+        )
+        .await?;
+        parts.push(part);
+    }
+    let manifest = Manifest {
+        parts: parts.clone(),
+    };
+    let (version_id, n_pieces) = cli.create_manifest(key, manifest, user_meta).await?;
+    Ok(CreateRep {
+        version_id,
+        n_pieces: n_pieces as u32,
+    })
+}
+
+fn send_piece_from_req(req: SendPieceReq) -> SendPiece {
+    SendPiece {
+        version: req.version,
+        loc: PieceLocator {
+            key: req.key,
+            index: req.index as u8,
+        },
+        data: req.data.map(|data| Piece {
+            data,
+            checksum: req.checksum,
+        }),
+    }
+}
+
+fn send_piece_error_code(res: std::result::Result<(), SendPieceError>) -> i32 {
+    match res {
+        Ok(()) => 0,
+        Err(SendPieceError::Rejected) => -1,
+        Err(SendPieceError::Failed) => -2,
+        Err(SendPieceError::Deleted) => -3,
+    }
+}
+
+fn request_piece_rep(piece: Option<Piece>) -> RequestPieceRep {
+    match piece {
+        Some(piece) => RequestPieceRep {
+            data: Some(piece.data),
+            checksum: piece.checksum,
+        },
+        None => RequestPieceRep {
+            data: None,
+            checksum: 0,
+        },
+    }
+}
+
 pub struct Server {
     io_front_cli: io_front::ClientT,
     peer_in_cli: peer_in::ClientT,
     self_chan: Channel,
     cap_tib: f64,
+    cpu_pool: CpuPool,
 }
 impl Server {
     pub fn new(
@@ -23,6 +280,7 @@ impl Server {
         peer_in_cli: peer_in::ClientT,
         uri: Uri,
         cap_tib: f64,
+        cpu_pool: CpuPool,
     ) -> Self {
         let e = Endpoint::new(uri).unwrap();
         let self_chan = e.connect_lazy();
@@ -31,6 +289,7 @@ impl Server {
             peer_in_cli,
             self_chan,
             cap_tib,
+            cpu_pool,
         }
     }
 }
@@ -41,9 +300,11 @@ impl Sorock for Server {
         req: tonic::Request<ReadReq>,
     ) -> Result<tonic::Response<ReadRep>, tonic::Status> {
         let req = req.into_inner();
+        if manifest::is_internal_key(&req.key) {
+            return Err(tonic::Status::invalid_argument("the key is reserved."));
+        }
         let mut cli = self.io_front_cli.clone();
-        let key = req.key;
-        let res = cli.read(key).await.unwrap();
+        let res = cli.read(req.key, version_id(req.version_id)).await?;
         let rep = ReadRep { data: res };
         Ok(tonic::Response::new(rep))
     }
@@ -52,30 +313,377 @@ impl Sorock for Server {
         req: tonic::Request<SanityCheckReq>,
     ) -> Result<tonic::Response<SanityCheckRep>, tonic::Status> {
         let req = req.into_inner();
+        if manifest::is_internal_key(&req.key) {
+            return Err(tonic::Status::invalid_argument("the key is reserved."));
+        }
         let mut cli = self.io_front_cli.clone();
         let key = req.key;
-        let n_lost = cli.sanity_check(key).await.unwrap();
+        let n_lost = cli.sanity_check(key).await?;
         let rep = SanityCheckRep {
             n_lost: n_lost as u32,
         };
         Ok(tonic::Response::new(rep))
     }
+    async fn stats(
+        &self,
+        _: tonic::Request<()>,
+    ) -> Result<tonic::Response<StatsRep>, tonic::Status> {
+        let rep = StatsRep {
+            foreground: Some(cpu_pool_stats(self.cpu_pool.stats(Priority::Foreground))),
+            background: Some(cpu_pool_stats(self.cpu_pool.stats(Priority::Background))),
+            compression: Some(compression_stats(
+                self.io_front_cli.clone().compression_stats().await,
+            )),
+        };
+        Ok(tonic::Response::new(rep))
+    }
     async fn create(
         &self,
         request: tonic::Request<CreateReq>,
-    ) -> Result<tonic::Response<()>, tonic::Status> {
+    ) -> Result<tonic::Response<CreateRep>, tonic::Status> {
         let req = request.into_inner();
         let mut cli = self.io_front_cli.clone();
         let key = req.key;
+        if manifest::is_internal_key(&key) {
+            return Err(tonic::Status::invalid_argument("the key is reserved."));
+        }
         let data = req.data;
-        cli.create(key, data).await.unwrap();
+        let user_meta = req.metadata.into_iter().collect();
+        let expires_at = if req.expires_at == 0 {
+            None
+        } else {
+            Some(req.expires_at)
+        };
+        let durability = durability(req.durability)?;
+        let compression = compression(req.compression)?;
+        let (version_id, n_pieces) = cli
+            .create(
+                key,
+                data,
+                user_meta,
+                expires_at,
+                durability,
+                req.storage_class,
+                compression,
+            )
+            .await?;
+        Ok(tonic::Response::new(CreateRep {
+            version_id,
+            n_pieces: n_pieces as u32,
+        }))
+    }
+    async fn batch_create(
+        &self,
+        request: tonic::Request<BatchCreateReq>,
+    ) -> Result<tonic::Response<BatchCreateRep>, tonic::Status> {
+        let req = request.into_inner();
+        let mut cli = self.io_front_cli.clone();
+        // The entries rejected here aren't sent to the io front.
+        let mut checked = vec![];
+        let mut entries = vec![];
+        for entry in req.entries {
+            let key = entry.key.clone();
+            match create_entry(entry) {
+                Ok(entry) => {
+                    entries.push(entry);
+                    checked.push((key, None));
+                }
+                Err(e) => checked.push((key, Some(e))),
+            }
+        }
+        let mut results = cli.batch_create(entries).await.into_iter();
+        let mut statuses = vec![];
+        for (key, err) in checked {
+            let res = match err {
+                Some(e) => Err(e),
+                None => results.next().unwrap(),
+            };
+            statuses.push(batch_status(key, res));
+        }
+        Ok(tonic::Response::new(BatchCreateRep { statuses }))
+    }
+    async fn batch_read(
+        &self,
+        request: tonic::Request<BatchReadReq>,
+    ) -> Result<tonic::Response<BatchReadRep>, tonic::Status> {
+        let req = request.into_inner();
+        let mut cli = self.io_front_cli.clone();
+        let valid_keys = req
+            .keys
+            .iter()
+            .filter(|key| !manifest::is_internal_key(key))
+            .cloned()
+            .collect();
+        let mut results = cli.batch_read(valid_keys).await.into_iter();
+        let mut entries = vec![];
+        for key in req.keys {
+            let res = if manifest::is_internal_key(&key) {
+                Err(reserved_key_error(&key))
+            } else {
+                results.next().unwrap()
+            };
+            let entry = match res {
+                Ok(data) => BatchReadEntry {
+                    status: Some(batch_status(key, Ok(String::new()))),
+                    data,
+                },
+                Err(e) => BatchReadEntry {
+                    status: Some(batch_status(key, Err(e))),
+                    data: Bytes::new(),
+                },
+            };
+            entries.push(entry);
+        }
+        Ok(tonic::Response::new(BatchReadRep { entries }))
+    }
+    async fn put(
+        &self,
+        request: tonic::Request<PutReq>,
+    ) -> Result<tonic::Response<PutRep>, tonic::Status> {
+        let req = request.into_inner();
+        let mut cli = self.io_front_cli.clone();
+        let algorithm = match proto_compiled::HashAlgorithm::from_i32(req.algorithm) {
+            Some(proto_compiled::HashAlgorithm::Sha256) => io_front::HashAlgorithm::Sha256,
+            Some(proto_compiled::HashAlgorithm::Blake3) => io_front::HashAlgorithm::Blake3,
+            None => return Err(tonic::Status::invalid_argument("unknown hash algorithm.")),
+        };
+        let user_meta = req.metadata.into_iter().collect();
+        let (key, version_id, created) = cli.put(req.data, algorithm, user_meta).await?;
+        Ok(tonic::Response::new(PutRep {
+            key,
+            created,
+            version_id,
+        }))
+    }
+    async fn initiate_multipart(
+        &self,
+        request: tonic::Request<InitiateMultipartReq>,
+    ) -> Result<tonic::Response<InitiateMultipartRep>, tonic::Status> {
+        let req = request.into_inner();
+        let mut cli = self.io_front_cli.clone();
+        if manifest::is_internal_key(&req.key) {
+            return Err(tonic::Status::invalid_argument("the key is reserved."));
+        }
+        let user_meta = req.metadata.into_iter().collect();
+        let upload_id = cli.initiate_multipart(req.key, user_meta).await?;
+        Ok(tonic::Response::new(InitiateMultipartRep { upload_id }))
+    }
+    async fn upload_part(
+        &self,
+        request: tonic::Request<UploadPartReq>,
+    ) -> Result<tonic::Response<UploadPartRep>, tonic::Status> {
+        let req = request.into_inner();
+        let mut cli = self.io_front_cli.clone();
+        if manifest::is_internal_key(&req.key) {
+            return Err(tonic::Status::invalid_argument("the key is reserved."));
+        }
+        let part = cli
+            .upload_part(req.key, req.upload_id, req.part_number, req.data)
+            .await?;
+        Ok(tonic::Response::new(UploadPartRep {
+            checksum: part.checksum,
+        }))
+    }
+    async fn complete_multipart(
+        &self,
+        request: tonic::Request<CompleteMultipartReq>,
+    ) -> Result<tonic::Response<CreateRep>, tonic::Status> {
+        let req = request.into_inner();
+        let mut cli = self.io_front_cli.clone();
+        if manifest::is_internal_key(&req.key) {
+            return Err(tonic::Status::invalid_argument("the key is reserved."));
+        }
+        let (version_id, n_pieces) = cli
+            .complete_multipart(req.key, req.upload_id, req.part_numbers)
+            .await?;
+        Ok(tonic::Response::new(CreateRep {
+            version_id,
+            n_pieces: n_pieces as u32,
+        }))
+    }
+    async fn abort_multipart(
+        &self,
+        request: tonic::Request<AbortMultipartReq>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let req = request.into_inner();
+        let mut cli = self.io_front_cli.clone();
+        if manifest::is_internal_key(&req.key) {
+            return Err(tonic::Status::invalid_argument("the key is reserved."));
+        }
+        cli.abort_multipart(req.key, req.upload_id).await?;
         Ok(tonic::Response::new(()))
     }
+    async fn head(
+        &self,
+        req: tonic::Request<HeadReq>,
+    ) -> Result<tonic::Response<HeadRep>, tonic::Status> {
+        let req = req.into_inner();
+        if manifest::is_internal_key(&req.key) {
+            return Err(tonic::Status::invalid_argument("the key is reserved."));
+        }
+        let mut cli = self.io_front_cli.clone();
+        let meta = cli.head(req.key, version_id(req.version_id)).await?;
+        let rep = HeadRep {
+            size: meta.size,
+            created_at: meta.created_at,
+            checksum: meta.checksum,
+            cluster_version: meta.cluster_version,
+            metadata: meta.user.into_iter().collect(),
+            version_id: meta.version_id,
+            expires_at: meta.expires_at.unwrap_or(0),
+            storage_class: meta.storage_class,
+            compression: match meta.compression {
+                Compression::None => proto_compiled::Compression::None,
+                Compression::Zstd => proto_compiled::Compression::Zstd,
+                Compression::Lz4 => proto_compiled::Compression::Lz4,
+            } as i32,
+        };
+        Ok(tonic::Response::new(rep))
+    }
+    async fn list_versions(
+        &self,
+        req: tonic::Request<ListVersionsReq>,
+    ) -> Result<tonic::Response<ListVersionsRep>, tonic::Status> {
+        let req = req.into_inner();
+        let mut cli = self.io_front_cli.clone();
+        if manifest::is_internal_key(&req.key) {
+            return Err(tonic::Status::invalid_argument("the key is reserved."));
+        }
+        let version_ids = cli.list_versions(req.key).await?;
+        Ok(tonic::Response::new(ListVersionsRep { version_ids }))
+    }
+    async fn create_stream(
+        &self,
+        request: tonic::Request<tonic::Streaming<CreateStreamReq>>,
+    ) -> Result<tonic::Response<CreateRep>, tonic::Status> {
+        let mut stream = request.into_inner();
+        let mut cli = self.io_front_cli.clone();
+        let mut parts = vec![];
+        let res = create_stream(&mut cli, &mut stream, &mut parts).await;
+        // The stripes written are unreachable without the manifest.
+        if res.is_err() && !parts.is_empty() {
+            cli.abort_stream(parts).await;
+        }
+        res.map(tonic::Response::new)
+    }
+    async fn read_range(
+        &self,
+        req: tonic::Request<ReadRangeReq>,
+    ) -> Result<tonic::Response<ReadRep>, tonic::Status> {
+        let req = req.into_inner();
+        if manifest::is_internal_key(&req.key) {
+            return Err(tonic::Status::invalid_argument("the key is reserved."));
+        }
+        let mut cli = self.io_front_cli.clone();
+        let res = cli
+            .read_range(req.key, version_id(req.version_id), req.offset, req.length)
+            .await?;
+        let rep = ReadRep { data: res };
+        Ok(tonic::Response::new(rep))
+    }
+    type ListStream = ReceiverStream<Result<ListRep, tonic::Status>>;
+    async fn list(
+        &self,
+        req: tonic::Request<ListReq>,
+    ) -> Result<tonic::Response<Self::ListStream>, tonic::Status> {
+        let req = req.into_inner();
+        let mut cli = self.io_front_cli.clone();
+        let prefix = req.prefix;
+        let mut start_after = if req.continuation_token.is_empty() {
+            None
+        } else {
+            let token = hex::decode(&req.continuation_token)
+                .ok()
+                .and_then(|x| String::from_utf8(x).ok())
+                .ok_or_else(|| tonic::Status::invalid_argument("invalid continuation token."))?;
+            Some(token)
+        };
+        let max_keys = match req.max_keys {
+            0 => usize::MAX,
+            n => n as usize,
+        };
+
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        tokio::spawn(async move {
+            let mut n_sent = 0;
+            while n_sent < max_keys {
+                let limit = std::cmp::min(LIST_PAGE_SIZE, max_keys - n_sent);
+                let page = cli.list(prefix.clone(), start_after, limit).await;
+                let (keys, next) = match page {
+                    Ok(x) => x,
+                    Err(e) => {
+                        tx.send(Err(e.into())).await.ok();
+                        return;
+                    }
+                };
+                n_sent += keys.len();
+                start_after = next;
+                // The token is returned only when the listing is stopped by max_keys.
+                let next_continuation_token = match &start_after {
+                    Some(x) if n_sent >= max_keys => hex::encode(x),
+                    _ => "".to_string(),
+                };
+                let rep = ListRep {
+                    keys,
+                    next_continuation_token,
+                };
+                if tx.send(Ok(rep)).await.is_err() {
+                    return;
+                }
+                if start_after.is_none() {
+                    return;
+                }
+            }
+        });
+        Ok(tonic::Response::new(ReceiverStream::new(rx)))
+    }
+    type ReadStreamStream = ReceiverStream<Result<ReadRep, tonic::Status>>;
+    async fn read_stream(
+        &self,
+        req: tonic::Request<ReadReq>,
+    ) -> Result<tonic::Response<Self::ReadStreamStream>, tonic::Status> {
+        let req = req.into_inner();
+        if manifest::is_internal_key(&req.key) {
+            return Err(tonic::Status::invalid_argument("the key is reserved."));
+        }
+        let mut cli = self.io_front_cli.clone();
+        let object = cli.read_object(req.key, version_id(req.version_id)).await?;
+
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        match object {
+            Object::Data(data) => {
+                tx.send(Ok(ReadRep { data })).await.ok();
+            }
+            Object::Manifest(manifest) => {
+                // Read the parts one by one so the memory usage is bounded.
+                tokio::spawn(async move {
+                    for part in manifest.parts {
+                        let rep = cli
+                            .read(part.key, None)
+                            .await
+                            .map(|data| ReadRep { data })
+                            .map_err(|e| tonic::Status::from(e.into_part_error()));
+                        let failed = rep.is_err();
+                        if tx.send(rep).await.is_err() || failed {
+                            break;
+                        }
+                    }
+                });
+            }
+        }
+        Ok(tonic::Response::new(ReceiverStream::new(rx)))
+    }
     async fn delete(
         &self,
         request: tonic::Request<DeleteReq>,
     ) -> Result<tonic::Response<()>, tonic::Status> {
-        unimplemented!()
+        let req = request.into_inner();
+        if manifest::is_internal_key(&req.key) {
+            return Err(tonic::Status::invalid_argument("the key is reserved."));
+        }
+        let mut cli = self.io_front_cli.clone();
+        cli.delete(req.key, version_id(req.version_id)).await?;
+        Ok(tonic::Response::new(()))
     }
     async fn ping(
         &self,
@@ -90,8 +698,13 @@ impl Sorock for Server {
         let req = request.into_inner();
 
         // Get cap from the tgt.
-        let tgt_uri: Uri = req.uri.parse().unwrap();
-        let chan_tgt = Endpoint::new(tgt_uri.clone()).unwrap().connect_lazy();
+        let tgt_uri: Uri = req
+            .uri
+            .parse()
+            .map_err(|_| tonic::Status::invalid_argument("invalid uri."))?;
+        let chan_tgt = Endpoint::new(tgt_uri.clone())
+            .map_err(|_| tonic::Status::invalid_argument("invalid uri."))?
+            .connect_lazy();
         let mut cli = proto_compiled::sorock_client::SorockClient::new(chan_tgt);
         let config = cli.request_config(ConfigReq {}).await?.into_inner();
 
@@ -116,15 +729,111 @@ impl Sorock for Server {
         let req = request.into_inner();
         let chan = self.self_chan.clone();
         let mut cli = lol_core::RaftClient::new(chan);
-        let tgt_uri = req.uri.parse().unwrap();
+        let tgt_uri = req
+            .uri
+            .parse()
+            .map_err(|_| tonic::Status::invalid_argument("invalid uri."))?;
         let msg = Command::RemoveNode { uri: URI(tgt_uri) };
         cli.request_commit(lol_core::api::CommitReq {
             message: Command::encode(&msg),
         })
-        .await
-        .unwrap();
+        .await?;
+        Ok(tonic::Response::new(()))
+    }
+    async fn set_ec_params(
+        &self,
+        request: tonic::Request<SetEcParamsReq>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let req = request.into_inner();
+        let ec = EcParams {
+            k: req.k as usize,
+            n: req.n as usize,
+        };
+        let cluster = self.io_front_cli.clone().cluster().await;
+        ec.validate(cluster.members().len())
+            .map_err(|e| tonic::Status::invalid_argument(e.to_string()))?;
+
+        let chan = self.self_chan.clone();
+        let mut cli = lol_core::RaftClient::new(chan);
+        let msg = Command::SetEcParams { ec };
+        cli.request_commit(lol_core::api::CommitReq {
+            message: Command::encode(&msg),
+        })
+        .await?;
+        Ok(tonic::Response::new(()))
+    }
+    async fn set_durability(
+        &self,
+        request: tonic::Request<SetDurabilityReq>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let req = request.into_inner();
+        let durability = durability(req.durability)?.ok_or_else(|| {
+            tonic::Status::invalid_argument("the default of the cluster can't be set.")
+        })?;
+
+        let chan = self.self_chan.clone();
+        let mut cli = lol_core::RaftClient::new(chan);
+        let msg = Command::SetDurability { durability };
+        cli.request_commit(lol_core::api::CommitReq {
+            message: Command::encode(&msg),
+        })
+        .await?;
+        Ok(tonic::Response::new(()))
+    }
+    async fn set_storage_class(
+        &self,
+        request: tonic::Request<StorageClass>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let req = request.into_inner();
+        validate_storage_class_name(&req.name)
+            .map_err(|e| tonic::Status::invalid_argument(e.to_string()))?;
+        let ec = EcParams {
+            k: req.k as usize,
+            n: req.n as usize,
+        };
+        let cluster = self.io_front_cli.clone().cluster().await;
+        ec.validate(cluster.members().len())
+            .map_err(|e| tonic::Status::invalid_argument(e.to_string()))?;
+
+        let chan = self.self_chan.clone();
+        let mut cli = lol_core::RaftClient::new(chan);
+        let msg = Command::SetStorageClass { name: req.name, ec };
+        cli.request_commit(lol_core::api::CommitReq {
+            message: Command::encode(&msg),
+        })
+        .await?;
+        Ok(tonic::Response::new(()))
+    }
+    async fn delete_storage_class(
+        &self,
+        request: tonic::Request<DeleteStorageClassReq>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let req = request.into_inner();
+        let chan = self.self_chan.clone();
+        let mut cli = lol_core::RaftClient::new(chan);
+        let msg = Command::DeleteStorageClass { name: req.name };
+        cli.request_commit(lol_core::api::CommitReq {
+            message: Command::encode(&msg),
+        })
+        .await?;
         Ok(tonic::Response::new(()))
     }
+    async fn list_storage_classes(
+        &self,
+        _: tonic::Request<()>,
+    ) -> Result<tonic::Response<ListStorageClassesRep>, tonic::Status> {
+        let cluster = self.io_front_cli.clone().cluster().await;
+        let classes = cluster
+            .storage_classes()
+            .into_iter()
+            .map(|(name, ec)| StorageClass {
+                name,
+                k: ec.k as u32,
+                n: ec.n as u32,
+            })
+            .collect();
+        Ok(tonic::Response::new(ListStorageClassesRep { classes }))
+    }
     async fn piece_exists(
         &self,
         req: tonic::Request<PieceExistsReq>,
@@ -135,7 +844,7 @@ impl Sorock for Server {
             index: req.index as u8,
         };
         let mut cli = self.peer_in_cli.clone();
-        let rep = cli.piece_exists(loc).await.unwrap();
+        let rep = cli.piece_exists(loc).await?;
         Ok(tonic::Response::new(PieceExistsRep { exists: rep }))
     }
     async fn send_piece(
@@ -144,22 +853,26 @@ impl Sorock for Server {
     ) -> Result<tonic::Response<SendPieceRep>, tonic::Status> {
         let req = request.into_inner();
         let mut cli = self.peer_in_cli.clone();
-        let send_piece = SendPiece {
-            version: req.version,
-            loc: PieceLocator {
-                key: req.key,
-                index: req.index as u8,
-            },
-            data: req.data,
-        };
-        let rep = cli.save_piece(send_piece).await;
-        let error_code = match rep {
-            Ok(()) => 0,
-            Err(SendPieceError::Rejected) => -1,
-            Err(SendPieceError::Failed) => -2,
-        };
+        let rep = cli.save_piece(send_piece_from_req(req)).await;
+        let error_code = send_piece_error_code(rep);
         Ok(tonic::Response::new(SendPieceRep { error_code }))
     }
+    async fn send_pieces(
+        &self,
+        request: tonic::Request<SendPiecesReq>,
+    ) -> Result<tonic::Response<SendPiecesRep>, tonic::Status> {
+        let req = request.into_inner();
+        let mut futs = vec![];
+        for piece_req in req.pieces {
+            let mut cli = self.peer_in_cli.clone();
+            futs.push(async move {
+                let rep = cli.save_piece(send_piece_from_req(piece_req)).await;
+                send_piece_error_code(rep)
+            });
+        }
+        let error_codes = futures::future::join_all(futs).await;
+        Ok(tonic::Response::new(SendPiecesRep { error_codes }))
+    }
     async fn request_piece(
         &self,
         request: tonic::Request<RequestPieceReq>,
@@ -170,8 +883,45 @@ impl Sorock for Server {
             key: req.key,
             index: req.index as u8,
         };
-        let res = cli.find_piece(loc).await.unwrap();
-        let rep = RequestPieceRep { data: res };
+        let res = cli.find_piece(loc).await?;
+        Ok(tonic::Response::new(request_piece_rep(res)))
+    }
+    async fn request_pieces(
+        &self,
+        request: tonic::Request<RequestPiecesReq>,
+    ) -> Result<tonic::Response<RequestPiecesRep>, tonic::Status> {
+        let req = request.into_inner();
+        let mut futs = vec![];
+        for loc_req in req.locs {
+            let mut cli = self.peer_in_cli.clone();
+            let loc = PieceLocator {
+                key: loc_req.key,
+                index: loc_req.index as u8,
+            };
+            futs.push(async move {
+                // A piece failed to be read is reported missing.
+                // The requester falls back to the rebuild.
+                let piece = cli.find_piece(loc).await.ok().flatten();
+                request_piece_rep(piece)
+            });
+        }
+        let pieces = futures::future::join_all(futs).await;
+        Ok(tonic::Response::new(RequestPiecesRep { pieces }))
+    }
+    async fn request_piece_header(
+        &self,
+        request: tonic::Request<RequestPieceReq>,
+    ) -> Result<tonic::Response<RequestPieceHeaderRep>, tonic::Status> {
+        let req = request.into_inner();
+        let mut cli = self.peer_in_cli.clone();
+        let loc = PieceLocator {
+            key: req.key,
+            index: req.index as u8,
+        };
+        let res = cli.find_piece_header(loc).await?;
+        let rep = RequestPieceHeaderRep {
+            header: res.map(|header| piece::encode_header(&header)),
+        };
         Ok(tonic::Response::new(rep))
     }
     async fn request_any_pieces(
@@ -181,17 +931,74 @@ impl Sorock for Server {
         let mut cli = self.peer_in_cli.clone();
         let req = req.into_inner();
         let key = req.key;
-        let rep = cli.find_any_pieces(key).await.unwrap();
+        let rep = cli.find_any_pieces(key).await?;
         let mut pieces = vec![];
-        for (i, data) in rep {
+        for (i, piece) in rep {
             pieces.push(IndexedPiece {
                 index: i as u32,
-                data,
+                data: piece.data,
+                checksum: piece.checksum,
             });
         }
         let out = RequestAnyPiecesRep { pieces };
         Ok(tonic::Response::new(out))
     }
+    async fn send_tombstone(
+        &self,
+        request: tonic::Request<SendTombstoneReq>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let req = request.into_inner();
+        let mut cli = self.peer_in_cli.clone();
+        let tombstone = Tombstone {
+            key: req.key,
+            deleted_at: req.deleted_at,
+        };
+        cli.save_tombstone(tombstone).await?;
+        Ok(tonic::Response::new(()))
+    }
+    async fn key_exists(
+        &self,
+        request: tonic::Request<KeyExistsReq>,
+    ) -> Result<tonic::Response<KeyExistsRep>, tonic::Status> {
+        let req = request.into_inner();
+        let mut cli = self.peer_in_cli.clone();
+        let exists = cli.key_exists(req.key).await?;
+        Ok(tonic::Response::new(KeyExistsRep { exists }))
+    }
+    async fn request_keys(
+        &self,
+        request: tonic::Request<RequestKeysReq>,
+    ) -> Result<tonic::Response<RequestKeysRep>, tonic::Status> {
+        let req = request.into_inner();
+        let mut cli = self.peer_in_cli.clone();
+        let keys = cli
+            .find_keys(req.prefix, req.start_after, req.limit as usize)
+            .await?;
+        Ok(tonic::Response::new(RequestKeysRep { keys }))
+    }
+    async fn request_versions(
+        &self,
+        request: tonic::Request<RequestVersionsReq>,
+    ) -> Result<tonic::Response<RequestVersionsRep>, tonic::Status> {
+        let req = request.into_inner();
+        let mut cli = self.peer_in_cli.clone();
+        let found = cli.find_versions(req.keys).await?;
+        let keys = found
+            .into_iter()
+            .map(|versions| KeyVersions {
+                versions: versions
+                    .into_iter()
+                    .map(|(version_id, n_pieces, ec)| VersionPieces {
+                        version_id,
+                        n_pieces: n_pieces as u32,
+                        k: ec.k as u32,
+                        n: ec.n as u32,
+                    })
+                    .collect(),
+            })
+            .collect();
+        Ok(tonic::Response::new(RequestVersionsRep { keys }))
+    }
     async fn request_config(
         &self,
         req: tonic::Request<ConfigReq>,
@@ -0,0 +1,157 @@
+use crate::*;
+
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+#[norpc::service]
+trait TombstoneGc {
+    fn run_once() -> anyhow::Result<()>;
+    fn set_new_cluster(cluster: ClusterMap);
+}
+define_client!(TombstoneGc);
+
+pub fn spawn(
+    piece_store_cli: piece_store::ClientT,
+    peer_out_cli: peer_out::ClientT,
+    state: State,
+) -> ClientT {
+    use norpc::runtime::tokio::*;
+    let svc = App {
+        piece_store_cli,
+        peer_out_cli,
+        state,
+    };
+    let svc = TombstoneGcService::new(svc);
+    let (chan, server) = ServerBuilder::new(svc).build();
+    tokio::spawn(server.serve());
+    TombstoneGcClient::new(chan)
+}
+
+pub fn spawn_tick(mut tombstone_gc_cli: ClientT, interval: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            tombstone_gc_cli.run_once().await.ok();
+        }
+    });
+}
+
+pub struct State {
+    uri: Uri,
+    /// Tombstones younger than this are never collected.
+    grace_period: Duration,
+    cluster: RwLock<ClusterMap>,
+}
+impl State {
+    pub fn new(uri: Uri, grace_period: Duration) -> Self {
+        Self {
+            uri,
+            grace_period,
+            cluster: RwLock::new(ClusterMap::new()),
+        }
+    }
+}
+
+struct App {
+    piece_store_cli: piece_store::ClientT,
+    peer_out_cli: peer_out::ClientT,
+    state: State,
+}
+#[norpc::async_trait]
+impl TombstoneGc for App {
+    async fn run_once(&self) -> anyhow::Result<()> {
+        let tombstones = self.piece_store_cli.clone().tombstones().await?;
+        let cur_cluster = self.state.cluster.read().await.clone();
+        let now = unix_time();
+
+        let futs = tombstones
+            .into_iter()
+            .filter(|tombstone| {
+                now.saturating_sub(tombstone.deleted_at) >= self.state.grace_period.as_secs()
+            })
+            .map(|tombstone| {
+                let exec = ExecGc {
+                    this_uri: self.state.uri.clone(),
+                    peer_out_cli: self.peer_out_cli.clone(),
+                    piece_store_cli: self.piece_store_cli.clone(),
+                    cur_cluster: cur_cluster.clone(),
+                };
+                exec.exec(tombstone)
+            });
+
+        let stream = futures::stream::iter(futs);
+        let n_par = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            * 2;
+        let mut buffered = stream.buffer_unordered(n_par);
+        while buffered.next().await.is_some() {}
+
+        Ok(())
+    }
+    async fn set_new_cluster(&self, cluster: ClusterMap) {
+        *self.state.cluster.write().await = cluster;
+    }
+}
+
+struct ExecGc {
+    this_uri: Uri,
+    peer_out_cli: peer_out::ClientT,
+    piece_store_cli: piece_store::ClientT,
+    cur_cluster: ClusterMap,
+}
+impl ExecGc {
+    /// The tombstone is collected once every holder has confirmed
+    /// that it doesn't have any piece of the key.
+    /// A holder still having some pieces gets the tombstone again.
+    async fn exec(mut self, tombstone: Tombstone) -> anyhow::Result<()> {
+        let key = tombstone.key.clone();
+        // The key may be of any storage class.
+        let n = self.cur_cluster.max_n();
+        let holders = self.cur_cluster.compute_holders(key.clone(), n);
+        let mut dests = HashSet::new();
+        for holder in holders {
+            match holder {
+                Some(holder) => {
+                    if holder != self.this_uri {
+                        dests.insert(holder);
+                    }
+                }
+                None => anyhow::bail!("failed to compute the holder node key={}", &key),
+            }
+        }
+
+        let mut futs = vec![];
+        for to in dests {
+            let mut peer_out_cli = self.peer_out_cli.clone();
+            let tombstone = tombstone.clone();
+            let fut = async move {
+                let exists = peer_out_cli
+                    .key_exists(to.clone(), tombstone.key.clone())
+                    .await?;
+                if exists {
+                    peer_out_cli.send_tombstone(to, tombstone).await?;
+                }
+                Ok::<bool, anyhow::Error>(!exists)
+            };
+            let fut = tokio::time::timeout(Duration::from_secs(5), fut);
+            futs.push(fut);
+        }
+        let stream = futures::stream::iter(futs);
+        let mut buffered = stream.buffer_unordered(n);
+        let mut all_confirmed = true;
+        while let Some(rep) = buffered.next().await {
+            match rep {
+                Ok(Ok(true)) => {}
+                _ => all_confirmed = false,
+            }
+        }
+
+        if all_confirmed {
+            self.piece_store_cli.delete_tombstone(key).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+use crate::*;
+use manifest::INTERNAL_SEP;
+
+/// Version IDs sort in the order they were minted:
+/// microseconds since the epoch followed by random bits to break the ties.
+pub fn new_version_id() -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_micros() as u64;
+    format!("{:016x}{:08x}", now, rand::random::<u32>())
+}
+
+/// Prefix of the keys of all versions of the key.
+pub fn version_prefix(key: &str) -> String {
+    format!("{}{}v{}", key, INTERNAL_SEP, INTERNAL_SEP)
+}
+
+/// Key the version of the object is stored under.
+pub fn version_key(key: &str, version_id: &str) -> String {
+    format!("{}{}", version_prefix(key), version_id)
+}
+
+/// Returns the key and the version id if it is the key of a version.
+pub fn parse_version_key(k: &str) -> Option<(String, String)> {
+    let mut iter = k.split(INTERNAL_SEP);
+    let key = iter.next()?;
+    if iter.next()? != "v" {
+        return None;
+    }
+    let version_id = iter.next()?;
+    if iter.next().is_some() {
+        return None;
+    }
+    Some((key.to_string(), version_id.to_string()))
+}
+
+/// Key to compute the holders from.
+/// All versions of a key are placed on the same nodes
+/// so the versions can be found by asking the holders of the key.
+pub fn placement_key(k: &str) -> &str {
+    match parse_version_key(k) {
+        Some((key, _)) => &k[..key.len()],
+        None => k,
+    }
+}
+
+#[test]
+fn test_version_keys() {
+    let v1 = new_version_id();
+    std::thread::sleep(std::time::Duration::from_millis(1));
+    let v2 = new_version_id();
+    assert_eq!(v1.len(), 24);
+    assert!(v1 < v2);
+    assert!(!manifest::is_internal_key(&v1));
+
+    let k = version_key("a", &v1);
+    assert!(manifest::is_internal_key(&k));
+    assert!(k.starts_with(&version_prefix("a")));
+    assert_eq!(parse_version_key(&k), Some(("a".to_string(), v1.clone())));
+    assert_eq!(placement_key(&k), "a");
+    assert_eq!(placement_key("a"), "a");
+
+    let stripe = manifest::stripe_key("a", &v1, 0);
+    assert_eq!(parse_version_key(&stripe), None);
+    assert_eq!(placement_key(&stripe), stripe);
+    assert_eq!(parse_version_key(&multipart::upload_key("a", &v1)), None);
+}
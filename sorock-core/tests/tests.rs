@@ -26,7 +26,23 @@ async fn start_server(port: u16) {
     let socket = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port));
     let uri = uri(port);
     let peer_out_cli = peer_out::spawn(peer_out::State::new());
-    let io_front_cli = io_front::spawn(peer_out_cli.clone(), io_front::State::new());
+    let cpu_pool = cpu_pool::CpuPool::new(2, 16);
+    let rollback_queue_cli =
+        rollback_queue::spawn(peer_out_cli.clone(), rollback_queue::State::new());
+    rollback_queue::spawn_tick(rollback_queue_cli.clone(), Duration::from_millis(500));
+    let io_front_cli = io_front::spawn(
+        peer_out_cli.clone(),
+        rollback_queue_cli.clone(),
+        io_front::State::new(
+            Duration::from_millis(50),
+            cpu_pool.clone(),
+            // Large objects are compressed.
+            compression::Policy {
+                codec: compression::Compression::Lz4,
+                threshold: 1 << 20,
+            },
+        ),
+    );
     // let piece_store_cli = mem_piece_store::spawn(mem_piece_store::State::new());
     let piece_store_cli = piece_store::sqlite::spawn(
         piece_store::sqlite::State::new(piece_store::sqlite::StoreType::Memory).await,
@@ -37,21 +53,40 @@ async fn start_server(port: u16) {
         stabilizer::State::new(uri.clone()),
     );
     stabilizer::spawn_tick(stabilizer_cli.clone(), Duration::from_millis(100));
+    let reaper_cli = reaper::spawn(piece_store_cli.clone());
+    reaper::spawn_tick(reaper_cli, Duration::from_millis(500));
     let rebuild_queue_cli = rebuild_queue::spawn(
         piece_store_cli.clone(),
         peer_out_cli.clone(),
         stabilizer_cli.clone(),
-        rebuild_queue::State::new(),
+        rebuild_queue::State::new(cpu_pool.clone()),
     );
     rebuild_queue::spawn_tick(rebuild_queue_cli.clone(), Duration::from_millis(500));
+    let tombstone_gc_cli = tombstone_gc::spawn(
+        piece_store_cli.clone(),
+        peer_out_cli.clone(),
+        tombstone_gc::State::new(uri.clone(), Duration::from_secs(1)),
+    );
+    tombstone_gc::spawn_tick(tombstone_gc_cli.clone(), Duration::from_millis(500));
+    let multipart_gc_cli = multipart_gc::spawn(
+        piece_store_cli.clone(),
+        io_front_cli.clone(),
+        multipart_gc::State::new(Duration::from_secs(5)),
+    );
+    multipart_gc::spawn_tick(multipart_gc_cli, Duration::from_millis(500));
     let peer_in_cli = peer_in::spawn(
         piece_store_cli,
         stabilizer_cli.clone(),
         rebuild_queue_cli.clone(),
         peer_in::State::new(),
     );
-    let server =
-        storage_service::Server::new(io_front_cli.clone(), peer_in_cli.clone(), uri.clone(), 1.);
+    let server = storage_service::Server::new(
+        io_front_cli.clone(),
+        peer_in_cli.clone(),
+        uri.clone(),
+        1.,
+        cpu_pool,
+    );
     let svc1 = storage_service::make_service(server).await;
 
     let app_in_cli = fd_app_in_stub::spawn();
@@ -60,6 +95,8 @@ async fn start_server(port: u16) {
         stabilizer_cli,
         peer_in_cli,
         rebuild_queue_cli,
+        rollback_queue_cli,
+        tombstone_gc_cli,
         app_in_cli,
     );
     let raft_app = raft_service::App::new(cluster_in_cli);
@@ -170,6 +207,26 @@ impl Cluster {
         let hdl = self.servers.remove(&uri).unwrap();
         hdl.abort();
     }
+    /// Stop the node without removing it from the cluster.
+    fn crash_node(&mut self, uri: Uri) {
+        let hdl = self.servers.remove(&uri).unwrap();
+        hdl.abort();
+    }
+    /// Keys of the pieces the node holds.
+    async fn request_keys(&self, uri: Uri) -> Vec<String> {
+        let chan = tonic::transport::Endpoint::new(uri)
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::RequestKeysReq {
+            prefix: String::new(),
+            start_after: None,
+            limit: 100,
+        };
+        cli.request_keys(req).await.unwrap().into_inner().keys
+    }
     async fn add_node(&self, uri: Uri) {
         let chan = self.connect().await;
         let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
@@ -187,20 +244,215 @@ impl Cluster {
         };
         cli.remove_node(req).await.unwrap();
     }
+    async fn set_ec_params(&self, k: u32, n: u32) -> Result<(), tonic::Status> {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::SetEcParamsReq { k, n };
+        cli.set_ec_params(req).await?;
+        Ok(())
+    }
     async fn create(&self, key: &str, value: &[u8]) {
         let chan = self.connect().await;
         let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
         let req = proto_compiled::CreateReq {
             key: key.to_string(),
             data: Bytes::copy_from_slice(value),
+            ..Default::default()
+        };
+        cli.create(req).await.unwrap();
+    }
+    async fn create_with_metadata(
+        &self,
+        key: &str,
+        value: &[u8],
+        metadata: HashMap<String, String>,
+    ) {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::CreateReq {
+            key: key.to_string(),
+            data: Bytes::copy_from_slice(value),
+            metadata,
+            ..Default::default()
         };
         cli.create(req).await.unwrap();
     }
+    async fn put(&self, value: &[u8], algorithm: proto_compiled::HashAlgorithm) -> (String, bool) {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::PutReq {
+            data: Bytes::copy_from_slice(value),
+            algorithm: algorithm as i32,
+            ..Default::default()
+        };
+        let rep = cli.put(req).await.unwrap().into_inner();
+        (rep.key, rep.created)
+    }
+    async fn batch_create(&self, entries: &[(String, Vec<u8>)]) -> Vec<tonic::Code> {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::BatchCreateReq {
+            entries: entries
+                .iter()
+                .map(|(k, v)| proto_compiled::CreateReq {
+                    key: k.clone(),
+                    data: Bytes::copy_from_slice(v),
+                    ..Default::default()
+                })
+                .collect(),
+        };
+        let rep = cli.batch_create(req).await.unwrap().into_inner();
+        rep.statuses
+            .into_iter()
+            .map(|st| tonic::Code::from_i32(st.code))
+            .collect()
+    }
+    async fn batch_read(&self, keys: &[String]) -> Vec<(tonic::Code, Vec<u8>)> {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::BatchReadReq {
+            keys: keys.to_vec(),
+        };
+        let rep = cli.batch_read(req).await.unwrap().into_inner();
+        rep.entries
+            .into_iter()
+            .map(|e| {
+                let code = tonic::Code::from_i32(e.status.unwrap().code);
+                (code, e.data.to_vec())
+            })
+            .collect()
+    }
+    async fn initiate_multipart(&self, key: &str) -> String {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::InitiateMultipartReq {
+            key: key.to_string(),
+            ..Default::default()
+        };
+        cli.initiate_multipart(req)
+            .await
+            .unwrap()
+            .into_inner()
+            .upload_id
+    }
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: u32,
+        value: &[u8],
+    ) -> Result<(), tonic::Status> {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::UploadPartReq {
+            key: key.to_string(),
+            upload_id: upload_id.to_string(),
+            part_number,
+            data: Bytes::copy_from_slice(value),
+        };
+        cli.upload_part(req).await?;
+        Ok(())
+    }
+    async fn complete_multipart(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_numbers: Vec<u32>,
+    ) -> Result<(), tonic::Status> {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::CompleteMultipartReq {
+            key: key.to_string(),
+            upload_id: upload_id.to_string(),
+            part_numbers,
+        };
+        cli.complete_multipart(req).await?;
+        Ok(())
+    }
+    async fn abort_multipart(&self, key: &str, upload_id: &str) {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::AbortMultipartReq {
+            key: key.to_string(),
+            upload_id: upload_id.to_string(),
+        };
+        cli.abort_multipart(req).await.unwrap();
+    }
+    async fn head(&self, key: &str) -> proto_compiled::HeadRep {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::HeadReq {
+            key: key.to_string(),
+            ..Default::default()
+        };
+        cli.head(req).await.unwrap().into_inner()
+    }
+    async fn create_stream(&self, key: &str, value: &[u8], chunk_size: usize) {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let mut reqs = vec![];
+        for chunk in value.chunks(chunk_size) {
+            reqs.push(proto_compiled::CreateStreamReq {
+                key: key.to_string(),
+                data: Bytes::copy_from_slice(chunk),
+                ..Default::default()
+            });
+        }
+        cli.create_stream(futures::stream::iter(reqs))
+            .await
+            .unwrap();
+    }
+    async fn read_stream(&self, key: &str) -> Vec<u8> {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::ReadReq {
+            key: key.to_string(),
+            ..Default::default()
+        };
+        let mut stream = cli.read_stream(req).await.unwrap().into_inner();
+        let mut out = vec![];
+        while let Some(rep) = stream.message().await.unwrap() {
+            out.extend_from_slice(&rep.data);
+        }
+        out
+    }
+    async fn read_range(&self, key: &str, offset: u64, length: u64) -> Vec<u8> {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::ReadRangeReq {
+            key: key.to_string(),
+            offset,
+            length,
+            ..Default::default()
+        };
+        let rep = cli.read_range(req).await.unwrap().into_inner();
+        let mut out = vec![];
+        out.extend_from_slice(&rep.data);
+        out
+    }
+    async fn list(&self, prefix: &str, token: &str, max_keys: u32) -> (Vec<String>, String) {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::ListReq {
+            prefix: prefix.to_string(),
+            continuation_token: token.to_string(),
+            max_keys,
+        };
+        let mut stream = cli.list(req).await.unwrap().into_inner();
+        let mut keys = vec![];
+        let mut next_token = "".to_string();
+        while let Some(rep) = stream.message().await.unwrap() {
+            keys.extend(rep.keys);
+            next_token = rep.next_continuation_token;
+        }
+        (keys, next_token)
+    }
     async fn read(&self, key: &str) -> Vec<u8> {
         let chan = self.connect().await;
         let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
         let req = proto_compiled::ReadReq {
             key: key.to_string(),
+            ..Default::default()
         };
         let rep = cli.read(req).await.unwrap().into_inner();
         let mut out = vec![];
@@ -216,8 +468,159 @@ impl Cluster {
         let rep = cli.sanity_check(req).await.unwrap().into_inner();
         rep.n_lost as u8
     }
+    async fn stats(&self) -> proto_compiled::StatsRep {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        cli.stats(()).await.unwrap().into_inner()
+    }
+    async fn try_read(&self, key: &str) -> Option<Vec<u8>> {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::ReadReq {
+            key: key.to_string(),
+            ..Default::default()
+        };
+        let rep = cli.read(req).await.ok()?.into_inner();
+        let mut out = vec![];
+        out.extend_from_slice(&rep.data);
+        Some(out)
+    }
+    async fn create_version(&self, key: &str, value: &[u8]) -> String {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::CreateReq {
+            key: key.to_string(),
+            data: Bytes::copy_from_slice(value),
+            ..Default::default()
+        };
+        cli.create(req).await.unwrap().into_inner().version_id
+    }
+    async fn create_durable(
+        &self,
+        key: &str,
+        value: &[u8],
+        durability: proto_compiled::Durability,
+    ) -> Result<proto_compiled::CreateRep, tonic::Status> {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::CreateReq {
+            key: key.to_string(),
+            data: Bytes::copy_from_slice(value),
+            durability: durability as i32,
+            ..Default::default()
+        };
+        Ok(cli.create(req).await?.into_inner())
+    }
+    async fn set_durability(&self, durability: proto_compiled::Durability) {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::SetDurabilityReq {
+            durability: durability as i32,
+        };
+        cli.set_durability(req).await.unwrap();
+    }
+    async fn set_storage_class(&self, name: &str, k: u32, n: u32) -> Result<(), tonic::Status> {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::StorageClass {
+            name: name.to_string(),
+            k,
+            n,
+        };
+        cli.set_storage_class(req).await?;
+        Ok(())
+    }
+    async fn list_storage_classes(&self) -> Vec<proto_compiled::StorageClass> {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        cli.list_storage_classes(())
+            .await
+            .unwrap()
+            .into_inner()
+            .classes
+    }
+    async fn create_in_class(
+        &self,
+        key: &str,
+        value: &[u8],
+        storage_class: &str,
+    ) -> Result<proto_compiled::CreateRep, tonic::Status> {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::CreateReq {
+            key: key.to_string(),
+            data: Bytes::copy_from_slice(value),
+            storage_class: storage_class.to_string(),
+            ..Default::default()
+        };
+        Ok(cli.create(req).await?.into_inner())
+    }
+    async fn create_compressed(
+        &self,
+        key: &str,
+        value: &[u8],
+        compression: proto_compiled::Compression,
+    ) {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::CreateReq {
+            key: key.to_string(),
+            data: Bytes::copy_from_slice(value),
+            compression: compression as i32,
+            ..Default::default()
+        };
+        cli.create(req).await.unwrap();
+    }
+    async fn create_expiring(&self, key: &str, value: &[u8], expires_at: u64) -> String {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::CreateReq {
+            key: key.to_string(),
+            data: Bytes::copy_from_slice(value),
+            expires_at,
+            ..Default::default()
+        };
+        cli.create(req).await.unwrap().into_inner().version_id
+    }
+    async fn read_version(&self, key: &str, version_id: &str) -> Result<Vec<u8>, tonic::Status> {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::ReadReq {
+            key: key.to_string(),
+            version_id: version_id.to_string(),
+        };
+        let rep = cli.read(req).await?.into_inner();
+        Ok(rep.data.to_vec())
+    }
+    async fn list_versions(&self, key: &str) -> Vec<String> {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::ListVersionsReq {
+            key: key.to_string(),
+        };
+        cli.list_versions(req)
+            .await
+            .unwrap()
+            .into_inner()
+            .version_ids
+    }
+    async fn delete_version(&self, key: &str, version_id: &str) {
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::DeleteReq {
+            key: key.to_string(),
+            version_id: version_id.to_string(),
+        };
+        cli.delete(req).await.unwrap();
+    }
     async fn delete(&self, key: &str) {
-        unimplemented!()
+        let chan = self.connect().await;
+        let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+        let req = proto_compiled::DeleteReq {
+            key: key.to_string(),
+            ..Default::default()
+        };
+        cli.delete(req).await.unwrap();
     }
 }
 
@@ -284,6 +687,198 @@ async fn test_io_1_node() -> anyhow::Result<()> {
         assert_eq!(&read, v);
     }
 
+    // Erasure coding runs in the CPU pool.
+    let stats = cluster.stats().await.foreground.unwrap();
+    assert!(stats.completed >= 200);
+    assert_eq!(stats.queued, 0);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_io_arbitrary_size() -> anyhow::Result<()> {
+    let mut cluster = Cluster::new();
+    for _ in 0..3 {
+        let uri = cluster.up_node().await;
+        cluster.add_node(uri).await;
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let mut dataset = vec![];
+    for len in [0, 1, 2, 3, 5, 7, 17, 1000, 4097] {
+        let v: Vec<u8> = (0..len).map(|_| rand::random::<u8>()).collect();
+        let k = format!("{:x}", md5::compute(&v));
+        dataset.push((k, v));
+    }
+    for (k, v) in &dataset {
+        cluster.create(k, v).await;
+    }
+    for (k, v) in &dataset {
+        let read = cluster.read(k).await;
+        assert_eq!(&read, v);
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_io_stream() -> anyhow::Result<()> {
+    let mut cluster = Cluster::new();
+    for _ in 0..3 {
+        let uri = cluster.up_node().await;
+        cluster.add_node(uri).await;
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    // small object which fits in a stripe.
+    let small: Vec<u8> = (0..1000).map(|_| rand::random::<u8>()).collect();
+    cluster.create_stream("small", &small, 100).await;
+    assert_eq!(cluster.read_stream("small").await, small);
+    assert_eq!(cluster.read("small").await, small);
+
+    // large object which is split into stripes.
+    let len = manifest::STRIPE_SIZE * 2 + 12345;
+    let large: Vec<u8> = (0..len).map(|_| rand::random::<u8>()).collect();
+    cluster.create_stream("large", &large, 1 << 20).await;
+    assert_eq!(cluster.read_stream("large").await, large);
+    assert_eq!(cluster.read("large").await, large);
+    assert_eq!(cluster.sanity_check("large").await, 0);
+
+    cluster.delete("large").await;
+    assert!(cluster.try_read("large").await.is_none());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_read_range() -> anyhow::Result<()> {
+    let mut cluster = Cluster::new();
+    for _ in 0..3 {
+        let uri = cluster.up_node().await;
+        cluster.add_node(uri).await;
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let small: Vec<u8> = (0..1001).map(|_| rand::random::<u8>()).collect();
+    cluster.create("small", &small).await;
+    for (offset, length) in [
+        (0, 10),
+        (0, 1001),
+        (250, 10),
+        (240, 520),
+        (1000, 100),
+        (2000, 1),
+    ] {
+        let start = std::cmp::min(offset, small.len());
+        let end = std::cmp::min(offset + length, small.len());
+        let read = cluster
+            .read_range("small", offset as u64, length as u64)
+            .await;
+        assert_eq!(&read, &small[start..end]);
+    }
+
+    let len = manifest::STRIPE_SIZE * 2 + 12345;
+    let large: Vec<u8> = (0..len).map(|_| rand::random::<u8>()).collect();
+    cluster.create_stream("large", &large, 1 << 20).await;
+    let stripe = manifest::STRIPE_SIZE;
+    for (offset, length) in [
+        (0, 10),
+        (stripe - 5, 10),
+        (stripe * 2, 20000),
+        (100, stripe * 2),
+    ] {
+        let start = std::cmp::min(offset, large.len());
+        let end = std::cmp::min(offset + length, large.len());
+        let read = cluster
+            .read_range("large", offset as u64, length as u64)
+            .await;
+        assert_eq!(&read, &large[start..end]);
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_head() -> anyhow::Result<()> {
+    let mut cluster = Cluster::new();
+    for _ in 0..3 {
+        let uri = cluster.up_node().await;
+        cluster.add_node(uri).await;
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let value: Vec<u8> = (0..1001).map(|_| rand::random::<u8>()).collect();
+    let mut metadata = HashMap::new();
+    metadata.insert("content-type".to_string(), "text/plain".to_string());
+    cluster
+        .create_with_metadata("small", &value, metadata.clone())
+        .await;
+    let rep = cluster.head("small").await;
+    assert_eq!(rep.size, 1001);
+    assert_eq!(rep.checksum, crc32c::crc32c(&value));
+    assert!(rep.created_at > 0);
+    assert!(rep.cluster_version > 0);
+    assert_eq!(rep.metadata, metadata);
+
+    let len = manifest::STRIPE_SIZE + 1;
+    let large: Vec<u8> = (0..len).map(|_| rand::random::<u8>()).collect();
+    cluster.create_stream("large", &large, 1 << 20).await;
+    let rep = cluster.head("large").await;
+    assert_eq!(rep.size, len as u64);
+    assert_eq!(rep.checksum, crc32c::crc32c(&large));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_list() -> anyhow::Result<()> {
+    let mut cluster = Cluster::new();
+    for _ in 0..5 {
+        let uri = cluster.up_node().await;
+        cluster.add_node(uri).await;
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let mut a_keys = vec![];
+    for i in 0..20 {
+        let key = format!("a/{:02}", i);
+        cluster.create(&key, &[i]).await;
+        a_keys.push(key);
+    }
+    for i in 0..10 {
+        cluster.create(&format!("b/{:02}", i), &[i]).await;
+    }
+    // The stripes are hidden.
+    let large = vec![1; manifest::STRIPE_SIZE + 1];
+    cluster.create_stream("c", &large, 1 << 20).await;
+
+    let (keys, token) = cluster.list("", "", 0).await;
+    assert_eq!(keys.len(), 31);
+    assert!(token.is_empty());
+
+    let (keys, token) = cluster.list("a/", "", 0).await;
+    assert_eq!(keys, a_keys);
+    assert!(token.is_empty());
+
+    // pagination
+    let mut paged = vec![];
+    let mut token = "".to_string();
+    loop {
+        let (keys, next_token) = cluster.list("a/", &token, 7).await;
+        assert!(keys.len() <= 7);
+        paged.extend(keys);
+        if next_token.is_empty() {
+            break;
+        }
+        token = next_token;
+    }
+    assert_eq!(paged, a_keys);
+
     Ok(())
 }
 
@@ -545,3 +1140,764 @@ async fn test_changing_cluster() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_delete_10_node() -> anyhow::Result<()> {
+    let mut cluster = Cluster::new();
+    for _ in 0..10 {
+        let uri = cluster.up_node().await;
+        cluster.add_node(uri).await;
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let dataset = prepare_dataset(100);
+    for (k, v) in &dataset {
+        cluster.create(k, v).await;
+    }
+
+    let (deleted, alive) = dataset.split_at(50);
+    for (k, _) in deleted {
+        cluster.delete(k).await;
+    }
+    for (k, _) in deleted {
+        assert!(cluster.try_read(k).await.is_none());
+    }
+    for (k, v) in alive {
+        let read = cluster.read(k).await;
+        assert_eq!(&read, v);
+    }
+
+    // Stabilization shouldn't resurrect the deleted keys.
+    let uri = cluster.up_node().await;
+    cluster.add_node(uri).await;
+    tokio::time::sleep(Duration::from_secs(10)).await;
+
+    for (k, _) in deleted {
+        assert!(cluster.try_read(k).await.is_none());
+    }
+    for (k, v) in alive {
+        let read = cluster.read(k).await;
+        assert_eq!(&read, v);
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_recreate_after_tombstone_gc() -> anyhow::Result<()> {
+    let mut cluster = Cluster::new();
+    for _ in 0..3 {
+        let uri = cluster.up_node().await;
+        cluster.add_node(uri).await;
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let dataset = prepare_dataset(10);
+    for (k, v) in &dataset {
+        cluster.create(k, v).await;
+    }
+    for (k, _) in &dataset {
+        cluster.delete(k).await;
+    }
+
+    // Wait for the tombstones to be collected.
+    tokio::time::sleep(Duration::from_secs(5)).await;
+
+    for (k, v) in &dataset {
+        cluster.create(k, v).await;
+    }
+    for (k, v) in &dataset {
+        let read = cluster.read(k).await;
+        assert_eq!(&read, v);
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_put() -> anyhow::Result<()> {
+    use sha2::Digest;
+
+    let mut cluster = Cluster::new();
+    for _ in 0..3 {
+        let uri = cluster.up_node().await;
+        cluster.add_node(uri).await;
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let value: Vec<u8> = (0..1001).map(|_| rand::random::<u8>()).collect();
+
+    let (key, created) = cluster
+        .put(&value, proto_compiled::HashAlgorithm::Sha256)
+        .await;
+    assert_eq!(key, hex::encode(sha2::Sha256::digest(&value)));
+    assert!(created);
+    assert_eq!(cluster.read(&key).await, value);
+
+    // The same value is not written twice.
+    let (key2, created) = cluster
+        .put(&value, proto_compiled::HashAlgorithm::Sha256)
+        .await;
+    assert_eq!(key2, key);
+    assert!(!created);
+
+    let (key, created) = cluster
+        .put(&value, proto_compiled::HashAlgorithm::Blake3)
+        .await;
+    assert_eq!(key, blake3::hash(&value).to_hex().to_string());
+    assert!(created);
+    assert_eq!(cluster.read(&key).await, value);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_set_ec_params() -> anyhow::Result<()> {
+    let mut cluster = Cluster::new();
+    for _ in 0..5 {
+        let uri = cluster.up_node().await;
+        cluster.add_node(uri).await;
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let old_dataset = prepare_dataset(100);
+    for (k, v) in &old_dataset {
+        cluster.create(k, v).await;
+    }
+
+    // Too few members
+    assert!(cluster.set_ec_params(5, 6).await.is_err());
+    assert!(cluster.set_ec_params(3, 3).await.is_err());
+    cluster.set_ec_params(3, 5).await.unwrap();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let new_dataset = prepare_dataset(100);
+    for (k, v) in &new_dataset {
+        cluster.create(k, v).await;
+    }
+
+    // Objects written under both parameters are moved to the new node.
+    let uri = cluster.up_node().await;
+    cluster.add_node(uri).await;
+    tokio::time::sleep(Duration::from_secs(10)).await;
+
+    for (k, v) in old_dataset.iter().chain(new_dataset.iter()) {
+        assert_eq!(cluster.sanity_check(k).await, 0);
+        let read = cluster.read(k).await;
+        assert_eq!(&read, v);
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_error_codes() -> anyhow::Result<()> {
+    let mut cluster = Cluster::new();
+    for _ in 0..3 {
+        let uri = cluster.up_node().await;
+        cluster.add_node(uri).await;
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let chan = cluster.connect().await;
+    let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+
+    let err = cli
+        .read(proto_compiled::ReadReq {
+            key: "a".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::NotFound);
+
+    let err = cli
+        .head(proto_compiled::HeadReq {
+            key: "a".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::NotFound);
+
+    let err = cli
+        .create(proto_compiled::CreateReq {
+            key: "a\0stripe\00".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+    // The internal keys can't be read or deleted directly.
+    let err = cli
+        .read(proto_compiled::ReadReq {
+            key: "a\0stripe\00".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    let err = cli
+        .head(proto_compiled::HeadReq {
+            key: "a\0stripe\00".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    let err = cli
+        .delete(proto_compiled::DeleteReq {
+            key: "a\0stripe\00".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+    let err = cli
+        .remove_node(proto_compiled::RemoveNodeReq {
+            uri: "not a uri".to_string(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_multipart() -> anyhow::Result<()> {
+    let mut cluster = Cluster::new();
+    for _ in 0..4 {
+        let uri = cluster.up_node().await;
+        cluster.add_node(uri).await;
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let mut parts = vec![];
+    for i in 0..5 {
+        parts.push(vec![i as u8; 1000 + i * 100]);
+    }
+    let mut whole = vec![];
+    for part in &parts {
+        whole.extend_from_slice(part);
+    }
+
+    let upload_id = cluster.initiate_multipart("a").await;
+    // Parts can be uploaded in any order and in parallel.
+    let mut futs = vec![];
+    for (i, part) in parts.iter().enumerate().rev() {
+        futs.push(cluster.upload_part("a", &upload_id, i as u32 + 1, part));
+    }
+    for r in futures::future::join_all(futs).await {
+        r.unwrap();
+    }
+    // Re-uploading a part replaces it.
+    cluster
+        .upload_part("a", &upload_id, 3, &[9; 500])
+        .await
+        .unwrap();
+    cluster
+        .upload_part("a", &upload_id, 3, &parts[2])
+        .await
+        .unwrap();
+
+    // The object isn't visible until it is completed.
+    assert!(cluster.try_read("a").await.is_none());
+    let err = cluster
+        .complete_multipart("a", &upload_id, vec![2, 1])
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    let err = cluster
+        .complete_multipart("a", &upload_id, vec![1, 6])
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+    cluster
+        .complete_multipart("a", &upload_id, vec![1, 2, 3, 4, 5])
+        .await
+        .unwrap();
+    assert_eq!(cluster.read("a").await, whole);
+    assert_eq!(cluster.read_stream("a").await, whole);
+    assert_eq!(cluster.read_range("a", 900, 300).await, &whole[900..1200]);
+    assert_eq!(cluster.head("a").await.size, whole.len() as u64);
+    let (keys, _) = cluster.list("", "", 100).await;
+    assert_eq!(keys, vec!["a".to_string()]);
+
+    // The upload is gone after completion.
+    let err = cluster
+        .upload_part("a", &upload_id, 1, &parts[0])
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::NotFound);
+
+    cluster.delete("a").await;
+    assert!(cluster.try_read("a").await.is_none());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_multipart_abort() -> anyhow::Result<()> {
+    let mut cluster = Cluster::new();
+    for _ in 0..4 {
+        let uri = cluster.up_node().await;
+        cluster.add_node(uri).await;
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let upload_id = cluster.initiate_multipart("a").await;
+    cluster
+        .upload_part("a", &upload_id, 1, &[1; 100])
+        .await
+        .unwrap();
+    cluster.abort_multipart("a", &upload_id).await;
+    let err = cluster
+        .upload_part("a", &upload_id, 2, &[2; 100])
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::NotFound);
+    let err = cluster
+        .complete_multipart("a", &upload_id, vec![1])
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::NotFound);
+    assert!(cluster.try_read("a").await.is_none());
+
+    // Uploads left behind are aborted by the GC.
+    let upload_id = cluster.initiate_multipart("b").await;
+    cluster
+        .upload_part("b", &upload_id, 1, &[1; 100])
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_secs(8)).await;
+    let err = cluster
+        .upload_part("b", &upload_id, 2, &[2; 100])
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::NotFound);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_batch() -> anyhow::Result<()> {
+    let mut cluster = Cluster::new();
+    for _ in 0..5 {
+        let uri = cluster.up_node().await;
+        cluster.add_node(uri).await;
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let dataset = prepare_dataset(100);
+    let codes = cluster.batch_create(&dataset).await;
+    assert!(codes.iter().all(|c| *c == tonic::Code::Ok));
+
+    let mut keys: Vec<String> = dataset.iter().map(|(k, _)| k.clone()).collect();
+    keys.push("not-found".to_string());
+    keys.push("a\0stripe\00".to_string());
+    let results = cluster.batch_read(&keys).await;
+    assert_eq!(results.len(), keys.len());
+    for (i, (_, v)) in dataset.iter().enumerate() {
+        assert_eq!(results[i], (tonic::Code::Ok, v.clone()));
+        assert_eq!(&cluster.read(&keys[i]).await, v);
+    }
+    assert_eq!(results[100].0, tonic::Code::NotFound);
+    assert_eq!(results[101].0, tonic::Code::InvalidArgument);
+
+    // Reserved and duplicate keys fail individually.
+    let entries = vec![
+        ("x".to_string(), vec![1; 10]),
+        ("a\0stripe\00".to_string(), vec![2; 10]),
+        ("x".to_string(), vec![3; 10]),
+    ];
+    let codes = cluster.batch_create(&entries).await;
+    assert_eq!(
+        codes,
+        vec![
+            tonic::Code::Ok,
+            tonic::Code::InvalidArgument,
+            tonic::Code::InvalidArgument
+        ]
+    );
+    assert_eq!(cluster.read("x").await, vec![1; 10]);
+
+    // The options of each entry are applied as Create.
+    let chan = cluster.connect().await;
+    let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+    let expires_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs()
+        + 3600;
+    let req = proto_compiled::BatchCreateReq {
+        entries: vec![
+            proto_compiled::CreateReq {
+                key: "y".to_string(),
+                data: Bytes::from(vec![4; 1000]),
+                expires_at,
+                compression: proto_compiled::Compression::Zstd as i32,
+                ..Default::default()
+            },
+            proto_compiled::CreateReq {
+                key: "z".to_string(),
+                data: Bytes::from(vec![5; 10]),
+                storage_class: "no-such-class".to_string(),
+                ..Default::default()
+            },
+        ],
+    };
+    let rep = cli.batch_create(req).await?.into_inner();
+    let codes: Vec<i32> = rep.statuses.iter().map(|st| st.code).collect();
+    assert_eq!(
+        codes,
+        vec![tonic::Code::Ok as i32, tonic::Code::InvalidArgument as i32]
+    );
+    let head = cli
+        .head(proto_compiled::HeadReq {
+            key: "y".to_string(),
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    assert_eq!(head.expires_at, expires_at);
+    assert_eq!(head.compression, proto_compiled::Compression::Zstd as i32);
+    assert_eq!(cluster.read("y").await, vec![4; 1000]);
+
+    // Objects written before the cluster changed are read through the fallback.
+    let uri = cluster.up_node().await;
+    cluster.add_node(uri).await;
+    let results = cluster.batch_read(&keys[..100]).await;
+    for (i, (_, v)) in dataset.iter().enumerate() {
+        assert_eq!(results[i], (tonic::Code::Ok, v.clone()));
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_versioning() -> anyhow::Result<()> {
+    let mut cluster = Cluster::new();
+    for _ in 0..4 {
+        let uri = cluster.up_node().await;
+        cluster.add_node(uri).await;
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    // Overwrites create new versions and the latest one is read.
+    let v1 = cluster.create_version("a", &[1; 100]).await;
+    let v2 = cluster.create_version("a", &[2; 200]).await;
+    let v3 = cluster.create_version("a", &[3; 300]).await;
+    assert!(v1 < v2 && v2 < v3);
+    assert_eq!(cluster.read("a").await, vec![3; 300]);
+    assert_eq!(cluster.head("a").await.version_id, v3);
+    assert_eq!(cluster.read_version("a", &v1).await.unwrap(), vec![1; 100]);
+    assert_eq!(cluster.read_version("a", &v2).await.unwrap(), vec![2; 200]);
+    assert_eq!(
+        cluster.list_versions("a").await,
+        vec![v3.clone(), v2.clone(), v1.clone()]
+    );
+
+    // The key is listed once.
+    cluster.create("b", &[4; 10]).await;
+    let (keys, _) = cluster.list("", "", 100).await;
+    assert_eq!(keys, vec!["a".to_string(), "b".to_string()]);
+    let (keys, token) = cluster.list("", "", 1).await;
+    assert_eq!(keys, vec!["a".to_string()]);
+    let (keys, _) = cluster.list("", &token, 1).await;
+    assert_eq!(keys, vec!["b".to_string()]);
+
+    // Deleting the latest version exposes the previous one.
+    cluster.delete_version("a", &v3).await;
+    assert_eq!(cluster.read("a").await, vec![2; 200]);
+    let err = cluster.read_version("a", &v3).await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::NotFound);
+    assert_eq!(
+        cluster.list_versions("a").await,
+        vec![v2.clone(), v1.clone()]
+    );
+
+    // Deleting without a version deletes all versions.
+    cluster.delete("a").await;
+    assert!(cluster.try_read("a").await.is_none());
+    assert!(cluster.list_versions("a").await.is_empty());
+
+    // The key can be created again right after the delete.
+    cluster.create("a", &[5; 10]).await;
+    assert_eq!(cluster.read("a").await, vec![5; 10]);
+
+    // Versions written before the cluster changed are still found.
+    let uri = cluster.up_node().await;
+    cluster.add_node(uri).await;
+    assert_eq!(cluster.read("a").await, vec![5; 10]);
+    assert_eq!(cluster.read("b").await, vec![4; 10]);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_expiration() -> anyhow::Result<()> {
+    let mut cluster = Cluster::new();
+    for _ in 0..4 {
+        let uri = cluster.up_node().await;
+        cluster.add_node(uri).await;
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    cluster.create_expiring("a", &[1; 100], now + 3).await;
+    let v1 = cluster.create_version("b", &[2; 100]).await;
+    let v2 = cluster.create_expiring("b", &[3; 100], now + 3).await;
+    cluster.create("c", &[4; 100]).await;
+    assert_eq!(cluster.read("a").await, vec![1; 100]);
+    assert_eq!(cluster.head("a").await.expires_at, now + 3);
+    assert_eq!(cluster.head("c").await.expires_at, 0);
+    assert_eq!(cluster.read("b").await, vec![3; 100]);
+
+    tokio::time::sleep(Duration::from_secs(4)).await;
+
+    // Expired objects are read as missing.
+    assert!(cluster.try_read("a").await.is_none());
+    let err = cluster.read_version("b", &v2).await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::NotFound);
+    // The expired version is hidden as if it was deleted.
+    assert_eq!(cluster.read("b").await, vec![2; 100]);
+    assert_eq!(cluster.list_versions("b").await, vec![v1]);
+    assert_eq!(cluster.read("c").await, vec![4; 100]);
+
+    // The reaper drops the pieces.
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let (keys, _) = cluster.list("", "", 100).await;
+    assert_eq!(keys, vec!["b".to_string(), "c".to_string()]);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_rollback_failed_create() -> anyhow::Result<()> {
+    let mut cluster = Cluster::new();
+    for _ in 0..4 {
+        let uri = cluster.up_node().await;
+        cluster.add_node(uri).await;
+    }
+    // Every node holds one piece.
+    cluster.set_ec_params(3, 4).await.unwrap();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let live = vec![cluster.leader.clone().unwrap(), cluster.choose_one()];
+    let crashed: Vec<Uri> = cluster
+        .servers
+        .keys()
+        .filter(|uri| !live.contains(uri))
+        .cloned()
+        .collect();
+    for uri in crashed {
+        cluster.crash_node(uri);
+    }
+
+    // Only two of the three pieces needed can be written.
+    let chan = cluster.connect().await;
+    let mut cli = proto_compiled::sorock_client::SorockClient::new(chan);
+    let err = cli
+        .create(proto_compiled::CreateReq {
+            key: "a".to_string(),
+            data: Bytes::from(vec![1; 100]),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::Unavailable);
+
+    // The pieces written are deleted.
+    for uri in live {
+        assert!(cluster.request_keys(uri).await.is_empty());
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_durability() -> anyhow::Result<()> {
+    use proto_compiled::Durability;
+
+    let mut cluster = Cluster::new();
+    for _ in 0..4 {
+        let uri = cluster.up_node().await;
+        cluster.add_node(uri).await;
+    }
+    // Every node holds one piece.
+    cluster.set_ec_params(2, 4).await.unwrap();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let rep = cluster
+        .create_durable("a", &[1; 100], Durability::All)
+        .await?;
+    assert_eq!(rep.n_pieces, 4);
+
+    let uri = cluster.choose_one();
+    cluster.crash_node(uri);
+
+    // Three of the four pieces are written.
+    let rep = cluster
+        .create_durable("b", &[2; 100], Durability::K)
+        .await?;
+    assert_eq!(rep.n_pieces, 3);
+    let rep = cluster
+        .create_durable("c", &[3; 100], Durability::KPlusOne)
+        .await?;
+    assert_eq!(rep.n_pieces, 3);
+    let err = cluster
+        .create_durable("d", &[4; 100], Durability::All)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::Unavailable);
+
+    // The default of the cluster is used if not specified.
+    let rep = cluster
+        .create_durable("e", &[5; 100], Durability::Default)
+        .await?;
+    assert_eq!(rep.n_pieces, 3);
+    cluster.set_durability(Durability::All).await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let err = cluster
+        .create_durable("f", &[6; 100], Durability::Default)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::Unavailable);
+
+    assert_eq!(cluster.read("c").await, vec![3; 100]);
+    assert!(cluster.try_read("d").await.is_none());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_storage_classes() -> anyhow::Result<()> {
+    let mut cluster = Cluster::new();
+    for _ in 0..4 {
+        let uri = cluster.up_node().await;
+        cluster.add_node(uri).await;
+    }
+    cluster.set_ec_params(2, 3).await.unwrap();
+    cluster.set_storage_class("replica3", 1, 3).await?;
+    cluster.set_storage_class("ec2+2", 2, 4).await?;
+    // More pieces than the nodes.
+    let err = cluster.set_storage_class("wide", 2, 5).await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let classes = cluster.list_storage_classes().await;
+    assert_eq!(classes.len(), 2);
+
+    let rep = cluster.create_in_class("a", &[1; 100], "replica3").await?;
+    assert_eq!(rep.n_pieces, 3);
+    let rep = cluster.create_in_class("b", &[2; 100], "ec2+2").await?;
+    assert_eq!(rep.n_pieces, 4);
+    let rep = cluster.create_in_class("c", &[3; 100], "").await?;
+    assert_eq!(rep.n_pieces, 3);
+    let err = cluster
+        .create_in_class("d", &[4; 100], "unknown")
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+    assert_eq!(cluster.head("a").await.storage_class, "replica3");
+    assert_eq!(cluster.head("b").await.storage_class, "ec2+2");
+    assert_eq!(cluster.head("c").await.storage_class, "");
+    for key in ["a", "b", "c"] {
+        assert_eq!(cluster.sanity_check(key).await, 0);
+    }
+
+    // Every object survives the loss of a node.
+    let uri = cluster.choose_one();
+    cluster.crash_node(uri);
+    assert_eq!(cluster.read("a").await, vec![1; 100]);
+    assert_eq!(cluster.read("b").await, vec![2; 100]);
+    assert_eq!(cluster.read("c").await, vec![3; 100]);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_compression() -> anyhow::Result<()> {
+    use proto_compiled::Compression;
+
+    let mut cluster = Cluster::new();
+    for _ in 0..4 {
+        let uri = cluster.up_node().await;
+        cluster.add_node(uri).await;
+    }
+    cluster.set_ec_params(2, 4).await.unwrap();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let log: Vec<u8> = (0..40000)
+        .flat_map(|i| format!("{{\"seq\":{},\"level\":\"info\"}}\n", i).into_bytes())
+        .collect();
+    assert!(log.len() > 1 << 20);
+    let random: Vec<u8> = (0..2 << 20).map(|_| rand::random::<u8>()).collect();
+
+    // Chosen by the size threshold of the node.
+    cluster.create("large", &log).await;
+    cluster.create("small", &log[..1000]).await;
+    // Chosen by the request.
+    cluster
+        .create_compressed("zstd", &log[..1000], Compression::Zstd)
+        .await;
+    cluster
+        .create_compressed("none", &log, Compression::None)
+        .await;
+    // Stored as it is because it doesn't shrink.
+    cluster.create("random", &random).await;
+
+    let codec = |rep: proto_compiled::HeadRep| Compression::from_i32(rep.compression).unwrap();
+    assert_eq!(codec(cluster.head("large").await), Compression::Lz4);
+    assert_eq!(codec(cluster.head("small").await), Compression::None);
+    assert_eq!(codec(cluster.head("zstd").await), Compression::Zstd);
+    assert_eq!(codec(cluster.head("none").await), Compression::None);
+    assert_eq!(codec(cluster.head("random").await), Compression::None);
+    // The size is of the original content.
+    assert_eq!(cluster.head("large").await.size, log.len() as u64);
+
+    assert_eq!(cluster.read("large").await, log);
+    assert_eq!(cluster.read("zstd").await, &log[..1000]);
+    assert_eq!(cluster.read("random").await, random);
+    assert_eq!(
+        cluster.read_range("large", 100000, 1000).await,
+        &log[100000..101000]
+    );
+    let keys = vec!["large".to_string(), "zstd".to_string()];
+    let rep = cluster.batch_read(&keys).await;
+    assert_eq!(rep[0], (tonic::Code::Ok, log.clone()));
+    assert_eq!(rep[1], (tonic::Code::Ok, log[..1000].to_vec()));
+
+    // Compressed objects are rebuilt as they are stored.
+    let uri = cluster.choose_one();
+    cluster.crash_node(uri);
+    assert_eq!(cluster.read("large").await, log);
+
+    let stats = cluster.stats().await.compression.unwrap();
+    assert_eq!(stats.compressed, 2);
+    assert_eq!(stats.original_bytes, (log.len() + 1000) as u64);
+    assert!(stats.stored_bytes * 2 < stats.original_bytes);
+
+    Ok(())
+}
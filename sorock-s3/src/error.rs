@@ -0,0 +1,60 @@
+use crate::xml;
+use hyper::{Body, Response, StatusCode};
+
+/// Error returned to the client in the XML format of S3.
+#[derive(Debug)]
+pub struct S3Error {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+}
+impl S3Error {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+        }
+    }
+    pub fn access_denied(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, "AccessDenied", message)
+    }
+    pub fn invalid_argument(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "InvalidArgument", message)
+    }
+    pub fn not_implemented(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_IMPLEMENTED, "NotImplemented", message)
+    }
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "InternalError", message)
+    }
+    /// `not_found` is the code of S3 the missing resource is reported with.
+    /// e.g. NoSuchKey or NoSuchUpload
+    pub fn from_status(status: tonic::Status, not_found: &'static str) -> Self {
+        use tonic::Code;
+        let message = status.message().to_string();
+        match status.code() {
+            Code::NotFound => Self::new(StatusCode::NOT_FOUND, not_found, message),
+            Code::InvalidArgument => Self::invalid_argument(message),
+            Code::Unavailable => Self::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "ServiceUnavailable",
+                message,
+            ),
+            _ => Self::internal(message),
+        }
+    }
+    pub fn into_response(self, resource: &str) -> Response<Body> {
+        let body = xml::error(self.code, &self.message, resource);
+        Response::builder()
+            .status(self.status)
+            .header("content-type", "application/xml")
+            .body(Body::from(body))
+            .unwrap()
+    }
+}
+impl From<hyper::Error> for S3Error {
+    fn from(e: hyper::Error) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "IncompleteBody", e.to_string())
+    }
+}
@@ -0,0 +1,821 @@
+use crate::auth::{self, Credentials};
+use crate::error::S3Error;
+use crate::xml;
+use bytes::Bytes;
+use futures::StreamExt;
+use hyper::http::request::Parts;
+use hyper::http::response::Builder;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use sha2::{Digest, Sha256};
+use sorock_core::proto_compiled::sorock_client::SorockClient;
+use sorock_core::proto_compiled::{
+    AbortMultipartReq, CompleteMultipartReq, CreateReq, DeleteReq, HeadRep, HeadReq,
+    InitiateMultipartReq, ListReq, ReadRangeReq, ReadReq, UploadPartReq,
+};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tonic::transport::Channel;
+
+/// Max number of the keys in a page of ListObjectsV2.
+const MAX_KEYS: usize = 1000;
+/// Number of the objects headed at once to list their sizes.
+const N_PAR_HEAD: usize = 16;
+/// Prefix of the headers of the user-defined metadata.
+/// They are stored in the metadata of the object as they are.
+const META_PREFIX: &str = "x-amz-meta-";
+
+pub struct Gateway {
+    pub cli: SorockClient<Channel>,
+    pub credentials: Credentials,
+    pub region: String,
+    /// The body is buffered to be sent in a message so larger ones are rejected.
+    pub max_body_size: usize,
+}
+
+pub async fn handle(gw: Arc<Gateway>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let resource = req.uri().path().to_string();
+    match gw.serve(req).await {
+        Ok(rep) => Ok(rep),
+        Err(e) => Ok(e.into_response(&resource)),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Op {
+    ListObjectsV2,
+    PutObject,
+    GetObject,
+    HeadObject,
+    DeleteObject,
+    CreateMultipartUpload,
+    UploadPart { upload_id: String, part_number: u32 },
+    CompleteMultipartUpload { upload_id: String },
+    AbortMultipartUpload { upload_id: String },
+}
+
+/// Only the path-style requests are supported: `/bucket/key`.
+fn parse_path(path: &str) -> Result<(String, Option<String>), S3Error> {
+    let path = auth::uri_decode(path)?;
+    let path = path.strip_prefix('/').unwrap_or(&path);
+    let (bucket, key) = match path.split_once('/') {
+        Some((bucket, key)) if !key.is_empty() => (bucket, Some(key.to_string())),
+        Some((bucket, _)) => (bucket, None),
+        None => (path, None),
+    };
+    if bucket.is_empty() {
+        return Err(S3Error::not_implemented("listing buckets is not supported"));
+    }
+    Ok((bucket.to_string(), key))
+}
+
+fn route(
+    method: &Method,
+    key: Option<&str>,
+    query: &HashMap<String, String>,
+) -> Result<Op, S3Error> {
+    let unsupported = || S3Error::not_implemented("the operation is not supported");
+    if key.is_none() {
+        return match (method, query.get("list-type").map(|x| x.as_str())) {
+            (&Method::GET, Some("2")) => Ok(Op::ListObjectsV2),
+            _ => Err(unsupported()),
+        };
+    }
+    let op = match (method, query.get("uploadId").cloned()) {
+        (&Method::PUT, None) => Op::PutObject,
+        (&Method::GET, None) => Op::GetObject,
+        (&Method::HEAD, None) => Op::HeadObject,
+        (&Method::DELETE, None) => Op::DeleteObject,
+        (&Method::POST, None) if query.contains_key("uploads") => Op::CreateMultipartUpload,
+        (&Method::PUT, Some(upload_id)) => {
+            let part_number = query
+                .get("partNumber")
+                .and_then(|x| x.parse().ok())
+                .ok_or_else(|| S3Error::invalid_argument("invalid part number"))?;
+            Op::UploadPart {
+                upload_id,
+                part_number,
+            }
+        }
+        (&Method::POST, Some(upload_id)) => Op::CompleteMultipartUpload { upload_id },
+        (&Method::DELETE, Some(upload_id)) => Op::AbortMultipartUpload { upload_id },
+        _ => return Err(unsupported()),
+    };
+    Ok(op)
+}
+
+/// The inclusive range of the Range header.
+/// An invalid or multiple range is ignored and the whole object is returned as S3 does.
+fn parse_range(s: &str, size: u64) -> Result<Option<(u64, u64)>, S3Error> {
+    let unsatisfiable = || {
+        S3Error::new(
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            "InvalidRange",
+            "the requested range is not satisfiable",
+        )
+    };
+    let spec = match s.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Ok(None),
+    };
+    let (first, last) = match spec.split_once('-') {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    if first.is_empty() {
+        // The last n bytes.
+        let n: u64 = match last.parse() {
+            Ok(n) => n,
+            Err(_) => return Ok(None),
+        };
+        if n == 0 || size == 0 {
+            return Err(unsatisfiable());
+        }
+        return Ok(Some((size.saturating_sub(n), size - 1)));
+    }
+    let first: u64 = match first.parse() {
+        Ok(x) => x,
+        Err(_) => return Ok(None),
+    };
+    let last = if last.is_empty() {
+        u64::MAX
+    } else {
+        match last.parse() {
+            Ok(x) => x,
+            Err(_) => return Ok(None),
+        }
+    };
+    if last < first {
+        return Ok(None);
+    }
+    if first >= size {
+        return Err(unsatisfiable());
+    }
+    Ok(Some((first, std::cmp::min(last, size - 1))))
+}
+
+/// An entry of the listing. The keys are relative to the bucket.
+#[derive(Debug, PartialEq)]
+enum Entry {
+    Key(String),
+    CommonPrefix(String),
+}
+impl Entry {
+    /// The listing resumes after this key.
+    /// The keys of a common prefix are skipped at once.
+    fn resume_key(&self) -> String {
+        match self {
+            Entry::Key(key) => key.clone(),
+            Entry::CommonPrefix(prefix) => format!("{}{}", prefix, char::MAX),
+        }
+    }
+}
+
+/// Roll up the keys that contain the delimiter after the prefix into common prefixes.
+/// The keys are sorted so the keys of a common prefix are adjacent.
+fn group_keys(keys: Vec<String>, prefix: &str, delimiter: &str) -> Vec<Entry> {
+    let mut out = vec![];
+    for key in keys {
+        let pos = match key.get(prefix.len()..) {
+            Some(rest) if !delimiter.is_empty() => rest.find(delimiter),
+            _ => None,
+        };
+        let entry = match pos {
+            Some(i) => Entry::CommonPrefix(key[..prefix.len() + i + delimiter.len()].to_string()),
+            None => Entry::Key(key),
+        };
+        if out.last() != Some(&entry) {
+            out.push(entry);
+        }
+    }
+    out
+}
+
+fn object_key(bucket: &str, key: &str) -> String {
+    format!("{}/{}", bucket, key)
+}
+
+fn etag(checksum: u32) -> String {
+    format!("\"{:08x}\"", checksum)
+}
+
+fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// The Content-Type and the user-defined metadata in the request.
+fn user_metadata(parts: &Parts) -> HashMap<String, String> {
+    let mut out = HashMap::new();
+    for (name, value) in &parts.headers {
+        let name = name.as_str();
+        if name == "content-type" || name.starts_with(META_PREFIX) {
+            if let Ok(value) = value.to_str() {
+                out.insert(name.to_string(), value.to_string());
+            }
+        }
+    }
+    out
+}
+
+fn object_headers(mut builder: Builder, head: &HeadRep) -> Builder {
+    let last_modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(head.created_at);
+    builder = builder
+        .header("etag", etag(head.checksum))
+        .header("last-modified", httpdate::fmt_http_date(last_modified))
+        .header("accept-ranges", "bytes")
+        .header("x-amz-version-id", &head.version_id);
+    if !head.metadata.contains_key("content-type") {
+        builder = builder.header("content-type", "binary/octet-stream");
+    }
+    for (name, value) in &head.metadata {
+        if name == "content-type" || name.starts_with(META_PREFIX) {
+            builder = builder.header(name, value);
+        }
+    }
+    builder
+}
+
+fn entity_too_large(max: usize) -> S3Error {
+    S3Error::new(
+        StatusCode::BAD_REQUEST,
+        "EntityTooLarge",
+        format!("the body should be at most {} bytes", max),
+    )
+}
+
+/// Read the body up to `max` bytes.
+/// The declared length is checked first so a too large body isn't read at all.
+async fn read_body(parts: &Parts, mut body: Body, max: usize) -> Result<Bytes, S3Error> {
+    let content_length = parts
+        .headers
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.parse::<u64>().ok());
+    if matches!(content_length, Some(n) if n > max as u64) {
+        return Err(entity_too_large(max));
+    }
+    let mut buf = bytes::BytesMut::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        if buf.len() + chunk.len() > max {
+            return Err(entity_too_large(max));
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(buf.freeze())
+}
+
+fn decode_token(token: &str) -> Result<String, S3Error> {
+    hex::decode(token)
+        .ok()
+        .and_then(|x| String::from_utf8(x).ok())
+        .ok_or_else(|| S3Error::invalid_argument("invalid continuation token"))
+}
+
+impl Gateway {
+    async fn serve(&self, req: Request<Body>) -> Result<Response<Body>, S3Error> {
+        let (parts, body) = req.into_parts();
+        let payload_hash = auth::verify(&self.credentials, &self.region, &parts, unix_time())?;
+        if payload_hash.starts_with("STREAMING-") {
+            return Err(S3Error::not_implemented(
+                "the chunked upload is not supported",
+            ));
+        }
+        let body = read_body(&parts, body, self.max_body_size).await?;
+        if payload_hash != auth::UNSIGNED_PAYLOAD
+            && hex::encode(Sha256::digest(&body)) != payload_hash
+        {
+            return Err(S3Error::new(
+                StatusCode::BAD_REQUEST,
+                "XAmzContentSHA256Mismatch",
+                "the payload doesn't match x-amz-content-sha256",
+            ));
+        }
+
+        let (bucket, key) = parse_path(parts.uri.path())?;
+        let query: HashMap<String, String> = auth::parse_query(parts.uri.query().unwrap_or(""))?
+            .into_iter()
+            .collect();
+        let op = route(&parts.method, key.as_deref(), &query)?;
+        let key = match key {
+            Some(key) => key,
+            None => return self.list_objects_v2(&bucket, &query).await,
+        };
+        let full_key = object_key(&bucket, &key);
+        let version_id = query.get("versionId").cloned().unwrap_or_default();
+        match op {
+            Op::ListObjectsV2 => unreachable!(),
+            Op::PutObject => self.put_object(&parts, full_key, body).await,
+            Op::GetObject => self.get_object(&parts, full_key, version_id).await,
+            Op::HeadObject => self.head_object(full_key, version_id).await,
+            Op::DeleteObject => self.delete_object(full_key, version_id).await,
+            Op::CreateMultipartUpload => {
+                self.create_multipart_upload(&parts, &bucket, &key, full_key)
+                    .await
+            }
+            Op::UploadPart {
+                upload_id,
+                part_number,
+            } => {
+                self.upload_part(full_key, upload_id, part_number, body)
+                    .await
+            }
+            Op::CompleteMultipartUpload { upload_id } => {
+                self.complete_multipart_upload(&bucket, &key, full_key, upload_id, body)
+                    .await
+            }
+            Op::AbortMultipartUpload { upload_id } => {
+                self.abort_multipart_upload(full_key, upload_id).await
+            }
+        }
+    }
+    async fn head(&self, key: String, version_id: String) -> Result<HeadRep, S3Error> {
+        let rep = self
+            .cli
+            .clone()
+            .head(HeadReq { key, version_id })
+            .await
+            .map_err(|e| S3Error::from_status(e, "NoSuchKey"))?;
+        Ok(rep.into_inner())
+    }
+    async fn put_object(
+        &self,
+        parts: &Parts,
+        key: String,
+        body: Bytes,
+    ) -> Result<Response<Body>, S3Error> {
+        if parts.headers.contains_key("x-amz-copy-source") {
+            return Err(S3Error::not_implemented("CopyObject is not supported"));
+        }
+        let checksum = crc32c::crc32c(&body);
+        let req = CreateReq {
+            key,
+            data: body,
+            metadata: user_metadata(parts),
+            ..Default::default()
+        };
+        let rep = self
+            .cli
+            .clone()
+            .create(req)
+            .await
+            .map_err(|e| S3Error::from_status(e, "NoSuchKey"))?
+            .into_inner();
+        Ok(Response::builder()
+            .header("etag", etag(checksum))
+            .header("x-amz-version-id", rep.version_id)
+            .body(Body::empty())
+            .unwrap())
+    }
+    async fn get_object(
+        &self,
+        parts: &Parts,
+        key: String,
+        version_id: String,
+    ) -> Result<Response<Body>, S3Error> {
+        // The version is fixed so the content matches the headers.
+        let head = self.head(key.clone(), version_id).await?;
+        let range = match parts.headers.get("range").and_then(|x| x.to_str().ok()) {
+            Some(range) => parse_range(range, head.size)?,
+            None => None,
+        };
+        let builder = object_headers(Response::builder(), &head);
+        match range {
+            Some((first, last)) => {
+                let req = ReadRangeReq {
+                    key,
+                    offset: first,
+                    length: last - first + 1,
+                    version_id: head.version_id.clone(),
+                };
+                let rep = self
+                    .cli
+                    .clone()
+                    .read_range(req)
+                    .await
+                    .map_err(|e| S3Error::from_status(e, "NoSuchKey"))?
+                    .into_inner();
+                Ok(builder
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header("content-length", rep.data.len())
+                    .header(
+                        "content-range",
+                        format!("bytes {}-{}/{}", first, last, head.size),
+                    )
+                    .body(Body::from(rep.data))
+                    .unwrap())
+            }
+            None => {
+                // The object is streamed part by part.
+                let req = ReadReq {
+                    key,
+                    version_id: head.version_id.clone(),
+                };
+                let stream = self
+                    .cli
+                    .clone()
+                    .read_stream(req)
+                    .await
+                    .map_err(|e| S3Error::from_status(e, "NoSuchKey"))?
+                    .into_inner();
+                let body = Body::wrap_stream(stream.map(|rep| rep.map(|rep| rep.data)));
+                Ok(builder
+                    .header("content-length", head.size)
+                    .body(body)
+                    .unwrap())
+            }
+        }
+    }
+    async fn head_object(
+        &self,
+        key: String,
+        version_id: String,
+    ) -> Result<Response<Body>, S3Error> {
+        let head = self.head(key, version_id).await?;
+        Ok(object_headers(Response::builder(), &head)
+            .header("content-length", head.size)
+            .body(Body::empty())
+            .unwrap())
+    }
+    async fn delete_object(
+        &self,
+        key: String,
+        version_id: String,
+    ) -> Result<Response<Body>, S3Error> {
+        let res = self.cli.clone().delete(DeleteReq { key, version_id }).await;
+        match res {
+            // Deleting a missing object succeeds in S3.
+            Ok(_) => {}
+            Err(e) if e.code() == tonic::Code::NotFound => {}
+            Err(e) => return Err(S3Error::from_status(e, "NoSuchKey")),
+        }
+        Ok(Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(Body::empty())
+            .unwrap())
+    }
+    async fn create_multipart_upload(
+        &self,
+        parts: &Parts,
+        bucket: &str,
+        key: &str,
+        full_key: String,
+    ) -> Result<Response<Body>, S3Error> {
+        let req = InitiateMultipartReq {
+            key: full_key,
+            metadata: user_metadata(parts),
+        };
+        let rep = self
+            .cli
+            .clone()
+            .initiate_multipart(req)
+            .await
+            .map_err(|e| S3Error::from_status(e, "NoSuchKey"))?
+            .into_inner();
+        let body = xml::initiate_multipart_upload_result(bucket, key, &rep.upload_id);
+        Ok(xml_response(body))
+    }
+    async fn upload_part(
+        &self,
+        key: String,
+        upload_id: String,
+        part_number: u32,
+        body: Bytes,
+    ) -> Result<Response<Body>, S3Error> {
+        let req = UploadPartReq {
+            key,
+            upload_id,
+            part_number,
+            data: body,
+        };
+        let rep = self
+            .cli
+            .clone()
+            .upload_part(req)
+            .await
+            .map_err(|e| S3Error::from_status(e, "NoSuchUpload"))?
+            .into_inner();
+        Ok(Response::builder()
+            .header("etag", etag(rep.checksum))
+            .body(Body::empty())
+            .unwrap())
+    }
+    async fn complete_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        full_key: String,
+        upload_id: String,
+        body: Bytes,
+    ) -> Result<Response<Body>, S3Error> {
+        let part_numbers = std::str::from_utf8(&body)
+            .ok()
+            .and_then(xml::parse_complete_multipart_upload)
+            .ok_or_else(|| {
+                S3Error::new(
+                    StatusCode::BAD_REQUEST,
+                    "MalformedXML",
+                    "the list of the parts is malformed",
+                )
+            })?;
+        let req = CompleteMultipartReq {
+            key: full_key.clone(),
+            upload_id,
+            part_numbers,
+        };
+        let rep = self
+            .cli
+            .clone()
+            .complete_multipart(req)
+            .await
+            .map_err(|e| S3Error::from_status(e, "NoSuchUpload"))?
+            .into_inner();
+        let head = self.head(full_key, rep.version_id).await?;
+        let location = format!("/{}/{}", bucket, auth::uri_encode(key, false));
+        let body =
+            xml::complete_multipart_upload_result(&location, bucket, key, &etag(head.checksum));
+        Ok(xml_response(body))
+    }
+    async fn abort_multipart_upload(
+        &self,
+        key: String,
+        upload_id: String,
+    ) -> Result<Response<Body>, S3Error> {
+        self.cli
+            .clone()
+            .abort_multipart(AbortMultipartReq { key, upload_id })
+            .await
+            .map_err(|e| S3Error::from_status(e, "NoSuchUpload"))?;
+        Ok(Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(Body::empty())
+            .unwrap())
+    }
+    /// Returns the keys and the token to the next page.
+    async fn list_page(
+        &self,
+        prefix: String,
+        start_after: Option<String>,
+    ) -> Result<(Vec<String>, String), S3Error> {
+        // The token of List is the hex-encoded key to start after.
+        let req = ListReq {
+            prefix,
+            continuation_token: start_after.map(hex::encode).unwrap_or_default(),
+            max_keys: MAX_KEYS as u32 + 1,
+        };
+        let to_error = |e| S3Error::from_status(e, "NoSuchKey");
+        let mut stream = self
+            .cli
+            .clone()
+            .list(req)
+            .await
+            .map_err(to_error)?
+            .into_inner();
+        let mut keys = vec![];
+        let mut next = String::new();
+        while let Some(rep) = stream.message().await.map_err(to_error)? {
+            keys.extend(rep.keys);
+            next = rep.next_continuation_token;
+        }
+        Ok((keys, next))
+    }
+    async fn list_objects_v2(
+        &self,
+        bucket: &str,
+        query: &HashMap<String, String>,
+    ) -> Result<Response<Body>, S3Error> {
+        let prefix = query.get("prefix").cloned().unwrap_or_default();
+        let delimiter = query.get("delimiter").cloned().unwrap_or_default();
+        let max_keys = match query.get("max-keys") {
+            Some(x) => x
+                .parse::<usize>()
+                .map_err(|_| S3Error::invalid_argument("invalid max-keys"))?,
+            None => MAX_KEYS,
+        };
+        let max_keys = std::cmp::min(max_keys, MAX_KEYS);
+        let continuation_token = query.get("continuation-token").cloned();
+        let start_after = query.get("start-after").cloned();
+        let bucket_prefix = format!("{}/", bucket);
+
+        // The keys are relative to the bucket.
+        let mut resume = match &continuation_token {
+            Some(token) => Some(decode_token(token)?),
+            None => start_after.clone(),
+        };
+        let mut entries = vec![];
+        let mut truncated = false;
+        while max_keys > 0 {
+            let (keys, next) = self
+                .list_page(
+                    format!("{}{}", bucket_prefix, prefix),
+                    resume.as_ref().map(|key| object_key(bucket, key)),
+                )
+                .await?;
+            let keys = keys
+                .into_iter()
+                .filter_map(|key| key.strip_prefix(&bucket_prefix).map(|x| x.to_string()))
+                .collect();
+            let page = group_keys(keys, &prefix, &delimiter);
+            if page.is_empty() {
+                break;
+            }
+            for entry in page {
+                if entries.len() == max_keys {
+                    truncated = true;
+                    break;
+                }
+                resume = Some(entry.resume_key());
+                entries.push(entry);
+            }
+            if truncated || next.is_empty() {
+                break;
+            }
+        }
+
+        let mut keys = vec![];
+        let mut common_prefixes = vec![];
+        for entry in entries {
+            match entry {
+                Entry::Key(key) => keys.push(key),
+                Entry::CommonPrefix(prefix) => common_prefixes.push(prefix),
+            }
+        }
+        let heads = futures::stream::iter(keys.into_iter().map(|key| async move {
+            let head = self.head(object_key(bucket, &key), String::new()).await;
+            (key, head)
+        }))
+        .buffered(N_PAR_HEAD)
+        .collect::<Vec<_>>()
+        .await;
+        let mut contents = vec![];
+        for (key, head) in heads {
+            match head {
+                Ok(head) => contents.push(xml::Content {
+                    key,
+                    size: head.size,
+                    last_modified: xml::iso8601(head.created_at),
+                    etag: etag(head.checksum),
+                }),
+                // Deleted after listed.
+                Err(e) if e.status == StatusCode::NOT_FOUND => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let result = xml::ListBucketResult {
+            bucket: bucket.to_string(),
+            prefix,
+            delimiter,
+            max_keys,
+            contents,
+            common_prefixes,
+            continuation_token,
+            next_continuation_token: if truncated {
+                resume.map(hex::encode)
+            } else {
+                None
+            },
+            start_after,
+        };
+        Ok(xml_response(result.to_xml()))
+    }
+}
+
+fn xml_response(body: String) -> Response<Body> {
+    Response::builder()
+        .header("content-type", "application/xml")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+#[test]
+fn test_parse_path() {
+    assert_eq!(
+        parse_path("/b/a/c%20d").unwrap(),
+        ("b".to_string(), Some("a/c d".to_string()))
+    );
+    assert_eq!(parse_path("/b").unwrap(), ("b".to_string(), None));
+    assert_eq!(parse_path("/b/").unwrap(), ("b".to_string(), None));
+    assert!(parse_path("/").is_err());
+}
+
+#[test]
+fn test_route() {
+    let query = |s: &str| -> HashMap<String, String> {
+        auth::parse_query(s).unwrap().into_iter().collect()
+    };
+    assert_eq!(
+        route(&Method::GET, None, &query("list-type=2&prefix=a")).unwrap(),
+        Op::ListObjectsV2
+    );
+    assert!(route(&Method::GET, None, &query("")).is_err());
+    assert_eq!(
+        route(&Method::PUT, Some("k"), &query("")).unwrap(),
+        Op::PutObject
+    );
+    assert_eq!(
+        route(&Method::GET, Some("k"), &query("versionId=v")).unwrap(),
+        Op::GetObject
+    );
+    assert_eq!(
+        route(&Method::POST, Some("k"), &query("uploads")).unwrap(),
+        Op::CreateMultipartUpload
+    );
+    assert_eq!(
+        route(&Method::PUT, Some("k"), &query("partNumber=2&uploadId=u")).unwrap(),
+        Op::UploadPart {
+            upload_id: "u".to_string(),
+            part_number: 2
+        }
+    );
+    assert!(route(&Method::PUT, Some("k"), &query("uploadId=u")).is_err());
+    assert_eq!(
+        route(&Method::POST, Some("k"), &query("uploadId=u")).unwrap(),
+        Op::CompleteMultipartUpload {
+            upload_id: "u".to_string()
+        }
+    );
+    assert_eq!(
+        route(&Method::DELETE, Some("k"), &query("uploadId=u")).unwrap(),
+        Op::AbortMultipartUpload {
+            upload_id: "u".to_string()
+        }
+    );
+    // ListParts
+    assert!(route(&Method::GET, Some("k"), &query("uploadId=u")).is_err());
+}
+
+#[test]
+fn test_parse_range() {
+    assert_eq!(parse_range("bytes=0-9", 100).unwrap(), Some((0, 9)));
+    assert_eq!(parse_range("bytes=90-200", 100).unwrap(), Some((90, 99)));
+    assert_eq!(parse_range("bytes=10-", 100).unwrap(), Some((10, 99)));
+    assert_eq!(parse_range("bytes=-10", 100).unwrap(), Some((90, 99)));
+    assert_eq!(parse_range("bytes=-200", 100).unwrap(), Some((0, 99)));
+    // Ignored
+    assert_eq!(parse_range("bytes=0-1,3-4", 100).unwrap(), None);
+    assert_eq!(parse_range("bytes=9-0", 100).unwrap(), None);
+    assert_eq!(parse_range("items=0-9", 100).unwrap(), None);
+    // Not satisfiable
+    assert!(parse_range("bytes=100-", 100).is_err());
+    assert!(parse_range("bytes=-0", 100).is_err());
+    assert!(parse_range("bytes=0-9", 0).is_err());
+}
+
+#[test]
+fn test_group_keys() {
+    let keys = ["a", "b/1", "b/2", "b/c/1", "c"]
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        group_keys(keys.clone(), "", "/"),
+        vec![
+            Entry::Key("a".to_string()),
+            Entry::CommonPrefix("b/".to_string()),
+            Entry::Key("c".to_string()),
+        ]
+    );
+    assert_eq!(
+        group_keys(keys[1..4].to_vec(), "b/", "/"),
+        vec![
+            Entry::Key("b/1".to_string()),
+            Entry::Key("b/2".to_string()),
+            Entry::CommonPrefix("b/c/".to_string()),
+        ]
+    );
+    assert_eq!(group_keys(keys.clone(), "", "").len(), 5);
+
+    // The listing resumes after the keys of the common prefix.
+    let resume = Entry::CommonPrefix("b/".to_string()).resume_key();
+    assert!(resume.as_str() > "b/c/1");
+    assert!(resume.as_str() < "c");
+}
+
+#[tokio::test]
+async fn test_read_body() {
+    let parts = |content_length: Option<usize>| {
+        let mut req = Request::builder();
+        if let Some(n) = content_length {
+            req = req.header("content-length", n);
+        }
+        req.body(()).unwrap().into_parts().0
+    };
+    let body = read_body(&parts(Some(3)), Body::from(vec![1, 2, 3]), 3)
+        .await
+        .unwrap();
+    assert_eq!(body, Bytes::from(vec![1, 2, 3]));
+
+    let err = read_body(&parts(Some(4)), Body::from(vec![1, 2, 3, 4]), 3)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code, "EntityTooLarge");
+    // The length isn't declared in a chunked body.
+    let err = read_body(&parts(None), Body::from(vec![1, 2, 3, 4]), 3)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code, "EntityTooLarge");
+}
@@ -0,0 +1,57 @@
+#![deny(unused_must_use)]
+
+use hyper::service::{make_service_fn, service_fn};
+use sorock_core::proto_compiled::sorock_client::SorockClient;
+use std::convert::Infallible;
+use std::sync::Arc;
+
+mod auth;
+mod error;
+mod gateway;
+mod xml;
+
+#[derive(serde::Deserialize, Debug)]
+struct Config {
+    /// A node in the cluster the requests are sent to.
+    #[serde(with = "http_serde::uri")]
+    sorock_uri: tonic::transport::Uri,
+    #[serde(default = "default_listen")]
+    listen: std::net::SocketAddr,
+    /// Access keys and their secrets.
+    credentials: std::path::PathBuf,
+    /// Region in the scope of the signatures.
+    #[serde(default = "default_region")]
+    region: String,
+    /// Max size of the body of a request in bytes.
+    /// A larger object is uploaded by multipart.
+    #[serde(default = "default_max_body_size")]
+    max_body_size: usize,
+}
+fn default_listen() -> std::net::SocketAddr {
+    "0.0.0.0:9000".parse().unwrap()
+}
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+fn default_max_body_size() -> usize {
+    64 << 20
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let config = envy::from_env::<Config>()?;
+    let credentials = auth::Credentials::load(&config.credentials)?;
+    let chan = tonic::transport::Endpoint::from(config.sorock_uri).connect_lazy();
+    let gw = Arc::new(gateway::Gateway {
+        cli: SorockClient::new(chan),
+        credentials,
+        region: config.region,
+        max_body_size: config.max_body_size,
+    });
+    let make_svc = make_service_fn(move |_| {
+        let gw = gw.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| gateway::handle(gw.clone(), req))) }
+    });
+    hyper::Server::bind(&config.listen).serve(make_svc).await?;
+    Ok(())
+}
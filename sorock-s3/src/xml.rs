@@ -0,0 +1,198 @@
+//! The XML documents of the S3 API.
+//! They are few and flat so they are written and read by hand.
+
+const DECLARATION: &str = r#"<?xml version="1.0" encoding="UTF-8"?>"#;
+const NAMESPACE: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn element(name: &str, value: &str) -> String {
+    format!("<{}>{}</{}>", name, escape(value), name)
+}
+
+pub fn error(code: &str, message: &str, resource: &str) -> String {
+    format!(
+        "{}<Error>{}{}{}</Error>",
+        DECLARATION,
+        element("Code", code),
+        element("Message", message),
+        element("Resource", resource),
+    )
+}
+
+/// Formats the unix time in ISO 8601 as LastModified of the listing.
+pub fn iso8601(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    // Civil from days (Howard Hinnant)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.000Z",
+        y,
+        m,
+        d,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// An object in the listing.
+pub struct Content {
+    pub key: String,
+    pub size: u64,
+    /// ISO 8601
+    pub last_modified: String,
+    pub etag: String,
+}
+
+pub struct ListBucketResult {
+    pub bucket: String,
+    pub prefix: String,
+    pub delimiter: String,
+    pub max_keys: usize,
+    pub contents: Vec<Content>,
+    pub common_prefixes: Vec<String>,
+    pub continuation_token: Option<String>,
+    pub next_continuation_token: Option<String>,
+    pub start_after: Option<String>,
+}
+impl ListBucketResult {
+    pub fn to_xml(&self) -> String {
+        let mut out = format!("{}<ListBucketResult xmlns=\"{}\">", DECLARATION, NAMESPACE);
+        out += &element("Name", &self.bucket);
+        out += &element("Prefix", &self.prefix);
+        if !self.delimiter.is_empty() {
+            out += &element("Delimiter", &self.delimiter);
+        }
+        out += &element("MaxKeys", &self.max_keys.to_string());
+        let key_count = self.contents.len() + self.common_prefixes.len();
+        out += &element("KeyCount", &key_count.to_string());
+        let truncated = self.next_continuation_token.is_some();
+        out += &element("IsTruncated", &truncated.to_string());
+        if let Some(token) = &self.continuation_token {
+            out += &element("ContinuationToken", token);
+        }
+        if let Some(token) = &self.next_continuation_token {
+            out += &element("NextContinuationToken", token);
+        }
+        if let Some(start_after) = &self.start_after {
+            out += &element("StartAfter", start_after);
+        }
+        for content in &self.contents {
+            out += "<Contents>";
+            out += &element("Key", &content.key);
+            out += &element("LastModified", &content.last_modified);
+            out += &element("ETag", &content.etag);
+            out += &element("Size", &content.size.to_string());
+            out += &element("StorageClass", "STANDARD");
+            out += "</Contents>";
+        }
+        for prefix in &self.common_prefixes {
+            out += "<CommonPrefixes>";
+            out += &element("Prefix", prefix);
+            out += "</CommonPrefixes>";
+        }
+        out += "</ListBucketResult>";
+        out
+    }
+}
+
+pub fn initiate_multipart_upload_result(bucket: &str, key: &str, upload_id: &str) -> String {
+    format!(
+        "{}<InitiateMultipartUploadResult xmlns=\"{}\">{}{}{}</InitiateMultipartUploadResult>",
+        DECLARATION,
+        NAMESPACE,
+        element("Bucket", bucket),
+        element("Key", key),
+        element("UploadId", upload_id),
+    )
+}
+
+pub fn complete_multipart_upload_result(
+    location: &str,
+    bucket: &str,
+    key: &str,
+    etag: &str,
+) -> String {
+    format!(
+        "{}<CompleteMultipartUploadResult xmlns=\"{}\">{}{}{}{}</CompleteMultipartUploadResult>",
+        DECLARATION,
+        NAMESPACE,
+        element("Location", location),
+        element("Bucket", bucket),
+        element("Key", key),
+        element("ETag", etag),
+    )
+}
+
+/// The part numbers in the body of CompleteMultipartUpload in the given order.
+/// The ETags are ignored because the parts are verified by their checksums.
+pub fn parse_complete_multipart_upload(body: &str) -> Option<Vec<u32>> {
+    let mut out = vec![];
+    let mut rest = body;
+    while let Some(start) = rest.find("<PartNumber>") {
+        rest = &rest[start + "<PartNumber>".len()..];
+        let end = rest.find("</PartNumber>")?;
+        out.push(rest[..end].trim().parse().ok()?);
+        rest = &rest[end..];
+    }
+    if out.is_empty() {
+        return None;
+    }
+    Some(out)
+}
+
+#[test]
+fn test_escape() {
+    assert_eq!(escape("a<b>&\"c'"), "a&lt;b&gt;&amp;&quot;c&apos;");
+    assert_eq!(
+        error("NoSuchKey", "x", "/b/<k>"),
+        r#"<?xml version="1.0" encoding="UTF-8"?><Error><Code>NoSuchKey</Code><Message>x</Message><Resource>/b/&lt;k&gt;</Resource></Error>"#
+    );
+}
+
+#[test]
+fn test_parse_complete_multipart_upload() {
+    let body = r#"<CompleteMultipartUpload xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Part><ETag>"a"</ETag><PartNumber>1</PartNumber></Part>
+  <Part><PartNumber> 3 </PartNumber><ETag>"b"</ETag></Part>
+</CompleteMultipartUpload>"#;
+    assert_eq!(parse_complete_multipart_upload(body), Some(vec![1, 3]));
+    assert_eq!(
+        parse_complete_multipart_upload("<CompleteMultipartUpload/>"),
+        None
+    );
+    assert_eq!(
+        parse_complete_multipart_upload("<Part><PartNumber>x</PartNumber></Part>"),
+        None
+    );
+}
+
+#[test]
+fn test_iso8601() {
+    assert_eq!(iso8601(0), "1970-01-01T00:00:00.000Z");
+    assert_eq!(iso8601(951782400), "2000-02-29T00:00:00.000Z");
+    assert_eq!(iso8601(1700000000), "2023-11-14T22:13:20.000Z");
+}
@@ -13,6 +13,25 @@ struct Config {
     #[serde(with = "http_serde::uri")]
     uri: tonic::transport::Uri,
     cap: byte_unit::Byte,
+    /// How long a read waits for a piece before requesting another one.
+    #[serde(default = "default_hedge_delay_ms")]
+    hedge_delay_ms: u64,
+    /// Codec the objects are compressed with unless the request chooses one.
+    /// none, zstd or lz4.
+    #[serde(default)]
+    compression: compression::Compression,
+    /// Objects smaller than this are not compressed.
+    #[serde(default = "default_compression_threshold")]
+    compression_threshold: usize,
+    /// Master keys to encrypt the pieces at rest with.
+    /// Encryption can only be enabled on a new volume.
+    encryption_keyfile: Option<std::path::PathBuf>,
+}
+fn default_hedge_delay_ms() -> u64 {
+    50
+}
+fn default_compression_threshold() -> usize {
+    4096
 }
 
 #[tokio::main]
@@ -20,6 +39,11 @@ async fn main() -> anyhow::Result<()> {
     let config = envy::from_env::<Config>()?;
     let uri = config.uri;
     let cap = config.cap;
+    let hedge_delay = Duration::from_millis(config.hedge_delay_ms);
+    let compression = compression::Policy {
+        codec: config.compression,
+        threshold: config.compression_threshold,
+    };
 
     let SOROCKDB_ROOT = Path::new("/var/lib/sorock/data");
     if SOROCKDB_ROOT.join("dead_flag").exists() {
@@ -27,7 +51,8 @@ async fn main() -> anyhow::Result<()> {
     }
     std::fs::write(SOROCKDB_ROOT.join("dead_flag"), "")?;
 
-    if !SOROCKDB_ROOT.join("init_flag").exists() {
+    let new_volume = !SOROCKDB_ROOT.join("init_flag").exists();
+    if new_volume {
         eprintln!("init_flag doesn't exist. The state will be recreated.");
 
         let snapshots = SOROCKDB_ROOT.join("snapshots");
@@ -49,7 +74,19 @@ async fn main() -> anyhow::Result<()> {
     // Storage Service
 
     let peer_out_cli = peer_out::spawn(peer_out::State::new());
-    let io_front_cli = io_front::spawn(peer_out_cli.clone(), io_front::State::new());
+    // Erasure coding runs in dedicated threads so it doesn't block the async runtime.
+    let n_cpus = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let cpu_pool = cpu_pool::CpuPool::new(n_cpus, n_cpus * 4);
+    let rollback_queue_cli =
+        rollback_queue::spawn(peer_out_cli.clone(), rollback_queue::State::new());
+    rollback_queue::spawn_tick(rollback_queue_cli.clone(), Duration::from_secs(10));
+    let io_front_cli = io_front::spawn(
+        peer_out_cli.clone(),
+        rollback_queue_cli.clone(),
+        io_front::State::new(hedge_delay, cpu_pool.clone(), compression),
+    );
     // let piece_store_cli = mem_piece_store::spawn(mem_piece_store::State::new());
     let piece_store_cli = piece_store::sqlite::spawn(
         piece_store::sqlite::State::new(piece_store::sqlite::StoreType::Directory {
@@ -57,19 +94,60 @@ async fn main() -> anyhow::Result<()> {
         })
         .await,
     );
+    let keyring = SOROCKDB_ROOT.join("keyring");
+    let piece_store_cli = match config.encryption_keyfile {
+        Some(keyfile) => {
+            if !new_volume && !keyring.exists() {
+                anyhow::bail!("Encryption can't be enabled on a volume written without it.");
+            }
+            use piece_store::encrypted;
+            let provider = std::sync::Arc::new(encrypted::KeyfileProvider::open(&keyfile)?);
+            let mut state = encrypted::State::new(provider, Some(keyring)).await?;
+            // The data keys are re-wrapped when a new master key is added to the keyfile.
+            let n = state.rotate().await?;
+            if n > 0 {
+                eprintln!(
+                    "{} data keys were re-wrapped with the current master key.",
+                    n
+                );
+            }
+            encrypted::spawn(piece_store_cli, state)
+        }
+        None => {
+            if keyring.exists() {
+                anyhow::bail!("The volume is encrypted but no keyfile is given.");
+            }
+            piece_store_cli
+        }
+    };
     let stabilizer_cli = stabilizer::spawn(
         piece_store_cli.clone(),
         peer_out_cli.clone(),
         stabilizer::State::new(uri.clone()),
     );
     stabilizer::spawn_tick(stabilizer_cli.clone(), Duration::from_millis(100));
+    let reaper_cli = reaper::spawn(piece_store_cli.clone());
+    // Every round reads all the local headers.
+    reaper::spawn_tick(reaper_cli, Duration::from_secs(3600));
     let rebuild_queue_cli = rebuild_queue::spawn(
         piece_store_cli.clone(),
         peer_out_cli.clone(),
         stabilizer_cli.clone(),
-        rebuild_queue::State::new(),
+        rebuild_queue::State::new(cpu_pool.clone()),
     );
     rebuild_queue::spawn_tick(rebuild_queue_cli.clone(), Duration::from_millis(500));
+    let tombstone_gc_cli = tombstone_gc::spawn(
+        piece_store_cli.clone(),
+        peer_out_cli.clone(),
+        tombstone_gc::State::new(uri.clone(), Duration::from_secs(3600)),
+    );
+    tombstone_gc::spawn_tick(tombstone_gc_cli.clone(), Duration::from_secs(60));
+    let multipart_gc_cli = multipart_gc::spawn(
+        piece_store_cli.clone(),
+        io_front_cli.clone(),
+        multipart_gc::State::new(Duration::from_secs(24 * 3600)),
+    );
+    multipart_gc::spawn_tick(multipart_gc_cli, Duration::from_secs(600));
     let peer_in_cli = peer_in::spawn(
         piece_store_cli,
         stabilizer_cli.clone(),
@@ -82,6 +160,7 @@ async fn main() -> anyhow::Result<()> {
         peer_in_cli.clone(),
         uri.clone(),
         cap.get_value(),
+        cpu_pool,
     );
     let svc1 = storage_service::make_service(server).await;
 
@@ -121,6 +200,8 @@ async fn main() -> anyhow::Result<()> {
         stabilizer_cli,
         peer_in_cli,
         rebuild_queue_cli,
+        rollback_queue_cli,
+        tombstone_gc_cli,
         app_in_cli,
     );
     let raft_app = raft_service::App::new(cluster_in_cli);